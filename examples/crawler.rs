@@ -1,22 +1,75 @@
+//! Crawls outward from a seed, checkpointing as it goes and picking up from the checkpoint when
+//! restarted. Ctrl-C stops the crawl, saving it first
+//!
+//! Usage: `crawler [seed url] [checkpoint file] [sites to scrape]`
+
+use std::{env, error::Error, path::Path};
+
 use indicatif::ProgressBar;
-use page_rank_from_scratch::crawler::WebCrawler;
+use page_rank_from_scratch::crawler::{Autosave, CrawlLimit, WebCrawler};
 
 /// How many sites to scrape for our fake internet
 pub const SITES_TO_SCRAPE: usize = 100_000;
+/// Where the crawl starts from when there's no checkpoint
+pub const SEED: &str = "https://www.wikipedia.org/";
+/// Where the crawl is checkpointed to and resumed from
+pub const CHECKPOINT: &str = "100_000_wiki_entries.json";
+/// How many sites are crawled between checkpoints
+pub const CHECKPOINT_EVERY: usize = 100;
 
 #[tokio::main]
-async fn main() {
-    println!("Starting Crawler");
-    let mut crawler = WebCrawler::default();
-    crawler.enqueue("https://www.wikipedia.org/");
+async fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = env::args().skip(1);
+    let seed = args.next().unwrap_or_else(|| SEED.to_string());
+    let checkpoint = args.next().unwrap_or_else(|| CHECKPOINT.to_string());
+    let sites_to_scrape = args
+        .next()
+        .and_then(|sites| sites.parse().ok())
+        .unwrap_or(SITES_TO_SCRAPE);
 
-    let pb = ProgressBar::new(SITES_TO_SCRAPE as u64);
+    let mut crawler = if Path::new(&checkpoint).exists() {
+        let crawler = WebCrawler::resume(&checkpoint)
+            .ok_or_else(|| format!("couldn't read checkpoint {checkpoint}"))?;
+        println!(
+            "Resuming Crawler from {checkpoint} with {} sites crawled",
+            crawler.visited.len()
+        );
+        crawler
+    } else {
+        println!("Starting Crawler");
+        let mut crawler = WebCrawler::default();
+        crawler.enqueue(seed);
+        crawler
+    };
+    crawler.autosave = Some(Autosave {
+        path: checkpoint.clone().into(),
+        every: CHECKPOINT_EVERY,
+    });
 
-    for _ in 0..SITES_TO_SCRAPE {
-        let _ = crawler.crawl().await;
-        pb.inc(1);
+    let pb = ProgressBar::new(sites_to_scrape as u64);
+    pb.set_position(crawler.visited.len() as u64);
+    let bar = pb.clone();
+    crawler.progress = Some(Box::new(move |progress| {
+        bar.set_position(progress.crawled as u64)
+    }));
+
+    let limit = CrawlLimit::Any(vec![
+        CrawlLimit::MaxPages(sites_to_scrape.saturating_sub(crawler.visited.len())),
+        CrawlLimit::QueueEmpty,
+    ]);
+    tokio::select! {
+        summary = crawler.crawl_until(limit) => {
+            println!(
+                "Crawled {} sites in {:.1?}, {} of which failed",
+                summary.pages, summary.elapsed, summary.failures
+            );
+        }
+        _ = tokio::signal::ctrl_c() => println!("Interrupted, saving before stopping"),
     }
+    pb.finish();
 
-    crawler.save("100_000_wiki_entries.json");
+    crawler.save(&checkpoint, true)?;
     println!("Saved!");
+
+    Ok(())
 }
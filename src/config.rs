@@ -0,0 +1,338 @@
+//! Crawler settings read from a TOML or JSON file, so they don't all have to be set in code
+
+use std::{
+    collections::BTreeMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    Client,
+};
+use serde::Deserialize;
+
+use crate::crawler::{Autosave, LinkSource, WebCrawler};
+
+/// Settings for a crawl. Anything left out keeps the crawler's default
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CrawlerConfig {
+    /// URLs queued before crawling starts
+    pub seeds: Vec<String>,
+    pub user_agent: Option<String>,
+    pub accept_language: Option<String>,
+    /// Further headers sent with every request
+    pub headers: BTreeMap<String, String>,
+    pub respect_robots: Option<bool>,
+    pub link_sources: Option<Vec<LinkSource>>,
+    pub store_body_text: Option<bool>,
+    pub body_text_limit: Option<usize>,
+    pub link_sample: Option<usize>,
+    pub record_nofollow: Option<bool>,
+    /// Only follow links under this path on the host they're found on
+    pub path_prefix: Option<String>,
+    /// Seed for the crawl's random choices
+    pub seed: Option<u64>,
+    pub autosave: Option<AutosaveConfig>,
+    /// Longest wait for a connection to a host before the fetch fails, in seconds
+    pub connect_timeout_secs: Option<f32>,
+    pub policy: PolicyConfig,
+}
+
+/// How often to checkpoint a crawl and where to
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AutosaveConfig {
+    pub path: PathBuf,
+    pub every: usize,
+}
+
+/// How politely requests are spaced out, with durations in seconds
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PolicyConfig {
+    pub per_host_delay_secs: Option<f32>,
+    pub max_requests_per_second: Option<f32>,
+    pub max_concurrent: Option<usize>,
+    pub max_per_host: Option<usize>,
+    pub timeout_secs: Option<f32>,
+    pub max_attempts: Option<u32>,
+    pub retry_backoff_secs: Option<f32>,
+    pub max_retry_after_secs: Option<f32>,
+    pub max_pages_per_domain: Option<usize>,
+}
+
+/// Why a crawler couldn't be set up from a config file
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    /// The file isn't well formed TOML or JSON, or has settings that don't exist
+    Format(String),
+    /// A setting has a value that can't be used
+    Invalid {
+        setting: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "failed to read crawler config: {err}"),
+            Self::Format(err) => write!(f, "crawler config is malformed: {err}"),
+            Self::Invalid { setting, message } => {
+                write!(f, "invalid crawler config setting `{setting}`: {message}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<io::Error> for ConfigError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl CrawlerConfig {
+    /// Reads a config file, parsed as TOML when it has a `.toml` extension and as JSON otherwise
+    pub fn load<P: AsRef<Path>>(file: P) -> Result<Self, ConfigError> {
+        let file = file.as_ref();
+        let text = fs::read_to_string(file)?;
+
+        let is_toml = file
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("toml"));
+        if is_toml {
+            toml::from_str(&text).map_err(|err| ConfigError::Format(err.to_string()))
+        } else {
+            serde_json::from_str(&text).map_err(|err| ConfigError::Format(err.to_string()))
+        }
+    }
+
+    /// Builds a crawler with these settings and every seed queued
+    pub fn build(self) -> Result<WebCrawler, ConfigError> {
+        let mut crawler = WebCrawler::default();
+        if let Some(seed) = self.seed {
+            crawler = crawler.with_seed(seed);
+        }
+
+        if let Some(secs) = self.connect_timeout_secs {
+            crawler.client = Client::builder()
+                .connect_timeout(duration("connect_timeout_secs", secs)?)
+                .build()
+                .map_err(|err| invalid("connect_timeout_secs", err))?;
+        }
+        if let Some(user_agent) = self.user_agent {
+            crawler.user_agent = user_agent;
+        }
+        crawler.accept_language = self.accept_language;
+        crawler.headers = headers(&self.headers)?;
+
+        if let Some(respect_robots) = self.respect_robots {
+            crawler.respect_robots = respect_robots;
+        }
+        if let Some(link_sources) = self.link_sources {
+            crawler.link_sources = link_sources;
+        }
+        if let Some(store_body_text) = self.store_body_text {
+            crawler.store_body_text = store_body_text;
+        }
+        if let Some(limit) = self.body_text_limit {
+            crawler.body_text_limit = limit;
+        }
+        crawler.link_sample = self.link_sample;
+        if let Some(record_nofollow) = self.record_nofollow {
+            crawler.record_nofollow = record_nofollow;
+        }
+        crawler.path_prefix = self.path_prefix;
+        crawler.autosave = self.autosave.map(|autosave| Autosave {
+            path: autosave.path,
+            every: autosave.every,
+        });
+
+        let policy = &mut crawler.policy;
+        let config = self.policy;
+        if let Some(secs) = config.per_host_delay_secs {
+            policy.per_host_delay = duration("policy.per_host_delay_secs", secs)?;
+        }
+        if let Some(rate) = config.max_requests_per_second {
+            policy.max_requests_per_second = rate;
+        }
+        if let Some(max) = config.max_concurrent {
+            policy.max_concurrent = max;
+        }
+        if let Some(max) = config.max_per_host {
+            policy.max_per_host = max;
+        }
+        if let Some(secs) = config.timeout_secs {
+            policy.timeout = duration("policy.timeout_secs", secs)?;
+        }
+        if let Some(attempts) = config.max_attempts {
+            policy.max_attempts = attempts;
+        }
+        if let Some(secs) = config.retry_backoff_secs {
+            policy.retry_backoff = duration("policy.retry_backoff_secs", secs)?;
+        }
+        if let Some(secs) = config.max_retry_after_secs {
+            policy.max_retry_after = duration("policy.max_retry_after_secs", secs)?;
+        }
+        policy.max_pages_per_domain = config.max_pages_per_domain;
+
+        for seed in self.seeds {
+            crawler.enqueue(seed);
+        }
+
+        Ok(crawler)
+    }
+}
+
+/// A setting's value in seconds as a duration, which has to be finite and not negative
+fn duration(setting: &str, secs: f32) -> Result<Duration, ConfigError> {
+    Duration::try_from_secs_f32(secs).map_err(|err| invalid(setting, err))
+}
+
+/// Header names and values as a header map, rejecting any that can't be sent
+fn headers(headers: &BTreeMap<String, String>) -> Result<HeaderMap, ConfigError> {
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+        let setting = format!("headers.{name}");
+        let name = HeaderName::from_bytes(name.as_bytes()).map_err(|err| invalid(&setting, err))?;
+        let value = HeaderValue::from_str(value).map_err(|err| invalid(&setting, err))?;
+        map.insert(name, value);
+    }
+
+    Ok(map)
+}
+
+fn invalid<E: fmt::Display>(setting: &str, err: E) -> ConfigError {
+    ConfigError::Invalid {
+        setting: setting.to_string(),
+        message: err.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, time::Duration};
+
+    use crate::crawler::{LinkSource, WebCrawler, DEFAULT_USER_AGENT};
+
+    use super::ConfigError;
+
+    /// Writes a config to a temporary file with the given extension
+    fn config_file(extension: &str, contents: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::Builder::new()
+            .suffix(extension)
+            .tempfile()
+            .expect("Create config file");
+        file.write_all(contents.as_bytes())
+            .expect("Write config file");
+        file
+    }
+
+    #[test]
+    fn toml_config_wired_into_crawler() {
+        let file = config_file(
+            ".toml",
+            r#"
+            seeds = ["https://example.com/", "https://rust-lang.org/"]
+            user_agent = "config-bot/1.0"
+            accept_language = "en"
+            respect_robots = false
+            link_sources = [{ tag = "a", attribute = "href" }, { tag = "link", attribute = "href" }]
+            link_sample = 5
+            path_prefix = "/docs/"
+            connect_timeout_secs = 2.5
+
+            [headers]
+            x-crawl-id = "7"
+
+            [autosave]
+            path = "crawl.json"
+            every = 50
+
+            [policy]
+            per_host_delay_secs = 0.5
+            max_concurrent = 4
+            timeout_secs = 10
+            max_attempts = 5
+            max_retry_after_secs = 120
+            max_pages_per_domain = 50
+            "#,
+        );
+
+        let crawler = WebCrawler::from_config_file(file.path()).expect("Build crawler");
+        assert_eq!(crawler.user_agent, "config-bot/1.0");
+        assert_eq!(crawler.accept_language.as_deref(), Some("en"));
+        assert_eq!(crawler.headers["x-crawl-id"], "7");
+        assert!(!crawler.respect_robots);
+        assert_eq!(
+            crawler.link_sources,
+            [
+                LinkSource::new("a", "href"),
+                LinkSource::new("link", "href")
+            ]
+        );
+        assert_eq!(crawler.link_sample, Some(5));
+        assert_eq!(crawler.path_prefix.as_deref(), Some("/docs/"));
+        let autosave = crawler.autosave.as_ref().expect("Autosave set");
+        assert_eq!(autosave.every, 50);
+
+        assert_eq!(crawler.policy.per_host_delay, Duration::from_millis(500));
+        assert_eq!(crawler.policy.max_concurrent, 4);
+        assert_eq!(crawler.policy.timeout, Duration::from_secs(10));
+        assert_eq!(crawler.policy.max_attempts, 5);
+        assert_eq!(crawler.policy.max_retry_after, Duration::from_secs(120));
+        assert_eq!(crawler.policy.max_pages_per_domain, Some(50));
+        // Settings left out keep their defaults
+        assert_eq!(crawler.policy.max_per_host, 2);
+        assert!(!crawler.store_body_text);
+
+        let urls: Vec<_> = crawler
+            .site_pool
+            .values()
+            .map(|site| site.url.as_str())
+            .collect();
+        assert_eq!(urls, ["https://example.com/", "https://rust-lang.org/"]);
+    }
+
+    #[test]
+    fn json_config_read() {
+        let file = config_file(".json", r#"{"respect_robots": false, "seed": 3}"#);
+
+        let crawler = WebCrawler::from_config_file(file.path()).expect("Build crawler");
+        assert!(!crawler.respect_robots);
+        assert_eq!(crawler.user_agent, DEFAULT_USER_AGENT);
+        assert!(crawler.site_pool.is_empty());
+    }
+
+    #[test]
+    fn bad_configs_rejected() {
+        let typo = config_file(".toml", "respect_robot = false");
+        assert!(matches!(
+            WebCrawler::from_config_file(typo.path()),
+            Err(ConfigError::Format(_))
+        ));
+
+        let header = config_file(".toml", "[headers]\nx-bad = \"line\\nbreak\"");
+        match WebCrawler::from_config_file(header.path()) {
+            Err(ConfigError::Invalid { setting, .. }) => assert_eq!(setting, "headers.x-bad"),
+            other => panic!("Expected invalid header, got {other:?}"),
+        }
+
+        let delay = config_file(".toml", "[policy]\nper_host_delay_secs = -1");
+        assert!(matches!(
+            WebCrawler::from_config_file(delay.path()),
+            Err(ConfigError::Invalid { .. })
+        ));
+
+        assert!(matches!(
+            WebCrawler::from_config_file("missing.toml"),
+            Err(ConfigError::Io(_))
+        ));
+    }
+}
@@ -1,196 +1,1953 @@
 //! Web crawler for collecting site information and sites linked to from this site
 
 use std::{
-    collections::HashSet,
-    fs::File,
-    io::{Read, Write},
-    path::PathBuf,
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
+use encoding_rs::{Encoding, UTF_8};
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use reqwest::{
+    header::{
+        HeaderMap, ACCEPT_LANGUAGE, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+        LAST_MODIFIED, RETRY_AFTER, USER_AGENT,
+    },
+    Client, RequestBuilder, StatusCode,
+};
+use scraper::{Html, Selector};
 use serde::{Deserialize, Serialize};
 use slotmap::{new_key_type, SlotMap};
-use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::{
+    sync::{mpsc::UnboundedSender, OwnedSemaphorePermit, Semaphore},
+    task::JoinSet,
+    time::{sleep, sleep_until, Instant},
+};
+use tracing::{debug, info, warn};
+use url::{Position, Url};
+
+use crate::{
+    config::{ConfigError, CrawlerConfig},
+    robots::Robots,
+};
 
 new_key_type! {pub struct SiteKey;}
 
+/// Seed for the random choices made while crawling unless another is given
+pub const DEFAULT_SEED: u64 = 0x5EED;
+
+/// Most bytes of a page's visible text kept by default
+pub const BODY_TEXT_LIMIT: usize = 32 * 1024;
+
+/// `User-Agent` sent with every request unless the crawler's is changed, naming the crate and its
+/// version
+pub const DEFAULT_USER_AGENT: &str =
+    concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// Elements whose text is never shown as part of a page's content
+const HIDDEN_ELEMENTS: [&str; 6] = ["head", "script", "style", "nav", "noscript", "template"];
+
+/// Called after each page is crawled with how far the crawl has got
+pub type ProgressCallback = Box<dyn FnMut(CrawlProgress) + Send>;
+
+/// A snapshot of a crawl's progress, taken after a page is crawled
+#[derive(Clone, Debug, PartialEq)]
+pub struct CrawlProgress {
+    /// URL of the page that was just crawled
+    pub url: String,
+    /// Pages crawled so far, including this one
+    pub crawled: usize,
+    /// Pages still waiting in the queue
+    pub queued: usize,
+}
+
+/// When [`WebCrawler::crawl_until`] stops crawling
+#[derive(Clone, Debug, PartialEq)]
+pub enum CrawlLimit {
+    /// Stops once this many pages are crawled
+    MaxPages(usize),
+    /// Stops once this long has passed, checked between pages
+    MaxDuration(Duration),
+    /// Stops once there's nothing left in the queue
+    QueueEmpty,
+    /// Stops as soon as any of these limits is reached
+    Any(Vec<CrawlLimit>),
+}
+
+impl CrawlLimit {
+    fn reached(&self, pages: usize, elapsed: Duration, queue_empty: bool) -> bool {
+        match self {
+            Self::MaxPages(max) => pages >= *max,
+            Self::MaxDuration(max) => elapsed >= *max,
+            Self::QueueEmpty => queue_empty,
+            Self::Any(limits) => limits
+                .iter()
+                .any(|limit| limit.reached(pages, elapsed, queue_empty)),
+        }
+    }
+}
+
+/// What a call to [`WebCrawler::crawl_until`] got done
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CrawlSummary {
+    /// Pages fetched, leaving out sites skipped by robots.txt, a host's page limit or a dry run
+    pub pages: usize,
+    /// Pages that couldn't be fetched even after retrying
+    pub failures: usize,
+    pub elapsed: Duration,
+}
+
+/// Something that happened during a crawl, sent to [`WebCrawler::events`] as it happens
+#[derive(Clone, Debug, PartialEq)]
+pub enum CrawlEvent {
+    /// A page's response was recorded
+    Fetched {
+        /// Key the page ends up under, which differs from the one it was queued under when it
+        /// redirected to a site already in the pool
+        key: SiteKey,
+        url: String,
+        /// Links on the page, whether or not they were followed
+        links_found: usize,
+        /// Time spent fetching the page, retries included
+        elapsed: Duration,
+    },
+    /// A site taken off the queue wasn't fetched, or was fetched but not recorded as a page of
+    /// its own
+    Skipped { url: String, reason: SkipReason },
+    /// A site couldn't be fetched even after retrying
+    Failed { url: String, error: String },
+    /// The site pool was written to a file
+    Checkpointed { path: PathBuf },
+}
+
+/// Why a site wasn't fetched or recorded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SkipReason {
+    /// robots.txt disallows it
+    Robots,
+    /// Its host already had `max_pages_per_domain` pages taken to be crawled
+    HostLimit,
+    /// It redirected to a site already in the pool, which it was merged into
+    Duplicate,
+}
+
+/// An element attribute that links to another page, like the `href` of an `<a>`
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct LinkSource {
+    pub tag: String,
+    pub attribute: String,
+}
+
+impl LinkSource {
+    pub fn new<S: Into<String>>(tag: S, attribute: S) -> Self {
+        Self {
+            tag: tag.into(),
+            attribute: attribute.into(),
+        }
+    }
+}
+
+impl Default for LinkSource {
+    fn default() -> Self {
+        Self::new("a", "href")
+    }
+}
+
+/// A link found on a page
+#[derive(Clone, Debug, PartialEq)]
+pub struct Link {
+    /// Where the link points, resolved against the page and normalized
+    pub url: String,
+    /// The link's `rel` attribute, if it had one
+    pub rel: Option<String>,
+    /// Text inside the link's element, with whitespace collapsed
+    pub anchor: String,
+}
+
+impl Link {
+    /// Whether the page asked for the link not to be followed with `rel="nofollow"`
+    pub fn is_nofollow(&self) -> bool {
+        self.rel.as_deref().is_some_and(|rel| {
+            rel.split_ascii_whitespace()
+                .any(|token| token.eq_ignore_ascii_case("nofollow"))
+        })
+    }
+}
+
+/// What's pulled out of a page's HTML
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ParsedPage {
+    /// Text of the first `<title>`, trimmed, or the Open Graph title if that's empty
+    pub title: String,
+    /// Every distinct link, in the order they're found. A link found more than once is only
+    /// nofollow if every copy of it is
+    pub links: Vec<Link>,
+    /// The `description` meta tag, falling back to `og:description`
+    pub description: Option<String>,
+    /// The `og:title` meta tag
+    pub og_title: Option<String>,
+    /// Text shown on the page, outside of scripts, styles and navigation, with whitespace
+    /// collapsed
+    pub text: String,
+}
+
+impl ParsedPage {
+    /// Every link's URL, followable or not
+    pub fn urls(&self) -> Vec<&str> {
+        self.links.iter().map(|link| link.url.as_str()).collect()
+    }
+
+    /// Parses a page, following links from the given element attributes. Links are resolved
+    /// against the page's URL and normalized, dropping any that aren't to web pages
+    pub fn parse(html: &str, page_url: &str, sources: &[LinkSource]) -> Self {
+        let document = Html::parse_document(html);
+
+        // Meta tags are keyed by `name`, or by `property` for Open Graph
+        let mut metas = HashMap::new();
+        if let Ok(selector) = Selector::parse("meta[content]") {
+            for meta in document.select(&selector) {
+                let meta = meta.value();
+                let (Some(key), Some(content)) = (
+                    meta.attr("name").or_else(|| meta.attr("property")),
+                    meta.attr("content").map(str::trim),
+                ) else {
+                    continue;
+                };
+                if !content.is_empty() {
+                    metas
+                        .entry(key.to_ascii_lowercase())
+                        .or_insert_with(|| content.to_string());
+                }
+            }
+        }
+        let description = metas
+            .get("description")
+            .or_else(|| metas.get("og:description"))
+            .cloned();
+        let og_title = metas.remove("og:title");
+
+        let title = Selector::parse("title")
+            .ok()
+            .and_then(|selector| document.select(&selector).next())
+            .map(|title| title.text().collect::<String>().trim().to_string())
+            .filter(|title| !title.is_empty())
+            .or_else(|| og_title.clone())
+            .unwrap_or_default();
+
+        let mut seen: HashMap<String, usize> = HashMap::new();
+        let mut links: Vec<Link> = vec![];
+        for source in sources {
+            // Parsing lowercases element and attribute names, so the selector has to match that
+            let tag = source.tag.to_ascii_lowercase();
+            let attribute = source.attribute.to_ascii_lowercase();
+            let Ok(selector) = Selector::parse(&format!("{tag}[{attribute}]")) else {
+                continue;
+            };
+
+            for element in document.select(&selector) {
+                let Some(url) = element
+                    .value()
+                    .attr(&attribute)
+                    .and_then(|url| normalize_url(url, page_url))
+                else {
+                    continue;
+                };
+
+                let anchor: String = element.text().collect();
+                let link = Link {
+                    url,
+                    rel: element.value().attr("rel").map(str::to_string),
+                    anchor: anchor.split_whitespace().collect::<Vec<_>>().join(" "),
+                };
+                match seen.get(&link.url) {
+                    Some(&idx) if links[idx].is_nofollow() && !link.is_nofollow() => {
+                        links[idx] = link
+                    }
+                    Some(_) => {}
+                    None => {
+                        seen.insert(link.url.clone(), links.len());
+                        links.push(link);
+                    }
+                }
+            }
+        }
+
+        let text: String = document
+            .root_element()
+            .descendants()
+            .filter_map(|node| {
+                let text = node.value().as_text()?;
+                let hidden = node.ancestors().any(|ancestor| {
+                    ancestor
+                        .value()
+                        .as_element()
+                        .is_some_and(|element| HIDDEN_ELEMENTS.contains(&element.name()))
+                });
+                (!hidden).then_some(&**text)
+            })
+            .collect();
+
+        Self {
+            title,
+            links,
+            description,
+            og_title,
+            text: text.split_whitespace().collect::<Vec<_>>().join(" "),
+        }
+    }
+}
+
+/// How politely the crawler spaces out its requests. By default requests aren't spaced at all
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CrawlPolicy {
+    /// Least time between two fetches from the same host. A longer robots.txt crawl delay wins
+    pub per_host_delay: Duration,
+    /// Most fetches per second across every host. Anything but a positive, finite rate is
+    /// unlimited
+    pub max_requests_per_second: f32,
+    /// Most fetches in flight at once when crawling concurrently
+    pub max_concurrent: usize,
+    /// Most fetches in flight at once to the same host when crawling concurrently, so one slow
+    /// host can't take up every fetch
+    pub max_per_host: usize,
+    /// Longest a single fetch may take, body included, before it's abandoned
+    pub timeout: Duration,
+    /// Most times a page is fetched before giving up on it. Connection errors, timeouts, server
+    /// errors and rate limiting are retried, anything else isn't
+    pub max_attempts: u32,
+    /// Wait before the first retry, doubling with each retry after. A rate limited response's
+    /// `Retry-After` wins over this
+    pub retry_backoff: Duration,
+    /// Longest wait a response's `Retry-After` can ask for before a retry, so a server can't
+    /// stall the crawl for hours
+    pub max_retry_after: Duration,
+    /// Most pages crawled from any one host when set, so one huge site can't take over a crawl.
+    /// Links to a host past its limit are still recorded, just never crawled
+    pub max_pages_per_domain: Option<usize>,
+}
+
+impl Default for CrawlPolicy {
+    fn default() -> Self {
+        Self {
+            per_host_delay: Duration::ZERO,
+            max_requests_per_second: f32::INFINITY,
+            max_concurrent: 8,
+            max_per_host: 2,
+            timeout: Duration::from_secs(30),
+            max_attempts: 3,
+            retry_backoff: Duration::from_millis(500),
+            max_retry_after: Duration::from_secs(60),
+            max_pages_per_domain: None,
+        }
+    }
+}
+
+/// A URL that couldn't be fetched, even after retrying
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CrawlFailure {
+    pub url: String,
+    /// What went wrong on the last attempt
+    pub error: String,
+    /// How many times the URL was fetched
+    pub attempts: u32,
+}
+
+/// A page's response, read in full away from the crawler so several can be fetched at once
+struct Fetched {
+    /// Where the response came from after following any redirects
+    url: String,
+    status: u16,
+    content_type: Option<String>,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    /// How long a rate limited or unavailable server asked to be left alone for
+    retry_after: Option<Duration>,
+    /// `None` if the response isn't an HTML page, so its body wasn't read
+    body: Option<Vec<u8>>,
+    /// How many times the page was fetched to get this response
+    attempts: u32,
+    /// Time spent fetching the page over every attempt
+    elapsed: Duration,
+}
+
+/// What became of a page's response once it was stored
+enum Stored {
+    /// The page was recorded, with this many links found on it
+    Page { links_found: usize },
+    /// The server was still failing once out of retries, for this reason
+    Failing(String),
+    /// The page redirected to a site already in the pool and was merged into it, leaving behind
+    /// the URL it was queued under
+    Merged(String),
+}
+
+/// What became of a site taken from the queue
+enum Visit {
+    /// The site was fetched and its response recorded under this key
+    Crawled(SiteKey),
+    /// The site was passed over without being fetched
+    Skipped,
+}
+
+/// Why a page couldn't be fetched, after however many attempts
+struct FetchError {
+    error: String,
+    attempts: u32,
+}
+
+/// Sends a request, retrying transient failures with exponential backoff until the policy's
+/// attempts run out. Once they have, a server error or rate limiting response is still given back
+async fn fetch(request: RequestBuilder, policy: CrawlPolicy) -> Result<Fetched, FetchError> {
+    let request = request.timeout(policy.timeout);
+    let started = Instant::now();
+    let mut attempts = 0;
+
+    loop {
+        attempts += 1;
+        let retries_left = attempts < policy.max_attempts;
+        let attempt = request.try_clone().ok_or_else(|| FetchError {
+            error: "request can't be resent".to_string(),
+            attempts,
+        })?;
+
+        let retry_after = match fetch_once(attempt).await {
+            Ok(fetched) if retries_left && is_transient(fetched.status) => fetched
+                .retry_after
+                .map(|wait| wait.min(policy.max_retry_after)),
+            Ok(fetched) => {
+                return Ok(Fetched {
+                    attempts,
+                    elapsed: started.elapsed(),
+                    ..fetched
+                })
+            }
+            Err(err) if retries_left && (err.is_connect() || err.is_timeout()) => None,
+            Err(err) => {
+                return Err(FetchError {
+                    error: err.to_string(),
+                    attempts,
+                })
+            }
+        };
+
+        let backoff = policy
+            .retry_backoff
+            .saturating_mul(2u32.saturating_pow(attempts - 1));
+        sleep(retry_after.unwrap_or(backoff)).await;
+    }
+}
+
+/// Whether a response status is worth retrying, being a server error or rate limiting
+fn is_transient(status: u16) -> bool {
+    status >= 500 || status == StatusCode::TOO_MANY_REQUESTS.as_u16()
+}
+
+/// Sends a request once, reading its body if it's an HTML page
+async fn fetch_once(request: RequestBuilder) -> reqwest::Result<Fetched> {
+    let response = request.send().await?;
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+
+    let status = response.status().as_u16();
+    let content_type = header(CONTENT_TYPE);
+    let etag = header(ETAG);
+    let last_modified = header(LAST_MODIFIED);
+    // Only the delay in seconds form is understood, not an HTTP date
+    let retry_after = header(RETRY_AFTER)
+        .and_then(|secs| secs.trim().parse().ok())
+        .map(Duration::from_secs);
+    let url = response.url().to_string();
+
+    let body = if is_html_page(status, content_type.as_deref()) {
+        Some(response.bytes().await?.to_vec())
+    } else {
+        None
+    };
+
+    Ok(Fetched {
+        url,
+        status,
+        content_type,
+        etag,
+        last_modified,
+        retry_after,
+        body,
+        attempts: 1,
+        elapsed: Duration::ZERO,
+    })
+}
+
+/// Whether a response is a page worth parsing for links, being successful and either HTML or of
+/// no stated type
+fn is_html_page(status: u16, content_type: Option<&str>) -> bool {
+    let html = content_type.is_none_or(|content_type| {
+        let mime = content_type.split(';').next().unwrap_or_default().trim();
+        mime.eq_ignore_ascii_case("text/html") || mime.eq_ignore_ascii_case("application/xhtml+xml")
+    });
+
+    (200..300).contains(&status) && html
+}
+
+/// Why a site pool couldn't be saved
+#[derive(Debug)]
+pub enum SaveError {
+    /// Something is already saved at the path, and overwriting it wasn't allowed
+    AlreadyExists(PathBuf),
+    Io(io::Error),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AlreadyExists(path) => {
+                write!(
+                    f,
+                    "{} already exists and won't be overwritten",
+                    path.display()
+                )
+            }
+            Self::Io(err) => write!(f, "failed to save site pool: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+impl From<io::Error> for SaveError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// First bytes of a gzipped file
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// First bytes of a site pool saved in the binary format, which has no header of its own
+const BINARY_MAGIC: &[u8] = b"PRSB\x01";
+
+/// How a saved site pool is encoded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    /// Indented JSON that's easier to read
+    PrettyJson,
+    /// Newline delimited JSON, one site and its key per line, which can be read back a line at a
+    /// time. See [`WebCrawler::load_stream`]
+    Ndjson,
+    /// Gzipped JSON
+    #[cfg(feature = "gzip")]
+    JsonGz,
+    /// A compact binary serde encoding, far smaller and quicker to load than JSON
+    #[cfg(feature = "binary")]
+    Binary,
+    /// The binary format, gzipped
+    #[cfg(all(feature = "binary", feature = "gzip"))]
+    BinaryGz,
+}
+
+/// Writes a site pool in a format
+fn encode(
+    pool: &SlotMap<SiteKey, SiteLog>,
+    format: Format,
+    writer: &mut dyn Write,
+) -> io::Result<()> {
+    match format {
+        Format::Json => serde_json::to_writer(writer, pool)?,
+        Format::PrettyJson => serde_json::to_writer_pretty(writer, pool)?,
+        Format::Ndjson => {
+            for (key, site) in pool {
+                serde_json::to_writer(&mut *writer, &SiteRecord { key, site })?;
+                writer.write_all(b"\n")?;
+            }
+        }
+        #[cfg(feature = "gzip")]
+        Format::JsonGz => gzipped(pool, Format::Json, writer)?,
+        #[cfg(feature = "binary")]
+        Format::Binary => {
+            writer.write_all(BINARY_MAGIC)?;
+            postcard::to_io(pool, writer).map_err(|err| io::Error::other(err.to_string()))?;
+        }
+        #[cfg(all(feature = "binary", feature = "gzip"))]
+        Format::BinaryGz => gzipped(pool, Format::Binary, writer)?,
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "gzip")]
+fn gzipped(
+    pool: &SlotMap<SiteKey, SiteLog>,
+    format: Format,
+    writer: &mut dyn Write,
+) -> io::Result<()> {
+    let mut encoder = flate2::write::GzEncoder::new(writer, flate2::Compression::default());
+    encode(pool, format, &mut encoder)?;
+    encoder.finish()?;
+
+    Ok(())
+}
+
+/// Reads a site pool in whichever format it was saved in
+fn decode(bytes: &[u8]) -> io::Result<SlotMap<SiteKey, SiteLog>> {
+    if bytes.starts_with(&GZIP_MAGIC) {
+        #[cfg(feature = "gzip")]
+        {
+            use std::io::Read;
+
+            let mut unzipped = vec![];
+            flate2::read::GzDecoder::new(bytes).read_to_end(&mut unzipped)?;
+            return decode(&unzipped);
+        }
+        #[cfg(not(feature = "gzip"))]
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "gzip support isn't built in",
+        ));
+    }
+
+    if let Some(encoded) = bytes.strip_prefix(BINARY_MAGIC) {
+        #[cfg(feature = "binary")]
+        return postcard::from_bytes(encoded)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()));
+        #[cfg(not(feature = "binary"))]
+        {
+            let _ = encoded;
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "binary format support isn't built in",
+            ));
+        }
+    }
+
+    // A JSON pool is an array, while each line of newline delimited JSON is an object
+    if bytes.trim_ascii_start().starts_with(b"{") {
+        return Ok(read_ndjson(bytes)?.sites);
+    }
+
+    Ok(serde_json::from_slice(bytes)?)
+}
+
+/// A site pool streamed in from newline delimited JSON
+#[derive(Debug, Default)]
+pub struct StreamedPool {
+    pub sites: SlotMap<SiteKey, SiteLog>,
+    /// Line numbers, counting from 1, of lines that couldn't be read and were skipped
+    pub bad_lines: Vec<usize>,
+}
+
+/// Reads newline delimited JSON a line at a time, skipping malformed lines along with any links to
+/// the sites they held
+fn read_ndjson<R: BufRead>(reader: R) -> io::Result<StreamedPool> {
+    let mut pool = StreamedPool::default();
+    let mut saved_keys = HashMap::new();
+
+    for (line_num, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match serde_json::from_str::<OwnedSiteRecord>(&line) {
+            Ok(record) => {
+                saved_keys.insert(record.key, pool.sites.insert(record.site));
+            }
+            Err(_) => pool.bad_lines.push(line_num + 1),
+        }
+    }
+
+    // Keys are reassigned on load, so links are remapped to the new keys. Sites are inserted in the
+    // order they were saved, so a pool nothing was removed from gets its own keys back
+    for site in pool.sites.values_mut() {
+        site.connections = site
+            .connections
+            .iter()
+            .filter_map(|key| saved_keys.get(key).copied())
+            .collect();
+    }
+
+    Ok(pool)
+}
+
+/// Where a crawl checkpoints itself to and how often
+#[derive(Clone, Debug, PartialEq)]
+pub struct Autosave {
+    pub path: PathBuf,
+    /// How many pages are crawled between checkpoints
+    pub every: usize,
+}
+
+/// Token bucket limiting how often fetches start, holding up to a second's worth of tokens
+#[derive(Debug)]
+struct RateLimiter {
+    tokens: f32,
+    refilled: Instant,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self {
+            tokens: 1f32,
+            refilled: Instant::now(),
+        }
+    }
+}
+
+impl RateLimiter {
+    /// Takes a token, waiting for one to refill if the bucket is empty
+    async fn acquire(&mut self, rate: f32) {
+        if !(rate.is_finite() && rate > 0f32) {
+            return;
+        }
+
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled).as_secs_f32();
+        self.tokens = (self.tokens + elapsed * rate).min(rate.max(1f32));
+        self.refilled = now;
+
+        if self.tokens < 1f32 {
+            let wait = Duration::from_secs_f32((1f32 - self.tokens) / rate);
+            sleep(wait).await;
+            self.refilled += wait;
+            self.tokens = 1f32;
+        }
+        self.tokens -= 1f32;
+    }
+}
+
 /// A webcrawling agent that parses a site's metadata and adds all links found within to a queue to
 /// do the same to
-#[derive(Debug)]
 pub struct WebCrawler {
     pub site_pool: SlotMap<SiteKey, SiteLog>,
-    pub site_queue: UnboundedReceiver<SiteKey>,
-    pub site_queue_sender: UnboundedSender<SiteKey>,
+    /// Sites waiting to be crawled, in the order they were queued
+    pub site_queue: VecDeque<SiteKey>,
     pub visited: HashSet<String>,
+    /// When set, sites are recorded in `planned` instead of being fetched
+    pub dry_run: bool,
+    /// URLs that would have been fetched during a dry run, in crawl order
+    pub planned: Vec<String>,
+    /// HTTP client shared by every request
+    pub client: Client,
+    /// `User-Agent` sent with every request, which robots.txt rules are matched against too
+    pub user_agent: String,
+    /// `Accept-Language` sent with every request when set
+    pub accept_language: Option<String>,
+    /// Further headers sent with every request, replacing the crawler's own where they share a
+    /// name
+    pub headers: HeaderMap,
+    /// Reports progress after every crawled page, leaving how to show it up to the caller
+    pub progress: Option<ProgressCallback>,
+    /// Sends what happens during the crawl as it happens when set, such as to a UI showing it live
+    pub events: Option<UnboundedSender<CrawlEvent>>,
+    /// Which element attributes links are followed from
+    pub link_sources: Vec<LinkSource>,
+    /// Whether robots.txt is checked before fetching, skipping disallowed pages and waiting out
+    /// any crawl delay
+    pub respect_robots: bool,
+    /// Parsed robots.txt of every origin fetched from so far
+    pub robots: HashMap<String, Robots>,
+    /// URLs that weren't fetched because robots.txt disallowed them, in crawl order
+    pub skipped: Vec<String>,
+    /// URLs that couldn't be fetched even after retrying, in crawl order
+    pub failures: Vec<CrawlFailure>,
+    /// How requests are spaced out
+    pub policy: CrawlPolicy,
+    /// Checkpoints the site pool every so many crawled pages when set
+    pub autosave: Option<Autosave>,
+    /// Whether each page's visible text is kept in its `SiteLog`, which makes saved pools much
+    /// larger
+    pub store_body_text: bool,
+    /// Most bytes of visible text kept per page
+    pub body_text_limit: usize,
+    /// URLs that redirected, mapped to the URL they landed on
+    pub aliases: HashMap<String, String>,
+    /// Follows at most this many of each page's new links when set, picked at random
+    pub link_sample: Option<usize>,
+    /// Whether links marked `rel="nofollow"` are kept in each page's `SiteLog`. They're never
+    /// crawled or ranked either way
+    pub record_nofollow: bool,
+    /// Only follows links on the same host as the page they're on whose path starts with this
+    /// prefix when set, such as `/docs/`, keeping a crawl seeded there to that part of its host
+    pub path_prefix: Option<String>,
+    /// Drives every random choice made while crawling, so crawls with the same seed are repeatable
+    rng: StdRng,
+    /// When each origin was last fetched from
+    last_fetch: HashMap<String, Instant>,
+    /// Limits fetches across every origin
+    rate_limiter: RateLimiter,
+    /// Sites taken off the queue but not yet crawled, by origin, alongside the order they were
+    /// taken in
+    frontier: HashMap<String, VecDeque<(u64, SiteKey)>>,
+    /// Order given to the next site taken off the queue
+    taken: u64,
+    /// Fetches each origin may still start while crawling concurrently
+    host_slots: HashMap<String, Arc<Semaphore>>,
+    /// Pages crawled from each host, and those being fetched, which `max_pages_per_domain` caps.
+    /// Once no fetches are in flight it counts the sites with a `last_crawled` time, like
+    /// `resume` does
+    host_pages: HashMap<String, usize>,
+    /// Key of the site under each URL added to the pool, so links are checked against the pool
+    /// without scanning it
+    urls: HashMap<String, SiteKey>,
+    /// Pages crawled so far, counting up by one with every page whatever it redirected to.
+    /// Progress reports and autosaves go by this
+    pages_crawled: usize,
+}
+
+impl fmt::Debug for WebCrawler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebCrawler")
+            .field("site_pool", &self.site_pool)
+            .field("site_queue", &self.site_queue)
+            .field("visited", &self.visited)
+            .field("dry_run", &self.dry_run)
+            .field("planned", &self.planned)
+            .field("client", &self.client)
+            .field("user_agent", &self.user_agent)
+            .field("accept_language", &self.accept_language)
+            .field("headers", &self.headers)
+            .field("progress", &self.progress.is_some())
+            .field("events", &self.events.is_some())
+            .field("link_sources", &self.link_sources)
+            .field("respect_robots", &self.respect_robots)
+            .field("robots", &self.robots)
+            .field("skipped", &self.skipped)
+            .field("failures", &self.failures)
+            .field("policy", &self.policy)
+            .field("autosave", &self.autosave)
+            .field("store_body_text", &self.store_body_text)
+            .field("body_text_limit", &self.body_text_limit)
+            .field("aliases", &self.aliases)
+            .field("link_sample", &self.link_sample)
+            .field("record_nofollow", &self.record_nofollow)
+            .field("path_prefix", &self.path_prefix)
+            .field("rng", &self.rng)
+            .field("last_fetch", &self.last_fetch)
+            .field("rate_limiter", &self.rate_limiter)
+            .field("frontier", &self.frontier)
+            .field("taken", &self.taken)
+            .field("host_slots", &self.host_slots)
+            .field("host_pages", &self.host_pages)
+            .field("urls", &self.urls)
+            .field("pages_crawled", &self.pages_crawled)
+            .finish()
+    }
 }
 
 impl Default for WebCrawler {
     fn default() -> Self {
-        let (sender, receiver) = unbounded_channel();
         Self {
-            site_queue: receiver,
-            site_queue_sender: sender,
+            site_queue: VecDeque::new(),
             site_pool: SlotMap::default(),
             visited: HashSet::new(),
+            dry_run: false,
+            planned: vec![],
+            client: Client::new(),
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            accept_language: None,
+            headers: HeaderMap::new(),
+            progress: None,
+            events: None,
+            link_sources: vec![LinkSource::default()],
+            respect_robots: true,
+            robots: HashMap::new(),
+            skipped: vec![],
+            failures: vec![],
+            policy: CrawlPolicy::default(),
+            autosave: None,
+            store_body_text: false,
+            body_text_limit: BODY_TEXT_LIMIT,
+            aliases: HashMap::new(),
+            link_sample: None,
+            record_nofollow: false,
+            path_prefix: None,
+            rng: StdRng::seed_from_u64(DEFAULT_SEED),
+            last_fetch: HashMap::new(),
+            rate_limiter: RateLimiter::default(),
+            frontier: HashMap::new(),
+            taken: 0,
+            host_slots: HashMap::new(),
+            host_pages: HashMap::new(),
+            urls: HashMap::new(),
+            pages_crawled: 0,
         }
     }
 }
 
 impl WebCrawler {
+    /// Seeds the random choices made while crawling, which otherwise use `DEFAULT_SEED`
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
     /// Adds a URL to the crawling queue
     pub fn enqueue<S: Into<String>>(&mut self, input: S) -> SiteKey {
-        let site_log = SiteLog {
-            url: input.into(),
-            ..Default::default()
-        };
-        let inserted = self.site_pool.insert(site_log);
-        let _ = self.site_queue_sender.send(inserted);
+        let inserted = self.add_site(input.into());
+        self.site_queue.push_back(inserted);
+        debug!(url = %self.site_pool[inserted].url, "queued");
 
         inserted
     }
 
-    /// Saves the site_pool slotmap as a JSON file
-    pub fn save<P: Into<PathBuf>>(&mut self, file: P) -> Option<()> {
-        let mut file = File::create_new(file.into()).ok()?;
-        file.write_all(serde_json::to_string(&self.site_pool).ok()?.as_bytes())
-            .ok()?;
+    /// Adds a URL to the site pool without queuing it to be crawled
+    fn add_site(&mut self, input: String) -> SiteKey {
+        let url = normalize_url(&input, &input).unwrap_or(input);
+        let key = self.site_pool.insert(SiteLog {
+            url: url.clone(),
+            ..Default::default()
+        });
+        self.urls.insert(url, key);
+
+        key
+    }
+
+    /// Adds every URL that hasn't already been visited or queued to the crawling queue, returning
+    /// the keys of those that were added
+    pub fn enqueue_all<I, S>(&mut self, urls: I) -> Vec<SiteKey>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut added = vec![];
+
+        for url in urls {
+            let url = url.into();
+            if !self.is_known(&url) {
+                added.push(self.enqueue(url));
+            }
+        }
+
+        added
+    }
+
+    /// Seeds the crawling queue from a file of newline delimited URLs, ignoring blank lines and
+    /// lines starting with `#`. Returns how many new URLs were queued
+    pub fn enqueue_from_file<P: Into<PathBuf>>(&mut self, file: P) -> io::Result<usize> {
+        let contents = fs::read_to_string(file.into())?;
+        let seeds = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+
+        Ok(self.enqueue_all(seeds).len())
+    }
+
+    /// Sends an event to `events`, only building it when something is listening
+    fn emit<F: FnOnce() -> CrawlEvent>(&self, event: F) {
+        if let Some(events) = self.events.as_ref().filter(|events| !events.is_closed()) {
+            let _ = events.send(event());
+        }
+    }
+
+    /// Whether a URL, once normalized, has already been visited or is sitting in the site pool
+    fn is_known(&self, url: &str) -> bool {
+        let url = normalize_url(url, url).unwrap_or_else(|| url.to_string());
+        self.visited.contains(&url) || self.site_for(&url).is_some()
+    }
+
+    /// Key of the site in the pool under a normalized URL, or the URL it redirected to
+    fn site_for(&self, url: &str) -> Option<SiteKey> {
+        let url = self.aliases.get(url).map_or(url, String::as_str);
+        self.urls
+            .get(url)
+            .copied()
+            // Sites changed or removed through `site_pool` directly leave stale entries behind
+            .filter(|key| self.site_pool.get(*key).is_some_and(|site| site.url == url))
+    }
+
+    /// Saves the site_pool slotmap as a JSON file, failing if something is already saved there
+    /// unless `overwrite` is set. Like [`WebCrawler::checkpoint`], the file is only ever replaced
+    /// whole
+    pub fn save<P: Into<PathBuf>>(&self, file: P, overwrite: bool) -> Result<(), SaveError> {
+        self.save_as(file, Format::Json, overwrite)
+    }
+
+    /// Saves the site_pool like [`WebCrawler::save`], as indented JSON that's easier to read
+    pub fn save_pretty<P: Into<PathBuf>>(&self, file: P, overwrite: bool) -> Result<(), SaveError> {
+        self.save_as(file, Format::PrettyJson, overwrite)
+    }
+
+    /// Saves the site_pool like [`WebCrawler::save`], encoded in the given format
+    pub fn save_as<P: Into<PathBuf>>(
+        &self,
+        file: P,
+        format: Format,
+        overwrite: bool,
+    ) -> Result<(), SaveError> {
+        let file = file.into();
+        match self.write_atomically(file.clone(), format, overwrite) {
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                Err(SaveError::AlreadyExists(file))
+            }
+            written => Ok(written?),
+        }
+    }
 
-        Some(())
+    /// Saves the site_pool like [`WebCrawler::save`], as newline delimited JSON that
+    /// [`WebCrawler::load_stream`] reads back without holding the whole file in memory
+    pub fn save_stream<P: Into<PathBuf>>(&self, file: P, overwrite: bool) -> Result<(), SaveError> {
+        self.save_as(file, Format::Ndjson, overwrite)
     }
 
     /// Loads a site slotmap from a JSON file
     pub fn load<P: Into<PathBuf>>(file: P) -> Option<SlotMap<SiteKey, SiteLog>> {
-        let mut file = File::open(file.into()).ok()?;
-        let mut buf = String::new();
+        let reader = BufReader::new(File::open(file.into()).ok()?);
 
-        file.read_to_string(&mut buf).ok()?;
-        serde_json::from_str(&buf).ok()
+        serde_json::from_reader(reader).ok()
     }
 
-    /// Crawls through the site queue, adding sites to the site pool and
-    pub async fn crawl(&mut self) -> Option<()> {
-        if let Some(url) = self.site_queue.recv().await {
-            self.parse_site(url).await
-        } else {
-            None
-        }
+    /// Loads a site slotmap saved in any [`Format`], telling them apart by the bytes they start
+    /// with. Formats left out of the build give an `Unsupported` error
+    pub fn load_auto<P: Into<PathBuf>>(file: P) -> io::Result<SlotMap<SiteKey, SiteLog>> {
+        decode(&fs::read(file.into())?)
     }
 
-    pub fn urls_and_title_within_site(text: &str, root_url: &str) -> Option<(String, Vec<String>)> {
-        let mut hrefs = HashSet::new();
-        let mut name = String::new();
+    /// Saves the site_pool as JSON over any earlier checkpoint. The pool is written to a temporary
+    /// file first, so an interrupted save never leaves a half written checkpoint behind
+    pub fn checkpoint<P: Into<PathBuf>>(&self, file: P) -> io::Result<()> {
+        self.write_atomically(file.into(), Format::Json, true)
+    }
 
-        let mut remaining = text.chars().rev().collect::<String>();
-        while !remaining.is_empty() {
-            if remaining.ends_with("=ferh") {
-                remaining.pop();
-                remaining.pop();
-                remaining.pop();
-                remaining.pop();
-                remaining.pop();
+    /// Writes the site_pool to a temporary file beside `file`, then moves it into place. Without
+    /// `overwrite`, fails with `AlreadyExists` if anything is at `file` by the time it's moved
+    fn write_atomically(&self, file: PathBuf, format: Format, overwrite: bool) -> io::Result<()> {
+        let mut partial = file.clone().into_os_string();
+        partial.push(".partial");
 
-                let open_quote = remaining.pop()?;
-                let mut url = String::new();
-                while let Some(character) = remaining.pop() {
-                    if character == open_quote {
-                        break;
-                    }
-                    url.push(character);
-                }
+        let mut writer = BufWriter::new(File::create(&partial)?);
+        encode(&self.site_pool, format, &mut writer)?;
+        writer
+            .into_inner()
+            .map_err(|err| err.into_error())?
+            .sync_all()?;
 
-                if !url.starts_with("http") {
-                    url = format!("{root_url}{url}");
-                }
+        if overwrite {
+            fs::rename(partial, &file)?;
+        } else {
+            // Linking never replaces an existing file, where renaming would, so checking for one
+            // first can't race with something else creating it
+            let linked = fs::hard_link(&partial, &file);
+            fs::remove_file(partial)?;
+            linked?;
+        }
+        self.emit(|| CrawlEvent::Checkpointed { path: file });
+        Ok(())
+    }
 
-                if let Some((normalized, _)) = url.split_once('?') {
-                    hrefs.insert(normalized.to_string());
-                } else if let Some((normalized, _)) = url.split_once('#') {
-                    hrefs.insert(normalized.to_string());
-                } else {
-                    hrefs.insert(url);
-                }
-            } else if remaining.ends_with(">eltit<") {
-                remaining.pop();
-                remaining.pop();
-                remaining.pop();
-                remaining.pop();
-                remaining.pop();
-                remaining.pop();
-                remaining.pop();
-
-                while let Some(character) = remaining.pop() {
-                    if remaining.ends_with("/<") {
-                        break;
-                    }
+    /// Sets up a crawler from a TOML or JSON config file, queuing its seeds. See [`CrawlerConfig`]
+    pub fn from_config_file<P: AsRef<Path>>(file: P) -> Result<Self, ConfigError> {
+        CrawlerConfig::load(file)?.build()
+    }
+
+    /// Picks a crawl back up from a saved site pool. Sites that were crawled, having a
+    /// `last_crawled` time, count as visited and the rest are queued again
+    pub fn resume<P: Into<PathBuf>>(file: P) -> Option<Self> {
+        let mut crawler = Self {
+            site_pool: Self::load(file)?,
+            ..Default::default()
+        };
 
-                    name.push(character);
+        for (key, site) in &crawler.site_pool {
+            crawler.urls.insert(site.url.clone(), key);
+            if site.last_crawled.is_some() {
+                crawler.pages_crawled += 1;
+                crawler.visited.insert(site.url.clone());
+                if let Some(host) = host_of(&site.url) {
+                    *crawler.host_pages.entry(host).or_default() += 1;
                 }
             } else {
-                remaining.pop();
+                crawler.site_queue.push_back(key);
             }
         }
 
-        Some((name.trim().to_string(), hrefs.into_iter().collect()))
+        Some(crawler)
     }
 
-    pub async fn parse_site(&mut self, url: SiteKey) -> Option<()> {
-        let site = &mut self.site_pool[url];
-        let response = reqwest::get(&site.url).await.ok()?;
-        self.visited.insert(site.url.clone());
+    /// Whether every queued site has been crawled
+    pub fn is_finished(&self) -> bool {
+        self.site_queue.is_empty() && self.frontier.is_empty()
+    }
 
-        let html = response.text().await.ok()?;
+    /// How many sites are waiting to be crawled
+    pub fn queue_len(&self) -> usize {
+        self.site_queue.len() + self.frontier.values().map(VecDeque::len).sum::<usize>()
+    }
 
-        let mut root_url = String::new();
-        let mut remaining = site.url.chars().rev().collect::<String>();
+    /// The site that's been waiting longest to be crawled, which is crawled next unless its host
+    /// is cooling down
+    pub fn peek_queue(&self) -> Option<SiteKey> {
+        self.frontier
+            .values()
+            .filter_map(VecDeque::front)
+            .min()
+            .map(|(_, key)| *key)
+            .or_else(|| self.site_queue.front().copied())
+    }
 
-        while !remaining.is_empty() {
-            if remaining.ends_with("//") {
-                remaining.pop();
-                remaining.pop();
+    /// Saves the site_pool like [`WebCrawler::save_stream`] over anything already saved there
+    pub fn save_ndjson<P: Into<PathBuf>>(&self, file: P) -> io::Result<()> {
+        self.write_atomically(file.into(), Format::Ndjson, true)
+    }
 
-                root_url.push('/');
-                root_url.push('/');
-            }
-            if let Some(character) = remaining.pop() {
-                if character == '/' {
-                    break;
-                }
-                root_url.push(character);
-            }
+    /// Loads a site slotmap like [`WebCrawler::load_stream`], logging a warning for each
+    /// malformed line instead of giving their line numbers back
+    pub fn load_ndjson<P: Into<PathBuf>>(file: P) -> io::Result<SlotMap<SiteKey, SiteLog>> {
+        let file = file.into();
+        let pool = Self::load_stream(&file)?;
+
+        for line_num in pool.bad_lines {
+            warn!(line = line_num, file = %file.display(), "skipping malformed line");
         }
 
-        let (title, hrefs) = WebCrawler::urls_and_title_within_site(&html, &root_url)?;
+        Ok(pool.sites)
+    }
 
-        let hrefs: Vec<_> = hrefs
-            .into_iter()
-            .filter_map(|href| {
-                if href.starts_with("http")
-                    && !self.visited.contains(&href)
-                    && self
-                        .site_pool
-                        .iter()
-                        .filter(|(_, log)| log.url == href)
-                        .count()
-                        == 0
-                {
-                    Some(self.enqueue(href))
-                } else {
-                    None
-                }
-            })
-            .collect();
+    /// Streams a site slotmap from a newline delimited JSON file line by line, so memory use is
+    /// bounded by the pool rather than the file. Malformed lines, such as the last line of a file
+    /// that was cut short, are skipped and their line numbers reported
+    pub fn load_stream<P: Into<PathBuf>>(file: P) -> io::Result<StreamedPool> {
+        read_ndjson(BufReader::new(File::open(file.into())?))
+    }
 
-        hrefs.iter().for_each(|key| {
-            let _ = self.site_queue_sender.send(*key);
-        });
-        self.site_pool[url].connections.extend(hrefs);
+    /// Writes every link between sites in the pool as CSV, one `source_url,target_url` row each
+    pub fn export_edges<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut writer = BufWriter::new(writer);
+        writeln!(writer, "source_url,target_url")?;
 
-        // Add self connection
-        self.site_pool[url].connections.push(url);
-        self.site_pool[url].title = title;
+        for (key, site) in &self.site_pool {
+            for target in self.exported_links(key) {
+                let target = &self.site_pool[target];
+                writeln!(
+                    writer,
+                    "{},{}",
+                    csv_field(&site.url),
+                    csv_field(&target.url)
+                )?;
+            }
+        }
 
-        Some(())
+        writer.flush()
     }
-}
+
+    /// Writes every site in the pool as CSV, one `url,title,out_degree` row each, where the out
+    /// degree counts the site's rows in [`WebCrawler::export_edges`]
+    pub fn export_nodes<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut writer = BufWriter::new(writer);
+        writeln!(writer, "url,title,out_degree")?;
+
+        for (key, site) in &self.site_pool {
+            writeln!(
+                writer,
+                "{},{},{}",
+                csv_field(&site.url),
+                csv_field(&site.title),
+                self.exported_links(key).count()
+            )?;
+        }
+
+        writer.flush()
+    }
+
+    /// Sites a site links to that are written out as edges, leaving out its link to itself and
+    /// links to sites no longer in the pool
+    fn exported_links(&self, key: SiteKey) -> impl Iterator<Item = SiteKey> + '_ {
+        self.site_pool[key]
+            .connections
+            .iter()
+            .copied()
+            .filter(move |target| *target != key && self.site_pool.contains_key(*target))
+    }
+
+    /// Writes the pool as a directed GraphML graph, with each site's URL and title as node data,
+    /// for graph tools such as Gephi
+    pub fn export_graphml<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut writer = BufWriter::new(writer);
+        let ids: HashMap<SiteKey, usize> = self
+            .site_pool
+            .keys()
+            .enumerate()
+            .map(|(id, key)| (key, id))
+            .collect();
+
+        writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            writer,
+            r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+        )?;
+        writeln!(
+            writer,
+            r#"  <key id="url" for="node" attr.name="url" attr.type="string"/>"#
+        )?;
+        writeln!(
+            writer,
+            r#"  <key id="title" for="node" attr.name="title" attr.type="string"/>"#
+        )?;
+        writeln!(writer, r#"  <graph id="crawl" edgedefault="directed">"#)?;
+
+        for (key, site) in &self.site_pool {
+            writeln!(
+                writer,
+                r#"    <node id="n{}"><data key="url">{}</data><data key="title">{}</data></node>"#,
+                ids[&key],
+                xml_escape(&site.url),
+                xml_escape(&site.title)
+            )?;
+        }
+        for key in self.site_pool.keys() {
+            for target in self.exported_links(key) {
+                writeln!(
+                    writer,
+                    r#"    <edge source="n{}" target="n{}"/>"#,
+                    ids[&key], ids[&target]
+                )?;
+            }
+        }
+
+        writeln!(writer, "  </graph>")?;
+        writeln!(writer, "</graphml>")?;
+        writer.flush()
+    }
+
+    /// Crawls through the site queue, adding sites to the site pool and
+    pub async fn crawl(&mut self) -> Option<()> {
+        self.crawl_next().await.map(|_| ())
+    }
+
+    /// Crawls the next site in the queue like [`WebCrawler::crawl`], telling whether it was
+    /// fetched or skipped
+    async fn crawl_next(&mut self) -> Option<Visit> {
+        let url = self.take_site()?;
+        let visit = self.visit(url).await?;
+        if let Visit::Crawled(url) = visit {
+            self.crawled(url);
+        }
+
+        Some(visit)
+    }
+
+    /// Crawls one page after another until a limit is reached. Crawling always stops once the
+    /// queue runs dry, since there's nothing left to crawl
+    pub async fn crawl_until(&mut self, stop: CrawlLimit) -> CrawlSummary {
+        let started = Instant::now();
+        let failures = self.failures.len();
+        let mut pages = 0;
+
+        loop {
+            let queue_empty = self.is_finished();
+            if queue_empty || stop.reached(pages, started.elapsed(), queue_empty) {
+                break;
+            }
+
+            // Sites skipped without being fetched don't count towards the limit
+            if let Some(Visit::Crawled(_)) = self.crawl_next().await {
+                pages += 1;
+            }
+        }
+
+        CrawlSummary {
+            pages,
+            failures: self.failures.len() - failures,
+            elapsed: started.elapsed(),
+        }
+    }
+
+    /// Crawls up to `pages` sites with several fetches in flight at once, as many as the policy's
+    /// `max_concurrent` across every origin and `max_per_host` to any one origin. Stops early once
+    /// the queue runs dry, returning how many sites were crawled. Sites skipped without being
+    /// fetched don't count
+    pub async fn crawl_concurrently(&mut self, pages: usize) -> usize {
+        let mut in_flight = JoinSet::new();
+        let mut started = 0;
+        let mut crawled = 0;
+
+        loop {
+            while started < pages && in_flight.len() < self.policy.max_concurrent.max(1) {
+                let Some(url) = self.take_site() else {
+                    break;
+                };
+                let Some(request) = self.request_for(url).await else {
+                    continue;
+                };
+                started += 1;
+
+                let permit = self.host_permit(&self.site_pool[url].url.clone());
+                let policy = self.policy;
+                in_flight.spawn(async move {
+                    let fetched = fetch(request, policy).await;
+                    drop(permit);
+                    (url, fetched)
+                });
+            }
+
+            match in_flight.join_next().await {
+                Some(Ok((url, fetched))) => {
+                    if let Some(url) = self.record(url, fetched) {
+                        crawled += 1;
+                        self.crawled(url);
+                    }
+                }
+                Some(Err(_)) => {}
+                None => break,
+            }
+        }
+
+        crawled
+    }
+
+    /// Counts a fetched site and reports it to the progress callback, checkpointing if it's time
+    /// to. Sites skipped without being fetched aren't reported
+    fn crawled(&mut self, url: SiteKey) {
+        self.pages_crawled += 1;
+        let crawled = self.pages_crawled;
+        let queued = self.queue_len();
+        if let Some(progress) = self.progress.as_mut() {
+            progress(CrawlProgress {
+                url: self.site_pool[url].url.clone(),
+                crawled,
+                queued,
+            });
+        }
+
+        if let Some(autosave) = self
+            .autosave
+            .as_ref()
+            .filter(|autosave| autosave.every > 0 && crawled.is_multiple_of(autosave.every))
+        {
+            if let Err(err) = self.checkpoint(&autosave.path) {
+                warn!(path = %autosave.path.display(), error = %err, "checkpoint failed");
+            }
+        }
+    }
+
+    /// Takes the longest waiting site whose host isn't cooling down, so one busy host doesn't hold
+    /// up the rest of the crawl. When every host is cooling down, takes the site on the host
+    /// ready soonest. Origins with as many fetches in flight as they're allowed are passed over
+    fn take_site(&mut self) -> Option<SiteKey> {
+        while let Some(key) = self.site_queue.pop_front() {
+            self.hold(key);
+        }
+
+        let now = Instant::now();
+        let (_, _, origin) = self
+            .frontier
+            .iter()
+            .filter(|(origin, _)| {
+                self.host_slots
+                    .get(*origin)
+                    .is_none_or(|slots| slots.available_permits() > 0)
+            })
+            .filter_map(|(origin, sites)| {
+                let (taken, _) = sites.front()?;
+                let ready = self.ready_at(origin).map_or(now, |ready| ready.max(now));
+                Some((ready, *taken, origin))
+            })
+            .min()?;
+        let origin = origin.clone();
+
+        let sites = self.frontier.get_mut(&origin)?;
+        let (_, key) = sites.pop_front()?;
+        if sites.is_empty() {
+            self.frontier.remove(&origin);
+        }
+
+        Some(key)
+    }
+
+    /// Sets a site taken off the queue aside under its origin until it's crawled
+    fn hold(&mut self, key: SiteKey) {
+        let url = self
+            .site_pool
+            .get(key)
+            .map(|site| site.url.clone())
+            .unwrap_or_default();
+        // Sites queued before their host reached its limit are dropped from the queue
+        if self.host_full(&url) {
+            debug!(%url, "dropped, host reached its page limit");
+            self.emit(|| CrawlEvent::Skipped {
+                url,
+                reason: SkipReason::HostLimit,
+            });
+            return;
+        }
+
+        let origin = origin_of(&url).unwrap_or_default();
+        self.frontier
+            .entry(origin)
+            .or_default()
+            .push_back((self.taken, key));
+        self.taken += 1;
+    }
+
+    /// Whether a URL's host has already had as many pages crawled as `max_pages_per_domain` allows
+    fn host_full(&self, url: &str) -> bool {
+        self.policy.max_pages_per_domain.is_some_and(|max| {
+            host_of(url).is_some_and(|host| {
+                self.host_pages
+                    .get(&host)
+                    .is_some_and(|pages| *pages >= max)
+            })
+        })
+    }
+
+    /// How many pages of a URL's host count against `max_pages_per_domain`, or `None` if the URL
+    /// has no host
+    fn host_count(&mut self, url: &str) -> Option<&mut usize> {
+        Some(self.host_pages.entry(host_of(url)?).or_default())
+    }
+
+    /// When an origin may next be fetched from, or `None` if it hasn't been fetched from yet
+    fn ready_at(&self, origin: &str) -> Option<Instant> {
+        let delay = self
+            .robots
+            .get(origin)
+            .filter(|_| self.respect_robots)
+            .and_then(|robots| robots.crawl_delay)
+            .map_or(self.policy.per_host_delay, |crawl_delay| {
+                crawl_delay.max(self.policy.per_host_delay)
+            });
+
+        self.last_fetch.get(origin).map(|last| *last + delay)
+    }
+
+    /// Claims one of the fetches a URL's origin may have in flight, if any are free
+    fn host_permit(&mut self, url: &str) -> Option<OwnedSemaphorePermit> {
+        let max_per_host = self.policy.max_per_host.max(1);
+        let slots = self
+            .host_slots
+            .entry(origin_of(url).unwrap_or_default())
+            .or_insert_with(|| Arc::new(Semaphore::new(max_per_host)));
+
+        Arc::clone(slots).try_acquire_owned().ok()
+    }
+
+    /// Waits until an origin has cooled down and the global rate limit allows another fetch, then
+    /// records the fetch
+    async fn wait_turn(&mut self, url: &str) {
+        let origin = origin_of(url).unwrap_or_default();
+
+        if let Some(ready) = self.ready_at(&origin) {
+            sleep_until(ready).await;
+        }
+        self.rate_limiter
+            .acquire(self.policy.max_requests_per_second)
+            .await;
+        self.last_fetch.insert(origin, Instant::now());
+    }
+
+    /// A GET request carrying the crawler's `User-Agent` and other headers
+    fn get(&self, url: &str) -> RequestBuilder {
+        let mut request = self.client.get(url).header(USER_AGENT, &self.user_agent);
+        if let Some(language) = &self.accept_language {
+            request = request.header(ACCEPT_LANGUAGE, language);
+        }
+
+        request.headers(self.headers.clone())
+    }
+
+    /// Checks a URL against its origin's robots.txt, fetching and caching the file on first use.
+    /// Origins without a robots.txt, or whose robots.txt can't be fetched, allow everything
+    async fn robots_allow(&mut self, url: &str) -> bool {
+        let Ok(parsed) = Url::parse(url) else {
+            return true;
+        };
+        let origin = parsed.origin().ascii_serialization();
+
+        if !self.robots.contains_key(&origin) {
+            let request = self
+                .get(&format!("{origin}/robots.txt"))
+                .timeout(self.policy.timeout);
+            let robots = match request.send().await {
+                Ok(response) if response.status().is_success() => {
+                    response.text().await.map_or_else(
+                        |_| Robots::allow_all(),
+                        |text| Robots::parse(&text, &self.user_agent),
+                    )
+                }
+                _ => Robots::allow_all(),
+            };
+            self.robots.insert(origin.clone(), robots);
+        }
+
+        self.robots[&origin].is_allowed(&parsed[Position::BeforePath..])
+    }
+
+    /// Finds a page's title and every link in it from an `<a href>`
+    pub fn urls_and_title_within_site(text: &str, page_url: &str) -> Option<(String, Vec<String>)> {
+        Self::links_and_title_within_site(text, page_url, &[LinkSource::default()])
+    }
+
+    /// Finds a page's title and every followable link in it from one of the given element
+    /// attributes. Links are resolved against the page's URL and normalized
+    pub fn links_and_title_within_site(
+        text: &str,
+        page_url: &str,
+        sources: &[LinkSource],
+    ) -> Option<(String, Vec<String>)> {
+        let page = ParsedPage::parse(text, page_url, sources);
+        let links = page
+            .links
+            .into_iter()
+            .filter(|link| !link.is_nofollow())
+            .map(|link| link.url)
+            .collect();
+        Some((page.title, links))
+    }
+
+    pub async fn parse_site(&mut self, url: SiteKey) -> Option<()> {
+        self.visit(url).await.map(|_| ())
+    }
+
+    /// Fetches and records a site, giving the key it ends up under, which differs when it
+    /// redirected to a site already in the pool
+    async fn visit(&mut self, url: SiteKey) -> Option<Visit> {
+        let Some(request) = self.request_for(url).await else {
+            return Some(Visit::Skipped);
+        };
+        let fetched = fetch(request, self.policy).await;
+
+        self.record(url, fetched).map(Visit::Crawled)
+    }
+
+    /// Gets a site ready to fetch, waiting for its turn. Sites that aren't fetched, because of a
+    /// dry run or robots.txt, are marked visited and give `None`
+    async fn request_for(&mut self, url: SiteKey) -> Option<RequestBuilder> {
+        if self.dry_run {
+            let site = &self.site_pool[url];
+            self.visited.insert(site.url.clone());
+            self.planned.push(site.url.clone());
+            return None;
+        }
+
+        let site_url = self.site_pool[url].url.clone();
+        // Sites held before their host filled up, while other pages on it were still in flight
+        if self.host_full(&site_url) {
+            debug!(url = %site_url, "skipped, host reached its page limit");
+            self.emit(|| CrawlEvent::Skipped {
+                url: site_url,
+                reason: SkipReason::HostLimit,
+            });
+            return None;
+        }
+        if self.respect_robots && !self.robots_allow(&site_url).await {
+            debug!(url = %site_url, "skipped, disallowed by robots.txt");
+            self.emit(|| CrawlEvent::Skipped {
+                url: site_url.clone(),
+                reason: SkipReason::Robots,
+            });
+            self.visited.insert(site_url.clone());
+            self.skipped.push(site_url);
+            return None;
+        }
+        self.wait_turn(&site_url).await;
+        // Counted from the request on, so fetches in flight together can't take a host past its
+        // limit. `record` takes it back off once the response is in
+        if let Some(count) = self.host_count(&site_url) {
+            *count += 1;
+        }
+
+        let site = &self.site_pool[url];
+        // Recrawls ask the server to skip the page if it hasn't changed since it was last fetched
+        let mut request = self.get(&site.url);
+        if let Some(etag) = &site.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &site.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+
+        Some(request)
+    }
+
+    /// Stores what was fetched for a site and queues the links found in it, returning the key the
+    /// site ends up under. A site that redirected takes on the URL it landed on, or is merged into
+    /// the site already in the pool with that URL. Sites that couldn't be fetched are marked
+    /// visited and logged as failures
+    fn record(&mut self, url: SiteKey, fetched: Result<Fetched, FetchError>) -> Option<SiteKey> {
+        // The request stops counting against its host, and the page counts against the host it
+        // landed on once it's stored
+        let site_url = self.site_pool[url].url.clone();
+        if let Some(count) = self.host_count(&site_url) {
+            *count = count.saturating_sub(1);
+        }
+
+        let fetched = match fetched {
+            Ok(fetched) => fetched,
+            Err(FetchError { error, attempts }) => {
+                warn!(url = %site_url, attempts, %error, "fetch failed");
+                self.emit(|| CrawlEvent::Failed {
+                    url: site_url.clone(),
+                    error: error.clone(),
+                });
+                self.visited.insert(site_url.clone());
+                self.failures.push(CrawlFailure {
+                    url: site_url,
+                    error,
+                    attempts,
+                });
+                return Some(url);
+            }
+        };
+        let elapsed = fetched.elapsed;
+        let (key, stored) = self.store(url, fetched)?;
+        self.emit(|| match stored {
+            Stored::Page { links_found } => CrawlEvent::Fetched {
+                key,
+                url: self.site_pool[key].url.clone(),
+                links_found,
+                elapsed,
+            },
+            Stored::Failing(error) => CrawlEvent::Failed {
+                url: self.site_pool[key].url.clone(),
+                error,
+            },
+            Stored::Merged(url) => CrawlEvent::Skipped {
+                url,
+                reason: SkipReason::Duplicate,
+            },
+        });
+
+        Some(key)
+    }
+
+    /// Stores a page's response under a site, returning the key it ends up under and what became
+    /// of the response
+    fn store(&mut self, url: SiteKey, fetched: Fetched) -> Option<(SiteKey, Stored)> {
+        let landed =
+            normalize_url(&fetched.url, &fetched.url).unwrap_or_else(|| fetched.url.clone());
+        let original = &self.site_pool[url].url;
+
+        if landed != *original {
+            self.visited.insert(original.clone());
+            self.aliases.insert(original.clone(), landed.clone());
+
+            let existing = self.site_for(&landed).filter(|existing| *existing != url);
+            if let Some(existing) = existing {
+                let original = original.clone();
+                self.merge(url, existing);
+                return Some((existing, Stored::Merged(original)));
+            }
+            self.urls.insert(landed.clone(), url);
+            self.site_pool[url].url = landed;
+        }
+
+        let site = &mut self.site_pool[url];
+        info!(url = %site.url, status = fetched.status, "fetched");
+        self.visited.insert(site.url.clone());
+        // Recrawled pages already count against their host
+        if site.last_crawled.replace(unix_now()).is_none() {
+            let site_url = site.url.clone();
+            if let Some(count) = self.host_count(&site_url) {
+                *count += 1;
+            }
+        }
+
+        let site = &mut self.site_pool[url];
+        if fetched.status == StatusCode::NOT_MODIFIED.as_u16() {
+            return Some((url, Stored::Page { links_found: 0 }));
+        }
+
+        site.status = Some(fetched.status);
+        site.content_type = fetched.content_type.clone();
+        site.etag = fetched.etag;
+        site.last_modified = fetched.last_modified;
+
+        // Still failing once out of retries, though the response is kept like any other error page
+        let mut failing = None;
+        if is_transient(fetched.status) {
+            let error = StatusCode::from_u16(fetched.status)
+                .map(|status| status.to_string())
+                .unwrap_or_else(|_| fetched.status.to_string());
+            warn!(url = %site.url, attempts = fetched.attempts, %error, "still failing after retries");
+            failing = Some(error.clone());
+            self.failures.push(CrawlFailure {
+                url: site.url.clone(),
+                error,
+                attempts: fetched.attempts,
+            });
+        }
+
+        // Error pages and files stay in the pool to be linked to, but don't link anywhere
+        if !is_html_page(fetched.status, fetched.content_type.as_deref()) {
+            site.connections.clear();
+            return Some((
+                url,
+                failing.map_or(Stored::Page { links_found: 0 }, Stored::Failing),
+            ));
+        }
+
+        let bytes = fetched.body?;
+        let html = decode_html(&bytes, fetched.content_type.as_deref());
+        site.content_hash = Some(content_hash(&html));
+
+        // Relative links resolve against the URL as served, since normalizing drops the trailing
+        // slash that makes `/docs/` a directory
+        let page = ParsedPage::parse(&html, &fetched.url, &self.link_sources);
+        let host = host_of(&site.url);
+
+        let links_found = page.links.len();
+        let (followed, nofollow): (Vec<_>, Vec<_>) =
+            page.links.into_iter().partition(|link| !link.is_nofollow());
+        for link in &nofollow {
+            debug!(url = %link.url, "link skipped, marked nofollow");
+        }
+        // Links to sites already in the pool keep their key, and only new ones are queued
+        let targets: Vec<Result<SiteKey, String>> = followed
+            .into_iter()
+            .map(|link| link.url)
+            .filter_map(|href| {
+                let skip = if !href.starts_with("http") {
+                    Some("not HTTP")
+                } else if self
+                    .path_prefix
+                    .as_deref()
+                    .is_some_and(|prefix| !within_prefix(&href, host.as_deref(), prefix))
+                {
+                    Some("outside the path prefix")
+                } else if let Some(key) = self.site_for(&href) {
+                    return Some(Ok(key));
+                } else if self.visited.contains(&href) {
+                    Some("already visited")
+                } else {
+                    None
+                };
+                if let Some(reason) = skip {
+                    debug!(url = %href, reason, "link skipped");
+                    return None;
+                }
+                Some(Err(href))
+            })
+            .collect();
+
+        let mut new: Vec<_> = targets
+            .iter()
+            .filter_map(|target| target.clone().err())
+            .collect();
+        if let Some(sample) = self.link_sample.filter(|sample| new.len() > *sample) {
+            debug!(found = new.len(), kept = sample, "links sampled");
+            new.shuffle(&mut self.rng);
+            new.truncate(sample);
+        }
+        let new: HashSet<_> = new.into_iter().collect();
+
+        let mut seen = HashSet::from([url]);
+        let mut connections = vec![];
+        for target in targets {
+            let key = match target {
+                Ok(key) => key,
+                Err(href) if new.contains(&href) => {
+                    if self.host_full(&href) {
+                        debug!(url = %href, "link not queued, host reached its page limit");
+                        self.add_site(href)
+                    } else {
+                        self.enqueue(href)
+                    }
+                }
+                Err(_) => continue,
+            };
+            if seen.insert(key) {
+                connections.push(key);
+            }
+        }
+
+        // Add self connection
+        connections.push(url);
+        self.site_pool[url].connections = connections;
+        let site = &mut self.site_pool[url];
+        site.title = page.title;
+        site.description = page.description;
+        site.og_title = page.og_title;
+        if self.record_nofollow {
+            site.nofollow = nofollow.into_iter().map(|link| link.url).collect();
+        }
+        if self.store_body_text {
+            let mut text = page.text;
+            text.truncate(text.floor_char_boundary(self.body_text_limit));
+            site.text = Some(text);
+        }
+
+        let stored = failing.map_or(Stored::Page { links_found }, Stored::Failing);
+        Some((url, stored))
+    }
+
+    /// Folds a site into another that turned out to be the same page, pointing every link to it
+    /// at the other instead
+    fn merge(&mut self, from: SiteKey, into: SiteKey) {
+        self.site_pool.remove(from);
+
+        for site in self.site_pool.values_mut() {
+            if site.connections.contains(&from) {
+                let mut seen = HashSet::new();
+                site.connections = site
+                    .connections
+                    .iter()
+                    .map(|key| if *key == from { into } else { *key })
+                    .filter(|key| seen.insert(*key))
+                    .collect();
+            }
+        }
+    }
+}
+
+/// Extracts the lowercased host from a URL, assuming `http` when the scheme is missing. Ports and
+/// credentials are dropped, and `None` is returned for anything that can't be parsed
+pub fn host_of(url: &str) -> Option<String> {
+    let parsed = if url.contains("://") {
+        Url::parse(url).ok()?
+    } else {
+        Url::parse(&format!("http://{url}")).ok()?
+    };
+
+    parsed.host_str().map(str::to_lowercase)
+}
+
+/// Resolves a URL against a base URL and puts it in the form every equivalent URL shares. The
+/// scheme and host are lowercased, default ports and `.` and `..` segments are dropped along with
+/// the query and fragment, and the path loses any trailing slash. Anything but an `http` or
+/// `https` URL gives `None`
+pub fn normalize_url(raw: &str, base: &str) -> Option<String> {
+    let raw = raw.trim();
+    let mut url = match Url::parse(base) {
+        Ok(base) => base.join(raw).ok()?,
+        Err(_) => Url::parse(raw).ok()?,
+    };
+    if !matches!(url.scheme(), "http" | "https") {
+        return None;
+    }
+
+    url.set_query(None);
+    url.set_fragment(None);
+    if url.path().len() > 1 && url.path().ends_with('/') {
+        let path = url.path().trim_end_matches('/').to_string();
+        url.set_path(&path);
+    }
+
+    Some(url.into())
+}
+
+/// Whether a URL is on a host and its path starts with a prefix
+fn within_prefix(url: &str, host: Option<&str>, prefix: &str) -> bool {
+    Url::parse(url)
+        .is_ok_and(|parsed| parsed.host_str() == host && parsed.path().starts_with(prefix))
+}
+
+/// The scheme, host and port of a URL, which robots.txt rules and politeness delays apply to
+fn origin_of(url: &str) -> Option<String> {
+    Url::parse(url)
+        .ok()
+        .map(|parsed| parsed.origin().ascii_serialization())
+}
+
+/// A stable 64-bit FNV-1a hash of a page's content, ignoring differences in whitespace so pages
+/// only reformatted still collide
+pub fn content_hash(html: &str) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    let mut hash = OFFSET_BASIS;
+    for (idx, word) in html.split_whitespace().enumerate() {
+        if idx > 0 {
+            hash = (hash ^ u64::from(b' ')).wrapping_mul(PRIME);
+        }
+        for byte in word.bytes() {
+            hash = (hash ^ u64::from(byte)).wrapping_mul(PRIME);
+        }
+    }
+
+    hash
+}
+
+/// Seconds since the Unix epoch
+pub(crate) fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+/// How many bytes into a document to look for a `<meta>` charset declaration
+const CHARSET_SNIFF_LEN: usize = 1024;
+
+/// Decodes a fetched page using the charset from its `Content-Type` header, falling back to a
+/// `<meta>` charset declaration near the start of the document and then UTF-8. A byte order mark
+/// always takes precedence
+pub fn decode_html(bytes: &[u8], content_type: Option<&str>) -> String {
+    let encoding = content_type
+        .and_then(charset_label)
+        .or_else(|| {
+            let head = &bytes[..bytes.len().min(CHARSET_SNIFF_LEN)];
+            charset_label(&String::from_utf8_lossy(head))
+        })
+        .and_then(|label| Encoding::for_label(label.as_bytes()))
+        .unwrap_or(UTF_8);
+
+    let (html, _, _) = encoding.decode(bytes);
+    html.into_owned()
+}
+
+/// Finds the value of the first `charset=` parameter in a header or document
+fn charset_label(text: &str) -> Option<String> {
+    let lowered = text.to_ascii_lowercase();
+    let start = lowered.find("charset=")? + "charset=".len();
+
+    let label: String = lowered[start..]
+        .trim_start_matches(['"', '\''])
+        .chars()
+        .take_while(|c| !matches!(c, '"' | '\'' | ';' | '>' | '/') && !c.is_whitespace())
+        .collect();
+
+    (!label.is_empty()).then_some(label)
+}
+
+/// A site as written to a line of newline delimited JSON, tagged with its key so links between
+/// sites survive the round trip
+#[derive(Serialize)]
+struct SiteRecord<'a> {
+    key: SiteKey,
+    #[serde(flatten)]
+    site: &'a SiteLog,
+}
+
+/// A site as read back from a line of newline delimited JSON
+#[derive(Deserialize)]
+struct OwnedSiteRecord {
+    key: SiteKey,
+    #[serde(flatten)]
+    site: SiteLog,
+}
+
+/// A CSV field, quoted when it holds a comma, quote or line break, with quotes inside doubled
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Text with the characters XML reserves escaped, so it can sit in element content or attributes
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            ch => escaped.push(ch),
+        }
+    }
+
+    escaped
+}
 
 /// Tracked information about a site
 #[derive(Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
@@ -198,11 +1955,112 @@ pub struct SiteLog {
     pub url: String,
     pub title: String,
     pub connections: Vec<SiteKey>,
+    /// Text content of the page, if it was kept while crawling
+    #[serde(default)]
+    pub text: Option<String>,
+    /// Meta description of the page, if it had one
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Open Graph title of the page, if it had one
+    #[serde(default)]
+    pub og_title: Option<String>,
+    /// When the site was last fetched, in seconds since the Unix epoch
+    #[serde(default)]
+    pub last_crawled: Option<u64>,
+    /// HTTP status of the last fetch
+    #[serde(default)]
+    pub status: Option<u16>,
+    /// `Content-Type` header from the last fetch
+    #[serde(default)]
+    pub content_type: Option<String>,
+    /// Links the page marked `rel="nofollow"`, if they were kept while crawling
+    #[serde(default)]
+    pub nofollow: Vec<String>,
+    /// `ETag` header from the last fetch, sent back when recrawling
+    #[serde(default)]
+    pub etag: Option<String>,
+    /// `Last-Modified` header from the last fetch, sent back when recrawling
+    #[serde(default)]
+    pub last_modified: Option<String>,
+    /// Hash of the page's content when it was last fetched, shared by pages with identical content
+    #[serde(default)]
+    pub content_hash: Option<u64>,
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::crawler::WebCrawler;
+    use std::{
+        collections::{BTreeMap, BTreeSet},
+        io::Write,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        time::{Duration, Instant},
+    };
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        sync::mpsc,
+    };
+
+    use crate::crawler::{
+        content_hash, decode_html, host_of, normalize_url, unix_now, Autosave, CrawlEvent,
+        CrawlFailure, CrawlLimit, CrawlPolicy, Format, LinkSource, ParsedPage, SaveError,
+        SkipReason, WebCrawler, DEFAULT_USER_AGENT,
+    };
+
+    /// Builds a raw HTTP response
+    fn http_response(status: &str, headers: &[(&str, &str)], body: &[u8]) -> Vec<u8> {
+        let mut response = format!("HTTP/1.1 {status}\r\n");
+        for (name, value) in headers {
+            response.push_str(&format!("{name}: {value}\r\n"));
+        }
+        response.push_str(&format!(
+            "Content-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len()
+        ));
+
+        let mut response = response.into_bytes();
+        response.extend_from_slice(body);
+        response
+    }
+
+    /// Serves HTTP on a local port, building each response from the head of its request, and
+    /// returns the URL to fetch from
+    async fn serve<F>(respond: F) -> String
+    where
+        F: Fn(&str) -> Vec<u8> + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Bind listener");
+        let addr = listener.local_addr().expect("Local address");
+
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = vec![];
+                let mut buf = [0u8; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    match stream.read(&mut buf).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(read) => request.extend_from_slice(&buf[..read]),
+                    }
+                }
+
+                let response = respond(&String::from_utf8_lossy(&request));
+                let _ = stream.write_all(&response).await;
+            }
+        });
+
+        format!("http://{addr}/")
+    }
+
+    /// Serves the same page to every request
+    async fn serve_once(content_type: &'static str, body: Vec<u8>) -> String {
+        serve(move |_| http_response("200 OK", &[("Content-Type", content_type)], &body)).await
+    }
 
     #[test]
     fn url_dupes_spotted() {
@@ -217,4 +2075,2139 @@ mod tests {
             Some(("".to_string(), vec!["https://example.com/path".to_string()]))
         );
     }
+
+    #[test]
+    fn meta_description_preferred_over_open_graph() {
+        let page = ParsedPage::parse(
+            r#"<head><title>Rust</title>
+            <meta property="og:title" content="Rust Programming Language">
+            <meta property="og:description" content="Build it in Rust">
+            <meta NAME="Description" content=" A language empowering everyone ">
+            </head>"#,
+            "https://example.com",
+            &[LinkSource::default()],
+        );
+
+        assert_eq!(page.title, "Rust");
+        assert_eq!(
+            page.description.as_deref(),
+            Some("A language empowering everyone")
+        );
+        assert_eq!(page.og_title.as_deref(), Some("Rust Programming Language"));
+    }
+
+    #[test]
+    fn open_graph_fills_in_missing_tags() {
+        let page = ParsedPage::parse(
+            r#"<head><title> </title>
+            <meta property="og:title" content="Rust Programming Language">
+            <meta property="og:description" content="Build it in Rust">
+            <meta name="description" content="">
+            </head>"#,
+            "https://example.com",
+            &[LinkSource::default()],
+        );
+
+        assert_eq!(page.title, "Rust Programming Language");
+        assert_eq!(page.description.as_deref(), Some("Build it in Rust"));
+
+        let page = ParsedPage::parse(
+            "<title>Bare</title>",
+            "https://example.com",
+            &[LinkSource::default()],
+        );
+        assert_eq!(page.title, "Bare");
+        assert_eq!(page.description, None);
+        assert_eq!(page.og_title, None);
+    }
+
+    #[test]
+    fn registry_without_metadata_loads() {
+        let mut file = tempfile::NamedTempFile::new().expect("Create registry file");
+        write!(
+            file,
+            r#"[{{"value":null,"version":0}},{{"value":{{"url":"https://a.com","title":"A","connections":[]}},"version":1}}]"#
+        )
+        .expect("Write registry");
+
+        let sites = WebCrawler::load(file.path()).expect("Load registry");
+        let site = sites.values().next().expect("Loaded site");
+        assert_eq!(site.title, "A");
+        assert_eq!(site.description, None);
+        assert_eq!(site.og_title, None);
+        assert_eq!(site.text, None);
+    }
+
+    #[test]
+    fn hidden_elements_left_out_of_text() {
+        let page = ParsedPage::parse(
+            r#"<html><head><title>Rust</title><style>body { color: red }</style></head>
+            <body>
+              <nav><a href="/">Home</a> | <a href="/docs">Docs</a></nav>
+              <script>var tracking = "never shown";</script>
+              <h1>Rust</h1>
+              <p>A language   empowering <b>every</b>one
+                 to build software.</p>
+              <noscript>Enable JavaScript</noscript>
+            </body></html>"#,
+            "https://example.com",
+            &[LinkSource::default()],
+        );
+
+        assert_eq!(
+            page.text,
+            "Rust A language empowering everyone to build software."
+        );
+    }
+
+    #[tokio::test]
+    async fn body_text_stored_only_when_asked() {
+        let url = serve_once(
+            "text/html; charset=utf-8",
+            "<title>Café</title><p>Crème brûlée</p>".as_bytes().to_vec(),
+        )
+        .await;
+
+        let mut crawler = WebCrawler {
+            respect_robots: false,
+            ..Default::default()
+        };
+        let site = crawler.enqueue(url.clone());
+        crawler.crawl().await.expect("Crawl page");
+        assert_eq!(crawler.site_pool[site].text, None);
+
+        // The cap lands partway through `è`, so the text stops before it
+        let mut crawler = WebCrawler {
+            respect_robots: false,
+            store_body_text: true,
+            body_text_limit: 3,
+            ..Default::default()
+        };
+        let site = crawler.enqueue(url);
+        crawler.crawl().await.expect("Crawl page");
+        assert_eq!(crawler.site_pool[site].text.as_deref(), Some("Cr"));
+    }
+
+    #[test]
+    fn urls_normalized() {
+        let base = "https://example.com/wiki/Rust";
+        let normalized = |raw| normalize_url(raw, base);
+
+        assert_eq!(
+            normalized("HTTP://Example.COM/Page").as_deref(),
+            Some("http://example.com/Page")
+        );
+        assert_eq!(
+            normalized("https://example.com:443/a").as_deref(),
+            Some("https://example.com/a")
+        );
+        assert_eq!(
+            normalized("http://example.com:8080/a").as_deref(),
+            Some("http://example.com:8080/a")
+        );
+        assert_eq!(
+            normalized("/docs/./guide/../intro").as_deref(),
+            Some("https://example.com/docs/intro")
+        );
+        assert_eq!(
+            normalized("page?x=1#frag").as_deref(),
+            Some("https://example.com/wiki/page")
+        );
+        assert_eq!(
+            normalized("#section").as_deref(),
+            Some("https://example.com/wiki/Rust")
+        );
+        assert_eq!(
+            normalized("https://example.com/docs/").as_deref(),
+            Some("https://example.com/docs")
+        );
+        for root in [
+            "http://Example.com",
+            "http://example.com/",
+            "http://example.com:80",
+        ] {
+            assert_eq!(normalized(root).as_deref(), Some("http://example.com/"));
+        }
+        assert_eq!(normalized("mailto:someone@example.com"), None);
+        assert_eq!(normalized("javascript:void(0)"), None);
+    }
+
+    #[test]
+    fn relative_links_resolved_against_page() {
+        let links = |page_url| {
+            ParsedPage::parse(
+                r#"<a href="subpage.html">Sub</a>
+                <a href="./sibling">Sibling</a>
+                <a href="../up">Up</a>
+                <a href="/root">Root</a>
+                <a href="//cdn.example.com/lib.js">CDN</a>
+                <a href="https://rust-lang.org/learn">Absolute</a>"#,
+                page_url,
+                &[LinkSource::default()],
+            )
+            .urls()
+            .into_iter()
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            links("https://example.com/docs/guide/intro.html"),
+            [
+                "https://example.com/docs/guide/subpage.html",
+                "https://example.com/docs/guide/sibling",
+                "https://example.com/docs/up",
+                "https://example.com/root",
+                "https://cdn.example.com/lib.js",
+                "https://rust-lang.org/learn",
+            ]
+        );
+        // Pages without a path resolve against the root
+        assert_eq!(
+            links("http://example.com"),
+            [
+                "http://example.com/subpage.html",
+                "http://example.com/sibling",
+                "http://example.com/up",
+                "http://example.com/root",
+                "http://cdn.example.com/lib.js",
+                "https://rust-lang.org/learn",
+            ]
+        );
+    }
+
+    #[test]
+    fn equivalent_urls_queued_once() {
+        let mut crawler = WebCrawler::default();
+        let added = crawler.enqueue_all([
+            "https://example.com",
+            "https://EXAMPLE.com/",
+            "https://example.com:443/#top",
+            "https://example.com/docs/",
+            "https://example.com/docs?page=2#intro",
+        ]);
+
+        assert_eq!(added.len(), 2);
+        let urls: Vec<_> = crawler.site_pool.values().map(|log| &log.url).collect();
+        assert_eq!(urls, ["https://example.com/", "https://example.com/docs"]);
+    }
+
+    #[test]
+    fn title_extracted_whole() {
+        let (title, _) =
+            WebCrawler::urls_and_title_within_site("<title>Rust</title>", "https://example.com")
+                .expect("Parse title");
+        assert_eq!(title, "Rust");
+
+        let (title, _) =
+            WebCrawler::urls_and_title_within_site("<title></title>", "https://example.com")
+                .expect("Parse title");
+        assert_eq!(title, "");
+    }
+
+    #[test]
+    fn links_only_followed_from_configured_elements() {
+        let text = r#"<title>Map</title>
+            <map><AREA shape="rect" href='/north'></map>
+            <a class=nav href=/south>South</a>
+            <link rel="stylesheet" href="/style.css">"#;
+
+        let (_, mut hrefs) = WebCrawler::urls_and_title_within_site(text, "https://example.com")
+            .expect("Parse site");
+        hrefs.sort();
+        assert_eq!(hrefs, ["https://example.com/south"]);
+
+        let (title, mut hrefs) = WebCrawler::links_and_title_within_site(
+            text,
+            "https://example.com",
+            &[LinkSource::default(), LinkSource::new("area", "href")],
+        )
+        .expect("Parse site");
+        hrefs.sort();
+        assert_eq!(title, "Map");
+        assert_eq!(
+            hrefs,
+            ["https://example.com/north", "https://example.com/south"]
+        );
+    }
+
+    #[test]
+    fn uppercase_markup_parsed() {
+        let page = ParsedPage::parse(
+            r#"<HTML><HEAD><TITLE> Shouting </TITLE></HEAD>
+            <BODY><A CLASS="nav" HREF="/loud" TARGET=_blank>Loud</A></BODY></HTML>"#,
+            "https://example.com",
+            &[LinkSource::default()],
+        );
+
+        assert_eq!(page.title, "Shouting");
+        assert_eq!(page.urls(), ["https://example.com/loud"]);
+        assert_eq!(page.links[0].anchor, "Loud");
+    }
+
+    #[test]
+    fn links_in_comments_and_scripts_ignored() {
+        let page = ParsedPage::parse(
+            r#"<!-- <a href="/commented">Old</a> -->
+            <script>document.write('<a href="/scripted">Late</a>');</script>
+            <a href="/real">Real</a>"#,
+            "https://example.com",
+            &[LinkSource::default()],
+        );
+
+        assert_eq!(page.urls(), ["https://example.com/real"]);
+    }
+
+    #[test]
+    fn untitled_page_has_empty_title() {
+        let page = ParsedPage::parse(
+            r#"<p>No title here, <a href="https://rust-lang.org">just a link</a></p>"#,
+            "https://example.com",
+            &[LinkSource::default()],
+        );
+
+        assert_eq!(page.title, "");
+        assert_eq!(page.urls(), ["https://rust-lang.org/"]);
+    }
+
+    #[test]
+    fn multibyte_title_and_links_kept_whole() {
+        let (title, hrefs) = WebCrawler::urls_and_title_within_site(
+            r#"<title>東京 Café</title><a href="/wiki/Zürich">Zürich</a>"#,
+            "https://example.com",
+        )
+        .expect("Parse site");
+
+        assert_eq!(title, "東京 Café");
+        assert_eq!(hrefs, ["https://example.com/wiki/Z%C3%BCrich"]);
+    }
+
+    #[test]
+    fn seeds_loaded_from_file() {
+        let mut seeds = tempfile::NamedTempFile::new().expect("Create seed file");
+        writeln!(
+            seeds,
+            "# Seeds for the crawl\nhttps://example.com\n\n  https://rust-lang.org  \nhttps://example.com"
+        )
+        .expect("Write seeds");
+
+        let mut crawler = WebCrawler::default();
+        let added = crawler
+            .enqueue_from_file(seeds.path())
+            .expect("Read seed file");
+
+        assert_eq!(added, 2);
+        let urls: Vec<_> = crawler.site_pool.values().map(|log| &log.url).collect();
+        assert_eq!(urls, ["https://example.com/", "https://rust-lang.org/"]);
+    }
+
+    #[tokio::test]
+    async fn dry_run_plans_without_fetching() {
+        let mut crawler = WebCrawler {
+            dry_run: true,
+            ..Default::default()
+        };
+        crawler.enqueue_all(["https://example.com", "https://rust-lang.org"]);
+
+        crawler.crawl().await.expect("Plan first site");
+        crawler.crawl().await.expect("Plan second site");
+
+        assert_eq!(
+            crawler.planned,
+            ["https://example.com/", "https://rust-lang.org/"]
+        );
+        assert!(crawler
+            .site_pool
+            .values()
+            .all(|site| site.title.is_empty() && site.connections.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn latin1_title_decoded() {
+        // "Café Crème" in ISO-8859-1, which isn't valid UTF-8
+        let body = b"<html><head><title>Caf\xe9 Cr\xe8me</title></head></html>".to_vec();
+        let url = serve_once("text/html; charset=ISO-8859-1", body).await;
+
+        let mut crawler = WebCrawler::default();
+        let key = crawler.enqueue(url);
+        crawler.crawl().await.expect("Crawl mock page");
+
+        assert_eq!(crawler.site_pool[key].title, "Café Crème");
+    }
+
+    #[tokio::test]
+    async fn links_resolved_against_directory_page() {
+        let url = serve(
+            |request| match request.split(' ').nth(1).unwrap_or_default() {
+                "/docs" => http_response("301 Moved Permanently", &[("Location", "/docs/")], b""),
+                _ => http_response(
+                    "200 OK",
+                    &[("Content-Type", "text/html")],
+                    b"<title>Docs</title><a href=\"intro\">Intro</a><a href=\"../about\">About</a>",
+                ),
+            },
+        )
+        .await;
+        let mut crawler = WebCrawler {
+            respect_robots: false,
+            ..Default::default()
+        };
+        let docs = crawler.enqueue(format!("{url}docs/"));
+
+        crawler.parse_site(docs).await.expect("Crawl docs");
+
+        let links: Vec<_> = crawler.site_pool[docs]
+            .connections
+            .iter()
+            .map(|key| crawler.site_pool[*key].url.clone())
+            .collect();
+        assert_eq!(
+            links,
+            [
+                format!("{url}docs/intro"),
+                format!("{url}about"),
+                format!("{url}docs")
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn recrawl_keeps_links_to_known_sites() {
+        let url = serve_site().await;
+        let mut crawler = WebCrawler {
+            respect_robots: false,
+            ..Default::default()
+        };
+        let root = crawler.enqueue(format!("{url}0"));
+        crawler.crawl_until(CrawlLimit::QueueEmpty).await;
+        let crawled = crawled_pages(&crawler);
+        let sites = crawler.site_pool.len();
+
+        // Every page is linked to from the pool already, so nothing new is queued
+        crawler.parse_site(root).await.expect("Recrawl");
+
+        assert_eq!(crawled_pages(&crawler), crawled);
+        assert_eq!(crawler.site_pool.len(), sites);
+        assert_eq!(crawler.queue_len(), 0);
+        let links: Vec<_> = crawler.site_pool[root]
+            .connections
+            .iter()
+            .map(|key| crawler.site_pool[*key].url.clone())
+            .collect();
+        assert_eq!(
+            links,
+            [format!("{url}1"), format!("{url}2"), format!("{url}0")]
+        );
+    }
+
+    #[tokio::test]
+    async fn unchanged_page_not_reparsed() {
+        let url = serve(|request| {
+            if request.to_lowercase().contains("if-none-match: \"v1\"") {
+                http_response("304 Not Modified", &[], b"")
+            } else {
+                http_response(
+                    "200 OK",
+                    &[("Content-Type", "text/html"), ("ETag", "\"v1\"")],
+                    b"<title>Original</title>",
+                )
+            }
+        })
+        .await;
+
+        let mut crawler = WebCrawler::default();
+        let key = crawler.enqueue(url);
+        crawler.parse_site(key).await.expect("First crawl");
+        assert_eq!(crawler.site_pool[key].etag.as_deref(), Some("\"v1\""));
+
+        // Anything re-parsed from a response would overwrite these
+        crawler.site_pool[key].title = "Kept".to_string();
+        crawler.site_pool[key].connections = vec![key, key];
+        crawler.site_pool[key].last_crawled = Some(0);
+
+        crawler.parse_site(key).await.expect("Recrawl");
+
+        let site = &crawler.site_pool[key];
+        assert_eq!(site.title, "Kept");
+        assert_eq!(site.connections, [key, key]);
+        assert!(site.last_crawled.is_some_and(|crawled| crawled > 0));
+    }
+
+    #[tokio::test]
+    async fn identical_pages_share_content_hash() {
+        let url = serve(|request| {
+            let body: &[u8] = if request.starts_with("GET /other") {
+                b"<title>Other</title><p>Something else</p>"
+            } else {
+                b"<title>Rust</title>\n<p>The same page</p>"
+            };
+            http_response("200 OK", &[("Content-Type", "text/html")], body)
+        })
+        .await;
+
+        let mut crawler = WebCrawler::default();
+        let original = crawler.enqueue(format!("{url}original"));
+        let mirror = crawler.enqueue(format!("{url}mirror"));
+        let other = crawler.enqueue(format!("{url}other"));
+        for key in [original, mirror, other] {
+            crawler.parse_site(key).await.expect("Crawl mock page");
+        }
+
+        let hash = |key| crawler.site_pool[key].content_hash.expect("Content hash");
+        assert_eq!(hash(original), hash(mirror));
+        assert_ne!(hash(original), hash(other));
+        assert_eq!(
+            content_hash("<title>Rust</title>   <p>The same\tpage</p>"),
+            hash(original)
+        );
+    }
+
+    /// Serves pages, and a robots.txt if one is given, recording the path of every request
+    async fn serve_with_robots(robots: Option<&'static str>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let log = Arc::clone(&requests);
+
+        let url = serve(move |request| {
+            let path = request.split(' ').nth(1).unwrap_or_default().to_string();
+            log.lock().expect("Lock request log").push(path.clone());
+
+            match (path.as_str(), robots) {
+                ("/robots.txt", Some(robots)) => http_response(
+                    "200 OK",
+                    &[("Content-Type", "text/plain")],
+                    robots.as_bytes(),
+                ),
+                ("/robots.txt", None) => http_response("404 Not Found", &[], b""),
+                _ => http_response(
+                    "200 OK",
+                    &[("Content-Type", "text/html")],
+                    format!("<title>{path}</title>").as_bytes(),
+                ),
+            }
+        })
+        .await;
+
+        (url, requests)
+    }
+
+    #[tokio::test]
+    async fn robots_disallowed_pages_skipped() {
+        let (url, requests) =
+            serve_with_robots(Some("User-agent: *\nDisallow: /private\nCrawl-delay: 0.2")).await;
+
+        let mut crawler = WebCrawler::default();
+        let private = crawler.enqueue(format!("{url}private/page"));
+        let public = crawler.enqueue(format!("{url}public"));
+        let other = crawler.enqueue(format!("{url}other"));
+
+        crawler
+            .parse_site(private)
+            .await
+            .expect("Skip private page");
+        let start = Instant::now();
+        crawler.parse_site(public).await.expect("Crawl public page");
+        crawler.parse_site(other).await.expect("Crawl other page");
+
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        assert_eq!(crawler.skipped, [format!("{url}private/page")]);
+        assert!(crawler.site_pool[private].title.is_empty());
+        assert_eq!(crawler.site_pool[public].title, "/public");
+        // robots.txt is only fetched once per host
+        assert_eq!(
+            *requests.lock().expect("Lock request log"),
+            ["/robots.txt", "/public", "/other"]
+        );
+    }
+
+    #[tokio::test]
+    async fn robots_ignored_when_disabled_or_missing() {
+        let (url, requests) = serve_with_robots(Some("User-agent: *\nDisallow: /")).await;
+        let mut crawler = WebCrawler {
+            respect_robots: false,
+            ..Default::default()
+        };
+        let key = crawler.enqueue(format!("{url}page"));
+        crawler.parse_site(key).await.expect("Crawl page");
+        assert_eq!(crawler.site_pool[key].title, "/page");
+        assert_eq!(*requests.lock().expect("Lock request log"), ["/page"]);
+
+        let (url, _) = serve_with_robots(None).await;
+        let mut crawler = WebCrawler::default();
+        let key = crawler.enqueue(format!("{url}page"));
+        crawler.parse_site(key).await.expect("Crawl page");
+        assert_eq!(crawler.site_pool[key].title, "/page");
+        assert!(crawler.skipped.is_empty());
+    }
+
+    /// Serves empty pages, logging the name and arrival time of every page requested
+    async fn serve_logged(name: &'static str, log: Arc<Mutex<Vec<(String, Instant)>>>) -> String {
+        serve(move |request| {
+            let path = request.split(' ').nth(1).unwrap_or_default();
+            if path != "/robots.txt" {
+                let page = format!("{name}{path}");
+                log.lock().expect("Lock log").push((page, Instant::now()));
+            }
+            http_response("200 OK", &[("Content-Type", "text/html")], b"")
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn same_host_fetches_spaced_out() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let url = serve_logged("a", Arc::clone(&log)).await;
+
+        let mut crawler = WebCrawler {
+            policy: CrawlPolicy {
+                per_host_delay: Duration::from_millis(100),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        crawler.enqueue_all((1..=3).map(|page| format!("{url}{page}")));
+        for _ in 0..3 {
+            crawler.crawl().await.expect("Crawl page");
+        }
+
+        let log = log.lock().expect("Lock log");
+        assert_eq!(log.len(), 3);
+        for pair in log.windows(2) {
+            // Allow for the first request taking longer to arrive than the second
+            assert!(pair[1].1 - pair[0].1 >= Duration::from_millis(90));
+        }
+    }
+
+    #[tokio::test]
+    async fn cooling_host_skipped_for_another() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let busy = serve_logged("busy", Arc::clone(&log)).await;
+        let quiet = serve_logged("quiet", Arc::clone(&log)).await;
+
+        let mut crawler = WebCrawler {
+            policy: CrawlPolicy {
+                per_host_delay: Duration::from_millis(200),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        crawler.enqueue_all([format!("{busy}1"), format!("{busy}2"), format!("{quiet}1")]);
+        for _ in 0..3 {
+            crawler.crawl().await.expect("Crawl page");
+        }
+
+        let order: Vec<_> = log
+            .lock()
+            .expect("Lock log")
+            .iter()
+            .map(|(page, _)| page.clone())
+            .collect();
+        assert_eq!(order, ["busy/1", "quiet/1", "busy/2"]);
+    }
+
+    #[tokio::test]
+    async fn requests_rate_limited_across_hosts() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let first = serve_logged("first", Arc::clone(&log)).await;
+        let second = serve_logged("second", Arc::clone(&log)).await;
+
+        let mut crawler = WebCrawler {
+            policy: CrawlPolicy {
+                max_requests_per_second: 10.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        crawler.enqueue_all([
+            format!("{first}1"),
+            format!("{second}1"),
+            format!("{first}2"),
+            format!("{second}2"),
+        ]);
+
+        let start = Instant::now();
+        for _ in 0..4 {
+            crawler.crawl().await.expect("Crawl page");
+        }
+
+        // The first request goes straight away and each after waits a tenth of a second
+        assert!(start.elapsed() >= Duration::from_millis(300));
+        assert_eq!(log.lock().expect("Lock log").len(), 4);
+    }
+
+    /// When a request to a host started and finished
+    type Visit = (&'static str, Instant, Instant);
+
+    /// Serves empty pages after a delay, handling requests side by side and logging when each
+    /// one was being served
+    async fn serve_slowly(name: &'static str, log: Arc<Mutex<Vec<Visit>>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Bind listener");
+        let addr = listener.local_addr().expect("Local address");
+
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let log = Arc::clone(&log);
+                tokio::spawn(async move {
+                    let start = Instant::now();
+                    let mut request = vec![];
+                    let mut buf = [0u8; 1024];
+                    while !request.ends_with(b"\r\n\r\n") {
+                        match stream.read(&mut buf).await {
+                            Ok(0) | Err(_) => break,
+                            Ok(read) => request.extend_from_slice(&buf[..read]),
+                        }
+                    }
+
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    let response = http_response("200 OK", &[("Content-Type", "text/html")], b"");
+                    log.lock()
+                        .expect("Lock log")
+                        .push((name, start, Instant::now()));
+                    let _ = stream.write_all(&response).await;
+                });
+            }
+        });
+
+        format!("http://{addr}/")
+    }
+
+    #[tokio::test]
+    async fn fetches_to_one_host_never_overlap() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let first = serve_slowly("first", Arc::clone(&log)).await;
+        let second = serve_slowly("second", Arc::clone(&log)).await;
+
+        let mut crawler = WebCrawler {
+            respect_robots: false,
+            policy: CrawlPolicy {
+                max_concurrent: 4,
+                max_per_host: 1,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        crawler.enqueue_all((1..=3).map(|page| format!("{first}{page}")));
+        crawler.enqueue_all((1..=3).map(|page| format!("{second}{page}")));
+
+        assert_eq!(crawler.crawl_concurrently(10).await, 6);
+
+        let log = log.lock().expect("Lock log");
+        let overlap = |a: &Visit, b: &Visit| a.1 < b.2 && b.1 < a.2;
+        for (idx, visit) in log.iter().enumerate() {
+            for other in &log[idx + 1..] {
+                if visit.0 == other.0 {
+                    assert!(!overlap(visit, other));
+                }
+            }
+        }
+        assert!(log.iter().any(|visit| log
+            .iter()
+            .any(|other| visit.0 != other.0 && overlap(visit, other))));
+    }
+
+    #[tokio::test]
+    async fn progress_reported_per_page() {
+        let url = serve(|request| {
+            let body: &[u8] = if request.starts_with("GET /next") {
+                b"<title>Next</title>"
+            } else {
+                b"<title>First</title><a href=\"/next\">Next</a>"
+            };
+            http_response("200 OK", &[("Content-Type", "text/html")], body)
+        })
+        .await;
+
+        let reports = Arc::new(Mutex::new(vec![]));
+        let sink = Arc::clone(&reports);
+        let mut crawler = WebCrawler {
+            progress: Some(Box::new(move |progress| {
+                sink.lock().expect("Lock reports").push(progress)
+            })),
+            ..Default::default()
+        };
+        crawler.enqueue(url.clone());
+
+        crawler.crawl().await.expect("Crawl first page");
+        crawler.crawl().await.expect("Crawl linked page");
+
+        let reports = reports.lock().expect("Lock reports");
+        let crawled: Vec<_> = reports
+            .iter()
+            .map(|progress| (progress.url.as_str(), progress.crawled))
+            .collect();
+        assert_eq!(crawled, [(url.as_str(), 1), (&format!("{url}next"), 2)]);
+        assert!(reports[0].queued > 0);
+    }
+
+    #[tokio::test]
+    async fn redirects_counted_once_in_progress() {
+        let url = serve_redirects().await;
+        let dir = tempfile::tempdir().expect("Create checkpoint dir");
+        let reports = Arc::new(Mutex::new(vec![]));
+        let sink = Arc::clone(&reports);
+        let (sender, mut events) = mpsc::unbounded_channel();
+        let mut crawler = WebCrawler {
+            respect_robots: false,
+            progress: Some(Box::new(move |progress| {
+                sink.lock().expect("Lock reports").push(progress.crawled)
+            })),
+            events: Some(sender),
+            autosave: Some(Autosave {
+                path: dir.path().join("crawl.json"),
+                every: 2,
+            }),
+            ..Default::default()
+        };
+        // `/a` redirects twice and `/old` lands on `/new`, already crawled from the hub
+        crawler.enqueue(url.clone());
+        crawler.enqueue(format!("{url}a"));
+
+        crawler.crawl_until(CrawlLimit::QueueEmpty).await;
+
+        assert_eq!(*reports.lock().expect("Lock reports"), [1, 2, 3, 4]);
+        let checkpoints = received(&mut events)
+            .iter()
+            .filter(|event| matches!(event, CrawlEvent::Checkpointed { .. }))
+            .count();
+        assert_eq!(checkpoints, 2);
+    }
+
+    /// Every event sent to a crawler's channel so far
+    fn received(events: &mut mpsc::UnboundedReceiver<CrawlEvent>) -> Vec<CrawlEvent> {
+        let mut received = vec![];
+        while let Ok(event) = events.try_recv() {
+            received.push(event);
+        }
+        received
+    }
+
+    #[tokio::test]
+    async fn crawl_events_streamed() {
+        let url = serve_site().await;
+        let dir = tempfile::tempdir().expect("Create checkpoint dir");
+        let checkpoint = dir.path().join("crawl.json");
+        let (sender, mut events) = mpsc::unbounded_channel();
+        let mut crawler = WebCrawler {
+            respect_robots: false,
+            events: Some(sender),
+            autosave: Some(Autosave {
+                path: checkpoint.clone(),
+                every: 5,
+            }),
+            ..Default::default()
+        };
+        crawler.enqueue(format!("{url}0"));
+
+        crawler.crawl_until(CrawlLimit::QueueEmpty).await;
+
+        let events = received(&mut events);
+        let fetched: Vec<_> = events[..5]
+            .iter()
+            .map(|event| match event {
+                CrawlEvent::Fetched {
+                    key,
+                    url,
+                    links_found,
+                    ..
+                } => {
+                    assert_eq!(crawler.site_pool[*key].url, *url);
+                    (url.clone(), *links_found)
+                }
+                other => panic!("Expected a fetch, got {other:?}"),
+            })
+            .collect();
+        let expected: Vec<_> = (0..5)
+            .map(|page| format!("{url}{page}"))
+            .zip([2, 1, 2, 1, 1])
+            .collect();
+        assert_eq!(fetched, expected);
+        // The checkpoint follows the page that was crawled when it was due
+        assert_eq!(events[5..], [CrawlEvent::Checkpointed { path: checkpoint }]);
+    }
+
+    #[tokio::test]
+    async fn skipped_and_failed_sites_streamed() {
+        let (url, _) = serve_with_robots(Some("User-agent: *\nDisallow: /private")).await;
+        let (sender, mut events) = mpsc::unbounded_channel();
+        let mut crawler = WebCrawler {
+            events: Some(sender),
+            ..Default::default()
+        };
+        crawler.policy.max_attempts = 1;
+        crawler.policy.max_pages_per_domain = Some(2);
+        crawler.enqueue(format!("{url}private/page"));
+        crawler.enqueue(format!("{url}public"));
+        crawler.enqueue(format!("{url}also-public"));
+        crawler.enqueue(format!("{url}over-limit"));
+        crawler.enqueue("http://localhost:1/down");
+
+        crawler.crawl_until(CrawlLimit::QueueEmpty).await;
+
+        let events = received(&mut events);
+        // Pages kept out by robots.txt aren't fetched, so don't count against the host's limit
+        assert!(events.contains(&CrawlEvent::Skipped {
+            url: format!("{url}private/page"),
+            reason: SkipReason::Robots,
+        }));
+        for page in ["public", "also-public"] {
+            assert!(events.iter().any(|event| matches!(
+                event,
+                CrawlEvent::Fetched { url: fetched, links_found: 0, .. } if *fetched == format!("{url}{page}")
+            )));
+        }
+        assert!(events.contains(&CrawlEvent::Skipped {
+            url: format!("{url}over-limit"),
+            reason: SkipReason::HostLimit,
+        }));
+        assert!(events.iter().any(|event| matches!(
+            event,
+            CrawlEvent::Failed { url, .. } if url == "http://localhost:1/down"
+        )));
+        assert_eq!(events.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn failing_and_merged_pages_not_reported_as_fetched() {
+        let down = serve(|_| http_response("503 Service Unavailable", &[], b"")).await;
+        let (sender, mut events) = mpsc::unbounded_channel();
+        let mut crawler = WebCrawler {
+            respect_robots: false,
+            events: Some(sender),
+            ..Default::default()
+        };
+        crawler.policy.max_attempts = 1;
+        let key = crawler.enqueue(down.clone());
+        crawler.parse_site(key).await.expect("Crawl failing page");
+
+        assert_eq!(
+            received(&mut events),
+            [CrawlEvent::Failed {
+                url: down,
+                error: "503 Service Unavailable".to_string(),
+            }]
+        );
+
+        // The hub links to `/new` and to `/old`, which redirects there
+        let url = serve_redirects().await;
+        crawler.enqueue(url.clone());
+        crawler.crawl_until(CrawlLimit::QueueEmpty).await;
+
+        let events = received(&mut events);
+        let fetched: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                CrawlEvent::Fetched { url, .. } => Some(url.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(fetched, [url.clone(), format!("{url}new")]);
+        assert!(events.contains(&CrawlEvent::Skipped {
+            url: format!("{url}old"),
+            reason: SkipReason::Duplicate,
+        }));
+    }
+
+    /// Crawls a hub page linking to twenty others, following a sample of four, and returns the
+    /// crawl order
+    async fn sampled_crawl(url: &str, seed: u64) -> Vec<String> {
+        let order = Arc::new(Mutex::new(vec![]));
+        let sink = Arc::clone(&order);
+        let mut crawler = WebCrawler {
+            respect_robots: false,
+            link_sample: Some(4),
+            progress: Some(Box::new(move |progress| {
+                sink.lock().expect("Lock order").push(progress.url)
+            })),
+            ..Default::default()
+        }
+        .with_seed(seed);
+        crawler.enqueue(url);
+
+        while !crawler.is_finished() {
+            crawler.crawl().await.expect("Crawl page");
+        }
+
+        let order = order.lock().expect("Lock order").clone();
+        order
+    }
+
+    #[tokio::test]
+    async fn same_seed_crawls_same_order() {
+        let url = serve(|request| {
+            let body = if request.starts_with("GET / ") {
+                (1..=20)
+                    .map(|page| format!("<a href=\"/{page}\">{page}</a>"))
+                    .collect()
+            } else {
+                String::new()
+            };
+            http_response("200 OK", &[("Content-Type", "text/html")], body.as_bytes())
+        })
+        .await;
+
+        let first = sampled_crawl(&url, 7).await;
+        assert_eq!(first.len(), 5);
+        assert_eq!(first, sampled_crawl(&url, 7).await);
+        assert_ne!(first, sampled_crawl(&url, 8).await);
+    }
+
+    /// Serves a hub linking to `/old` and `/new`, `/old` permanently redirecting to `/new`, and a
+    /// chain of redirects from `/a` through `/b` to `/c`
+    async fn serve_redirects() -> String {
+        serve(|request| {
+            let path = request.split(' ').nth(1).unwrap_or_default();
+            let redirect = |status, location| http_response(status, &[("Location", location)], b"");
+            match path {
+                "/old" => redirect("301 Moved Permanently", "/new"),
+                "/a" => redirect("302 Found", "/b"),
+                "/b" => redirect("301 Moved Permanently", "/c"),
+                "/" => http_response(
+                    "200 OK",
+                    &[("Content-Type", "text/html")],
+                    br#"<title>Hub</title><a href="/new">New</a><a href="/old">Old</a>"#,
+                ),
+                _ => http_response(
+                    "200 OK",
+                    &[("Content-Type", "text/html")],
+                    format!("<title>{path}</title>").as_bytes(),
+                ),
+            }
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn redirect_chain_recorded_under_final_url() {
+        let url = serve_redirects().await;
+        let mut crawler = WebCrawler {
+            respect_robots: false,
+            ..Default::default()
+        };
+        let site = crawler.enqueue(format!("{url}a"));
+
+        crawler.crawl().await.expect("Crawl redirected page");
+
+        assert_eq!(crawler.site_pool.len(), 1);
+        assert_eq!(crawler.site_pool[site].url, format!("{url}c"));
+        assert_eq!(crawler.site_pool[site].title, "/c");
+        assert_eq!(
+            crawler.aliases.get(&format!("{url}a")),
+            Some(&format!("{url}c"))
+        );
+        assert!(crawler.visited.contains(&format!("{url}a")));
+        assert!(crawler.visited.contains(&format!("{url}c")));
+        assert!(crawler.enqueue_all([format!("{url}a")]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn redirect_to_crawled_page_merged() {
+        let url = serve_redirects().await;
+        let mut crawler = WebCrawler {
+            respect_robots: false,
+            ..Default::default()
+        };
+        let hub = crawler.enqueue(url.clone());
+
+        for _ in 0..3 {
+            crawler.crawl().await.expect("Crawl page");
+        }
+
+        let urls: Vec<_> = crawler.site_pool.values().map(|site| &site.url).collect();
+        assert_eq!(urls, [&url, &format!("{url}new")]);
+        let new = crawler
+            .site_pool
+            .iter()
+            .find(|(_, site)| site.url.ends_with("/new"))
+            .map(|(key, _)| key)
+            .expect("Landed page");
+        assert_eq!(crawler.site_pool[hub].connections, [new, hub]);
+        assert_eq!(
+            crawler.aliases.get(&format!("{url}old")),
+            Some(&format!("{url}new"))
+        );
+    }
+
+    #[tokio::test]
+    async fn only_html_pages_parsed_for_links() {
+        let url = serve(|request| {
+            let path = request.split(' ').nth(1).unwrap_or_default();
+            let body = br#"<title>Page</title><a href="/linked">Linked</a>"#;
+            match path {
+                "/missing" => {
+                    http_response("404 Not Found", &[("Content-Type", "text/html")], body)
+                }
+                "/photo.jpg" => http_response("200 OK", &[("Content-Type", "image/jpeg")], body),
+                _ => http_response(
+                    "200 OK",
+                    &[("Content-Type", "text/html; charset=utf-8")],
+                    body,
+                ),
+            }
+        })
+        .await;
+
+        let mut crawler = WebCrawler {
+            respect_robots: false,
+            ..Default::default()
+        };
+        let missing = crawler.enqueue(format!("{url}missing"));
+        let photo = crawler.enqueue(format!("{url}photo.jpg"));
+        let page = crawler.enqueue(format!("{url}page"));
+        for _ in 0..3 {
+            crawler.crawl().await.expect("Crawl page");
+        }
+
+        let missing = &crawler.site_pool[missing];
+        assert_eq!(missing.status, Some(404));
+        assert!(missing.connections.is_empty());
+        assert_eq!(missing.title, "");
+
+        let photo = &crawler.site_pool[photo];
+        assert_eq!(photo.status, Some(200));
+        assert_eq!(photo.content_type.as_deref(), Some("image/jpeg"));
+        assert!(photo.connections.is_empty());
+        assert_eq!(photo.content_hash, None);
+
+        let page = &crawler.site_pool[page];
+        assert_eq!(page.status, Some(200));
+        assert_eq!(page.title, "Page");
+        assert_eq!(page.connections.len(), 2);
+        assert_eq!(crawler.site_pool.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn configured_headers_sent() {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let log = Arc::clone(&requests);
+        let url = serve(move |request| {
+            log.lock()
+                .expect("Lock request log")
+                .push(request.to_ascii_lowercase());
+            http_response(
+                "200 OK",
+                &[("Content-Type", "text/html")],
+                b"<title>Echo</title>",
+            )
+        })
+        .await;
+
+        let mut crawler = WebCrawler::default();
+        crawler.enqueue(format!("{url}page"));
+        crawler.crawl().await.expect("Crawl page");
+
+        let mut crawler = WebCrawler {
+            user_agent: "echo-bot/2.0".to_string(),
+            accept_language: Some("de-CH, de;q=0.9".to_string()),
+            ..Default::default()
+        };
+        crawler
+            .headers
+            .insert("x-crawl-id", "42".parse().expect("Header value"));
+        crawler.enqueue(format!("{url}other"));
+        crawler.crawl().await.expect("Crawl page");
+
+        let requests = requests.lock().expect("Lock request log");
+        let [robots, page, robots_again, other] = requests.as_slice() else {
+            panic!("Expected 4 requests, got {requests:?}");
+        };
+        let default_agent = format!("user-agent: {DEFAULT_USER_AGENT}\r\n");
+        assert!(robots.contains(&default_agent));
+        assert!(page.contains(&default_agent));
+        assert!(!page.contains("accept-language"));
+
+        for request in [robots_again, other] {
+            assert!(request.contains("user-agent: echo-bot/2.0\r\n"));
+            assert!(request.contains("accept-language: de-ch, de;q=0.9\r\n"));
+            assert!(request.contains("x-crawl-id: 42\r\n"));
+        }
+    }
+
+    #[tokio::test]
+    async fn transient_failures_retried() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let seen = Arc::clone(&requests);
+        let url = serve(move |_| match seen.fetch_add(1, Ordering::SeqCst) {
+            0 => http_response("429 Too Many Requests", &[("Retry-After", "0")], b""),
+            1 => http_response("503 Service Unavailable", &[], b""),
+            _ => http_response(
+                "200 OK",
+                &[("Content-Type", "text/html")],
+                b"<title>Up</title>",
+            ),
+        })
+        .await;
+
+        let mut crawler = WebCrawler {
+            respect_robots: false,
+            policy: CrawlPolicy {
+                retry_backoff: Duration::from_millis(10),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let page = crawler.enqueue(format!("{url}page"));
+        crawler.crawl().await.expect("Crawl page");
+
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        assert_eq!(crawler.site_pool[page].title, "Up");
+        assert_eq!(crawler.site_pool[page].status, Some(200));
+        assert!(crawler.failures.is_empty());
+
+        // Out of retries, the last response is kept and the page logged as failed
+        let down = serve(|_| http_response("503 Service Unavailable", &[], b"")).await;
+        crawler.policy.max_attempts = 2;
+        let page = crawler.enqueue(format!("{down}page"));
+        crawler.crawl().await.expect("Crawl page");
+
+        assert_eq!(crawler.site_pool[page].status, Some(503));
+        assert_eq!(
+            crawler.failures,
+            [CrawlFailure {
+                url: format!("{down}page"),
+                error: "503 Service Unavailable".to_string(),
+                attempts: 2,
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn long_retry_after_capped() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let seen = Arc::clone(&requests);
+        let url = serve(move |_| match seen.fetch_add(1, Ordering::SeqCst) {
+            0 => http_response("429 Too Many Requests", &[("Retry-After", "3600")], b""),
+            _ => http_response(
+                "200 OK",
+                &[("Content-Type", "text/html")],
+                b"<title>Up</title>",
+            ),
+        })
+        .await;
+
+        let mut crawler = WebCrawler {
+            respect_robots: false,
+            policy: CrawlPolicy {
+                max_retry_after: Duration::from_millis(10),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let page = crawler.enqueue(format!("{url}page"));
+        let started = Instant::now();
+        crawler.crawl().await.expect("Crawl page");
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        assert_eq!(crawler.site_pool[page].title, "Up");
+    }
+
+    #[tokio::test]
+    async fn hung_fetch_times_out() {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Bind listener");
+        let addr = listener.local_addr().expect("Local address");
+        let connections = Arc::new(AtomicUsize::new(0));
+        let accepted = Arc::clone(&connections);
+        tokio::spawn(async move {
+            // Connections are held open without ever being answered
+            let mut open = vec![];
+            while let Ok((stream, _)) = listener.accept().await {
+                accepted.fetch_add(1, Ordering::SeqCst);
+                open.push(stream);
+            }
+        });
+
+        let mut crawler = WebCrawler {
+            respect_robots: false,
+            policy: CrawlPolicy {
+                timeout: Duration::from_millis(100),
+                max_attempts: 2,
+                retry_backoff: Duration::from_millis(10),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let url = format!("http://{addr}/page");
+        let page = crawler.enqueue(url.clone());
+
+        let started = Instant::now();
+        crawler.crawl().await.expect("Crawl page");
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        assert_eq!(connections.load(Ordering::SeqCst), 2);
+        assert_eq!(crawler.failures.len(), 1);
+        assert_eq!(crawler.failures[0].url, url);
+        assert_eq!(crawler.failures[0].attempts, 2);
+        assert!(crawler.visited.contains(&url));
+        assert_eq!(crawler.site_pool[page].last_crawled, None);
+    }
+
+    #[tokio::test]
+    async fn hung_robots_fetch_times_out() {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Bind listener");
+        let addr = listener.local_addr().expect("Local address");
+        let connections = Arc::new(AtomicUsize::new(0));
+        let accepted = Arc::clone(&connections);
+        tokio::spawn(async move {
+            let mut open = vec![];
+            while let Ok((stream, _)) = listener.accept().await {
+                accepted.fetch_add(1, Ordering::SeqCst);
+                open.push(stream);
+            }
+        });
+
+        let mut crawler = WebCrawler {
+            policy: CrawlPolicy {
+                timeout: Duration::from_millis(100),
+                max_attempts: 1,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let url = format!("http://{addr}/page");
+        crawler.enqueue(url.clone());
+
+        // robots.txt is given up on like any page, leaving the page itself to time out
+        tokio::time::timeout(Duration::from_secs(5), crawler.crawl())
+            .await
+            .expect("Crawl finishes")
+            .expect("Crawl page");
+        assert_eq!(connections.load(Ordering::SeqCst), 2);
+        assert_eq!(crawler.failures.len(), 1);
+        assert_eq!(crawler.failures[0].url, url);
+    }
+
+    #[tokio::test]
+    async fn nofollow_links_left_out_of_connections() {
+        let url = serve_once(
+            "text/html",
+            br#"<title>Links</title>
+            <a href="/followed">Followed</a>
+            <a href="/sponsored" rel="sponsored NoFollow">Ad</a>
+            <a href="/comment" rel="nofollow">Comment</a>
+            <a href="/noopener" rel="noopener">External</a>
+            <a href="/twice" rel="nofollow">Once</a>
+            <a href="/twice">Again</a>"#
+                .to_vec(),
+        )
+        .await;
+
+        let mut crawler = WebCrawler {
+            respect_robots: false,
+            record_nofollow: true,
+            ..Default::default()
+        };
+        let root = crawler.enqueue(format!("{url}page"));
+        crawler.crawl().await.expect("Crawl page");
+
+        let site = &crawler.site_pool[root];
+        let mut connections: Vec<_> = site
+            .connections
+            .iter()
+            .map(|key| crawler.site_pool[*key].url.as_str())
+            .collect();
+        connections.sort();
+        let followed = [
+            format!("{url}followed"),
+            format!("{url}noopener"),
+            format!("{url}page"),
+            format!("{url}twice"),
+        ];
+        assert_eq!(connections, followed);
+        assert_eq!(
+            site.nofollow,
+            [format!("{url}sponsored"), format!("{url}comment")]
+        );
+        assert_eq!(crawler.site_pool.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn links_outside_path_prefix_skipped() {
+        let url = serve(|request| {
+            let path = request.split(' ').nth(1).unwrap_or_default();
+            let body = match path {
+                // Trailing slashes are dropped when URLs are normalized
+                "/docs" => {
+                    r#"<title>Docs</title>
+                    <a href="/docs/guide">Guide</a>
+                    <a href="/blog/">Blog</a>
+                    <a href="/docs-old/">Old docs</a>
+                    <a href="http://localhost/docs/guide">Same path, other host</a>"#
+                }
+                "/docs/guide" => r#"<title>Guide</title><a href="/">Home</a>"#,
+                _ => "<title>Elsewhere</title>",
+            };
+            http_response("200 OK", &[("Content-Type", "text/html")], body.as_bytes())
+        })
+        .await;
+
+        let mut crawler = WebCrawler {
+            respect_robots: false,
+            path_prefix: Some("/docs/".to_string()),
+            ..Default::default()
+        };
+        crawler.enqueue(format!("{url}docs/"));
+        while !crawler.is_finished() {
+            crawler.crawl().await;
+        }
+
+        let mut crawled: Vec<_> = crawler
+            .site_pool
+            .values()
+            .map(|site| site.title.as_str())
+            .collect();
+        crawled.sort();
+        assert_eq!(crawled, ["Docs", "Guide"]);
+    }
+
+    #[test]
+    fn meta_charset_used_without_header() {
+        let shift_jis = b"<meta charset=\"Shift_JIS\"><title>\x93\x8c\x8b\x9e</title>";
+        assert!(decode_html(shift_jis, None).contains("東京"));
+
+        let http_equiv =
+            b"<meta http-equiv=\"Content-Type\" content=\"text/html; charset=windows-1252\">\xe9";
+        assert!(decode_html(http_equiv, Some("text/html")).ends_with('é'));
+
+        assert_eq!(decode_html("東京".as_bytes(), None), "東京");
+    }
+
+    fn linked_crawler() -> WebCrawler {
+        let mut crawler = WebCrawler::default();
+        let keys = crawler.enqueue_all(["https://a.com", "https://b.com", "https://c.com"]);
+        for (idx, key) in keys.iter().enumerate() {
+            let site = &mut crawler.site_pool[*key];
+            site.title = format!("Site {idx}");
+            site.connections = vec![keys[(idx + 1) % keys.len()], *key];
+        }
+        crawler
+    }
+
+    #[test]
+    fn ndjson_round_trip() {
+        let crawler = linked_crawler();
+        let file = tempfile::NamedTempFile::new().expect("Create ndjson file");
+
+        crawler.save_ndjson(file.path()).expect("Save ndjson");
+        let loaded = WebCrawler::load_ndjson(file.path()).expect("Load ndjson");
+
+        let contents = std::fs::read_to_string(file.path()).expect("Read ndjson");
+        assert_eq!(contents.lines().count(), 3);
+        assert_eq!(loaded.len(), 3);
+        for (site, loaded) in crawler.site_pool.values().zip(loaded.values()) {
+            assert_eq!(site.url, loaded.url);
+            assert_eq!(site.title, loaded.title);
+        }
+        for site in loaded.values() {
+            let linked: Vec<_> = site
+                .connections
+                .iter()
+                .map(|key| &loaded[*key].url)
+                .collect();
+            let original = crawler
+                .site_pool
+                .values()
+                .find(|original| original.url == site.url)
+                .expect("Original site");
+            let expected: Vec<_> = original
+                .connections
+                .iter()
+                .map(|key| &crawler.site_pool[*key].url)
+                .collect();
+            assert_eq!(linked, expected);
+        }
+    }
+
+    #[test]
+    fn queue_grows_only_with_new_urls() {
+        let mut crawler = WebCrawler::default();
+        let keys = crawler.enqueue_all(["https://a.com/", "https://b.com/", "https://c.com/"]);
+        assert_eq!(crawler.queue_len(), 3);
+        assert_eq!(crawler.peek_queue(), Some(keys[0]));
+
+        let added = crawler.enqueue_all(["https://b.com", "https://A.com/", "https://c.com/"]);
+        assert!(added.is_empty());
+        assert_eq!(crawler.queue_len(), 3);
+        assert_eq!(crawler.site_queue, keys);
+
+        // Sites taken out of the pool directly aren't counted as known anymore
+        crawler.site_pool.remove(keys[1]);
+        assert_eq!(crawler.enqueue_all(["https://b.com/"]).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn queue_drained_in_order() {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let log = Arc::clone(&requests);
+        let url = serve(move |request| {
+            let path = request.split(' ').nth(1).unwrap_or_default().to_string();
+            log.lock().expect("Lock request log").push(path);
+            http_response(
+                "200 OK",
+                &[("Content-Type", "text/html")],
+                b"<title>Page</title>",
+            )
+        })
+        .await;
+
+        let mut crawler = WebCrawler {
+            respect_robots: false,
+            ..Default::default()
+        };
+        let order = ["/3", "/1", "/4", "/0", "/2"];
+        crawler.enqueue_all(order.iter().map(|path| format!("{url}{}", &path[1..])));
+
+        while let Some(next) = crawler.peek_queue() {
+            let queued = crawler.queue_len();
+            crawler.crawl().await.expect("Crawl page");
+            assert!(crawler.site_pool[next].last_crawled.is_some());
+            assert_eq!(crawler.queue_len(), queued - 1);
+        }
+
+        assert!(crawler.is_finished());
+        assert_eq!(*requests.lock().expect("Lock request log"), order);
+    }
+
+    #[test]
+    fn checkpoint_resumes_uncrawled_sites() {
+        let mut crawler = linked_crawler();
+        let keys: Vec<_> = crawler.site_pool.keys().collect();
+        crawler.site_pool[keys[0]].last_crawled = Some(unix_now());
+        let dir = tempfile::tempdir().expect("Create checkpoint dir");
+        let path = dir.path().join("checkpoint.json");
+
+        crawler.checkpoint(&path).expect("Checkpoint");
+        crawler.site_pool[keys[1]].last_crawled = Some(unix_now());
+        crawler.checkpoint(&path).expect("Overwrite checkpoint");
+
+        let mut resumed = WebCrawler::resume(&path).expect("Resume");
+        assert_eq!(resumed.site_pool.len(), 3);
+        assert_eq!(resumed.visited.len(), 2);
+        assert!(resumed.visited.contains(&crawler.site_pool[keys[0]].url));
+        assert!(resumed.visited.contains(&crawler.site_pool[keys[1]].url));
+        assert_eq!(resumed.site_queue.pop_front(), Some(keys[2]));
+        assert!(resumed.is_finished());
+        assert_eq!(std::fs::read_dir(dir.path()).expect("List dir").count(), 1);
+    }
+
+    /// Serves a small site where pages link back and forth, `/0` through `/4`
+    async fn serve_site() -> String {
+        serve(|request| {
+            let path = request.split(' ').nth(1).unwrap_or_default();
+            let links: &[usize] = match path {
+                "/0" => &[1, 2],
+                "/1" => &[3],
+                "/2" => &[3, 4],
+                "/3" | "/4" => &[0],
+                _ => return http_response("404 Not Found", &[], b""),
+            };
+            let mut body = format!("<title>Page {path}</title>");
+            for link in links {
+                body.push_str(&format!("<a href=\"/{link}\">{link}</a>"));
+            }
+            http_response("200 OK", &[("Content-Type", "text/html")], body.as_bytes())
+        })
+        .await
+    }
+
+    /// Every crawled site's title and the URLs it links to, keyed by URL
+    fn crawled_pages(crawler: &WebCrawler) -> BTreeMap<String, (String, BTreeSet<String>)> {
+        crawler
+            .site_pool
+            .values()
+            .map(|site| {
+                let links = site
+                    .connections
+                    .iter()
+                    .map(|key| crawler.site_pool[*key].url.clone())
+                    .collect();
+                (site.url.clone(), (site.title.clone(), links))
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn pages_per_host_capped() {
+        // The same server reached as two hosts, `127.0.0.1` and `localhost`
+        let requests = Arc::new(Mutex::new(vec![]));
+        let log = Arc::clone(&requests);
+        let url = serve(move |request| {
+            let path = request.split(' ').nth(1).unwrap_or_default().to_string();
+            let host = request
+                .lines()
+                .find_map(|line| {
+                    let (name, value) = line.split_once(':')?;
+                    name.eq_ignore_ascii_case("host")
+                        .then(|| value.trim().to_string())
+                })
+                .unwrap_or_default();
+            let port = host.rsplit(':').next().unwrap_or_default().to_string();
+            log.lock().expect("Lock request log").push(host.clone());
+
+            let body = if host.starts_with("localhost") {
+                format!("<title>Other {path}</title><a href=\"/b/1\">1</a><a href=\"/b/2\">2</a>")
+            } else {
+                let mut body = format!("<title>Big {path}</title>");
+                for page in 0..5 {
+                    body.push_str(&format!("<a href=\"/a/{page}\">{page}</a>"));
+                }
+                body.push_str(&format!(
+                    "<a href=\"http://localhost:{port}/b/0\">Other</a>"
+                ));
+                body
+            };
+            http_response("200 OK", &[("Content-Type", "text/html")], body.as_bytes())
+        })
+        .await;
+        let big_host = host_of(&url).expect("Fixture host");
+
+        let mut crawler = WebCrawler {
+            respect_robots: false,
+            policy: CrawlPolicy {
+                max_pages_per_domain: Some(2),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        crawler.enqueue(format!("{url}a/start"));
+        crawler.crawl_until(CrawlLimit::QueueEmpty).await;
+
+        let fetches = |host: &str| {
+            requests
+                .lock()
+                .expect("Lock request log")
+                .iter()
+                .filter(|requested| requested.starts_with(host))
+                .count()
+        };
+        assert_eq!(fetches(&big_host), 2);
+        // The other host is still reached from a capped host, with its own limit
+        assert_eq!(fetches("localhost"), 2);
+        // Links past the limit are kept, just not crawled
+        let uncrawled = crawler
+            .site_pool
+            .values()
+            .filter(|site| site.last_crawled.is_none())
+            .count();
+        assert!(uncrawled > 0);
+
+        let dir = tempfile::tempdir().expect("Create save dir");
+        let path = dir.path().join("crawl.json");
+        crawler.save(&path, false).expect("Save crawl");
+        let mut resumed = WebCrawler::resume(&path).expect("Resume");
+        // Resuming counts the same pages the crawl did
+        assert_eq!(resumed.host_pages, crawler.host_pages);
+        resumed.respect_robots = false;
+        resumed.policy.max_pages_per_domain = Some(2);
+        resumed.enqueue(format!("{url}a/late"));
+        resumed.crawl_until(CrawlLimit::QueueEmpty).await;
+        assert_eq!(fetches(&big_host), 2);
+        assert_eq!(fetches("localhost"), 2);
+    }
+
+    #[tokio::test]
+    async fn crawl_stops_at_page_limit() {
+        let url = serve_site().await;
+        let mut crawler = WebCrawler {
+            respect_robots: false,
+            ..Default::default()
+        };
+        crawler.enqueue(format!("{url}0"));
+
+        let summary = crawler.crawl_until(CrawlLimit::MaxPages(3)).await;
+        assert_eq!(summary.pages, 3);
+        assert_eq!(summary.failures, 0);
+        let crawled = crawler
+            .site_pool
+            .values()
+            .filter(|site| site.last_crawled.is_some())
+            .count();
+        assert_eq!(crawled, 3);
+        assert!(!crawler.is_finished());
+
+        let limit = CrawlLimit::Any(vec![
+            CrawlLimit::MaxPages(100),
+            CrawlLimit::MaxDuration(Duration::ZERO),
+        ]);
+        assert_eq!(crawler.crawl_until(limit).await.pages, 0);
+    }
+
+    #[tokio::test]
+    async fn skipped_sites_not_counted_as_pages() {
+        let (url, requests) = serve_with_robots(Some("User-agent: *\nDisallow: /private")).await;
+        let mut crawler = WebCrawler::default();
+        crawler.enqueue_all([
+            format!("{url}private/a"),
+            format!("{url}private/b"),
+            format!("{url}public"),
+            format!("{url}other"),
+        ]);
+
+        let summary = crawler.crawl_until(CrawlLimit::MaxPages(1)).await;
+        assert_eq!(summary.pages, 1);
+        assert_eq!(
+            *requests.lock().expect("Lock request log"),
+            ["/robots.txt", "/public"]
+        );
+
+        crawler.policy.max_pages_per_domain = Some(2);
+        crawler.enqueue(format!("{url}over-limit"));
+        let summary = crawler.crawl_until(CrawlLimit::QueueEmpty).await;
+        assert_eq!(summary.pages, 1);
+        assert!(!requests
+            .lock()
+            .expect("Lock request log")
+            .contains(&"/over-limit".to_string()));
+
+        let mut crawler = WebCrawler {
+            respect_robots: false,
+            ..Default::default()
+        };
+        crawler.enqueue_all([format!("{url}a"), format!("{url}b")]);
+        assert_eq!(crawler.crawl_concurrently(10).await, 2);
+        crawler.policy.max_pages_per_domain = Some(2);
+        crawler.enqueue(format!("{url}c"));
+        assert_eq!(crawler.crawl_concurrently(10).await, 0);
+    }
+
+    #[tokio::test]
+    async fn crawl_stops_when_queue_empty() {
+        let url = serve_site().await;
+        let mut crawler = WebCrawler {
+            respect_robots: false,
+            ..Default::default()
+        };
+        crawler.enqueue(format!("{url}0"));
+
+        let summary = crawler.crawl_until(CrawlLimit::QueueEmpty).await;
+        assert_eq!(summary.pages, 5);
+        assert!(crawler.is_finished());
+        assert_eq!(crawler.queue_len(), 0);
+        assert!(crawler
+            .site_pool
+            .values()
+            .all(|site| site.last_crawled.is_some()));
+    }
+
+    #[tokio::test]
+    async fn resumed_crawl_matches_uninterrupted_crawl() {
+        let url = serve_site().await;
+        let fresh = || {
+            let mut crawler = WebCrawler {
+                respect_robots: false,
+                ..Default::default()
+            };
+            crawler.enqueue(format!("{url}0"));
+            crawler
+        };
+
+        let mut uninterrupted = fresh();
+        while !uninterrupted.is_finished() {
+            uninterrupted.crawl().await.expect("Crawl page");
+        }
+
+        let dir = tempfile::tempdir().expect("Create save dir");
+        let path = dir.path().join("crawl.json");
+        let mut interrupted = fresh();
+        for _ in 0..2 {
+            interrupted.crawl().await.expect("Crawl page");
+        }
+        interrupted.save(&path, false).expect("Save crawl");
+
+        let mut resumed = WebCrawler::resume(&path).expect("Resume");
+        resumed.respect_robots = false;
+        assert_eq!(resumed.visited.len(), 2);
+        while !resumed.is_finished() {
+            resumed.crawl().await.expect("Crawl page");
+        }
+
+        assert_eq!(crawled_pages(&resumed), crawled_pages(&uninterrupted));
+        assert_eq!(resumed.site_pool.len(), 5);
+        assert!(resumed
+            .site_pool
+            .values()
+            .all(|site| site.last_crawled.is_some()));
+    }
+
+    #[test]
+    fn save_only_overwrites_when_asked() {
+        let mut crawler = linked_crawler();
+        let dir = tempfile::tempdir().expect("Create save dir");
+        let path = dir.path().join("pool.json");
+        crawler.save(&path, false).expect("Save");
+
+        let keys: Vec<_> = crawler.site_pool.keys().collect();
+        crawler.site_pool.remove(keys[2]);
+        match crawler.save(&path, false) {
+            Err(SaveError::AlreadyExists(existing)) => assert_eq!(existing, path),
+            other => panic!("Expected the save to be refused, got {other:?}"),
+        }
+        assert_eq!(WebCrawler::load(&path).expect("Load").len(), 3);
+        // The refused save's temporary file is cleaned up
+        assert_eq!(std::fs::read_dir(dir.path()).expect("List dir").count(), 1);
+
+        crawler.save(&path, true).expect("Overwrite");
+        assert_eq!(WebCrawler::load(&path).expect("Load").len(), 2);
+
+        crawler
+            .save_pretty(&path, true)
+            .expect("Overwrite with pretty JSON");
+        let text = std::fs::read_to_string(&path).expect("Read save");
+        assert!(text.lines().count() > 1);
+        let loaded = WebCrawler::load(&path).expect("Load");
+        assert!(loaded.iter().eq(crawler.site_pool.iter()));
+    }
+
+    #[test]
+    fn save_replaces_file_whole() {
+        let crawler = linked_crawler();
+        let dir = tempfile::tempdir().expect("Create save dir");
+        let path = dir.path().join("pool.json");
+        // Left behind by a save that was interrupted
+        std::fs::write(dir.path().join("pool.json.partial"), "{\"trunc").expect("Write partial");
+        std::fs::write(&path, "old").expect("Write old save");
+
+        crawler.save(&path, true).expect("Save");
+        let files: Vec<_> = std::fs::read_dir(dir.path())
+            .expect("List dir")
+            .map(|entry| entry.expect("Dir entry").file_name())
+            .collect();
+        assert_eq!(files, ["pool.json"]);
+        let loaded = WebCrawler::load(&path).expect("Load");
+        assert!(loaded.iter().eq(crawler.site_pool.iter()));
+
+        // A save that can't be written leaves the last one as it was
+        let blocked = dir.path().join("blocked.json");
+        std::fs::create_dir(dir.path().join("blocked.json.partial")).expect("Block partial");
+        std::fs::write(&blocked, "old").expect("Write old save");
+        assert!(matches!(
+            crawler.save(&blocked, true),
+            Err(SaveError::Io(_))
+        ));
+        assert_eq!(std::fs::read_to_string(&blocked).expect("Read save"), "old");
+    }
+
+    /// A pool of 10,000 linked sites with a bit of everything filled in
+    fn large_pool() -> WebCrawler {
+        let mut crawler = WebCrawler::default();
+        let keys: Vec<_> = (0..10_000)
+            .map(|idx| crawler.enqueue(format!("https://site{}.com/page/{idx}", idx % 50)))
+            .collect();
+        for (idx, key) in keys.iter().enumerate() {
+            let site = &mut crawler.site_pool[*key];
+            site.title = format!("Page {idx} of the synthetic web");
+            site.description = (idx % 3 == 0).then(|| format!("About page {idx}"));
+            site.last_crawled = Some(1_700_000_000 + idx as u64);
+            site.status = Some(200);
+            site.connections = (1..=5)
+                .map(|step| keys[(idx * 7 + step) % keys.len()])
+                .collect();
+        }
+        crawler
+    }
+
+    #[test]
+    fn every_format_round_trips() {
+        let crawler = large_pool();
+        let dir = tempfile::tempdir().expect("Create save dir");
+
+        let formats = [
+            Format::Json,
+            Format::PrettyJson,
+            #[cfg(feature = "gzip")]
+            Format::JsonGz,
+            #[cfg(feature = "binary")]
+            Format::Binary,
+            #[cfg(all(feature = "binary", feature = "gzip"))]
+            Format::BinaryGz,
+        ];
+        for format in formats {
+            let path = dir.path().join(format!("{format:?}"));
+            crawler.save_as(&path, format, false).expect("Save");
+
+            let loaded = WebCrawler::load_auto(&path).expect("Load");
+            assert!(
+                loaded.iter().eq(crawler.site_pool.iter()),
+                "{format:?} changed the pool"
+            );
+        }
+    }
+
+    #[cfg(all(feature = "binary", feature = "gzip"))]
+    #[test]
+    fn compressed_binary_much_smaller_than_json() {
+        let crawler = large_pool();
+        let dir = tempfile::tempdir().expect("Create save dir");
+        let size = |format| {
+            let path = dir.path().join(format!("{format:?}"));
+            crawler.save_as(&path, format, false).expect("Save");
+            std::fs::metadata(path).expect("Saved file").len()
+        };
+
+        let pretty = size(Format::PrettyJson);
+        let compact = size(Format::BinaryGz);
+        assert!(compact * 5 < pretty, "{compact} bytes against {pretty}");
+        assert!(size(Format::Binary) < size(Format::Json));
+    }
+
+    #[cfg(not(feature = "binary"))]
+    #[test]
+    fn formats_left_out_reported() {
+        let file = tempfile::NamedTempFile::new().expect("Create save file");
+        std::fs::write(file.path(), b"PRSB\x01\x00").expect("Write save");
+
+        let err = WebCrawler::load_auto(file.path()).expect_err("Binary format left out");
+        assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+    }
+
+    /// Two sites linking to each other, one with a title that needs quoting, plus a link to a
+    /// site no longer in the pool
+    fn export_pool() -> WebCrawler {
+        let mut crawler = WebCrawler::default();
+        let rust = crawler.enqueue("https://rust-lang.org/");
+        let book = crawler.enqueue("https://doc.rust-lang.org/book");
+        let gone = crawler.enqueue("https://gone.com/");
+        crawler.site_pool.remove(gone);
+
+        crawler.site_pool[rust].title = "Rust".to_string();
+        crawler.site_pool[rust].connections = vec![book, gone, rust];
+        crawler.site_pool[book].title = r#"The "Book", <2nd> & more"#.to_string();
+        crawler.site_pool[book].connections = vec![rust];
+        crawler
+    }
+
+    #[test]
+    fn graph_exported_as_csv() {
+        let crawler = export_pool();
+
+        let mut edges = vec![];
+        crawler.export_edges(&mut edges).expect("Export edges");
+        assert_eq!(
+            String::from_utf8(edges).expect("UTF-8 edges"),
+            "source_url,target_url\n\
+             https://rust-lang.org/,https://doc.rust-lang.org/book\n\
+             https://doc.rust-lang.org/book,https://rust-lang.org/\n"
+        );
+
+        let mut nodes = vec![];
+        crawler.export_nodes(&mut nodes).expect("Export nodes");
+        assert_eq!(
+            String::from_utf8(nodes).expect("UTF-8 nodes"),
+            "url,title,out_degree\n\
+             https://rust-lang.org/,Rust,1\n\
+             https://doc.rust-lang.org/book,\"The \"\"Book\"\", <2nd> & more\",1\n"
+        );
+    }
+
+    #[test]
+    fn graph_exported_as_graphml() {
+        let crawler = export_pool();
+
+        let mut graphml = vec![];
+        crawler
+            .export_graphml(&mut graphml)
+            .expect("Export GraphML");
+        assert_eq!(
+            String::from_utf8(graphml).expect("UTF-8 GraphML"),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<graphml xmlns="http://graphml.graphdrawing.org/xmlns">
+  <key id="url" for="node" attr.name="url" attr.type="string"/>
+  <key id="title" for="node" attr.name="title" attr.type="string"/>
+  <graph id="crawl" edgedefault="directed">
+    <node id="n0"><data key="url">https://rust-lang.org/</data><data key="title">Rust</data></node>
+    <node id="n1"><data key="url">https://doc.rust-lang.org/book</data><data key="title">The &quot;Book&quot;, &lt;2nd&gt; &amp; more</data></node>
+    <edge source="n0" target="n1"/>
+    <edge source="n1" target="n0"/>
+  </graph>
+</graphml>
+"#
+        );
+    }
+
+    #[test]
+    fn streamed_pool_keeps_keys() {
+        let crawler = large_pool();
+        let dir = tempfile::tempdir().expect("Create save dir");
+        let path = dir.path().join("pool.ndjson");
+
+        crawler.save_stream(&path, false).expect("Save stream");
+        let loaded = WebCrawler::load_stream(&path).expect("Load stream");
+        assert!(loaded.bad_lines.is_empty());
+        assert!(loaded.sites.iter().eq(crawler.site_pool.iter()));
+
+        let auto = WebCrawler::load_auto(&path).expect("Load auto");
+        assert!(auto.iter().eq(crawler.site_pool.iter()));
+    }
+
+    #[test]
+    fn truncated_stream_loads_intact_prefix() {
+        let crawler = linked_crawler();
+        let dir = tempfile::tempdir().expect("Create save dir");
+        let path = dir.path().join("pool.ndjson");
+        crawler.save_stream(&path, false).expect("Save stream");
+
+        // Cut the file off partway through its last line, as an interrupted copy would
+        let contents = std::fs::read(&path).expect("Read stream");
+        std::fs::write(&path, &contents[..contents.len() - 20]).expect("Truncate stream");
+
+        let loaded = WebCrawler::load_stream(&path).expect("Load stream");
+        assert_eq!(loaded.bad_lines, [3]);
+        let urls: Vec<_> = loaded
+            .sites
+            .values()
+            .map(|site| site.url.as_str())
+            .collect();
+        assert_eq!(urls, ["https://a.com/", "https://b.com/"]);
+        // Sites keep their keys, and only links to the lost site are dropped
+        for (key, site) in &loaded.sites {
+            let original = &crawler.site_pool[key];
+            assert_eq!(site.title, original.title);
+            let kept: Vec<_> = original
+                .connections
+                .iter()
+                .copied()
+                .filter(|key| loaded.sites.contains_key(*key))
+                .collect();
+            assert_eq!(site.connections, kept);
+        }
+    }
+
+    #[test]
+    fn corrupt_ndjson_line_skipped() {
+        let crawler = linked_crawler();
+        let file = tempfile::NamedTempFile::new().expect("Create ndjson file");
+        crawler.save_ndjson(file.path()).expect("Save ndjson");
+
+        let contents = std::fs::read_to_string(file.path()).expect("Read ndjson");
+        let mut lines: Vec<_> = contents.lines().map(str::to_string).collect();
+        let half = lines[1].len() / 2;
+        lines[1].truncate(half);
+        std::fs::write(file.path(), lines.join("\n")).expect("Corrupt ndjson");
+
+        let events = CapturedEvents::default();
+        let loaded = tracing::subscriber::with_default(events.clone(), || {
+            WebCrawler::load_ndjson(file.path()).expect("Load ndjson")
+        });
+        assert!(events.logged(tracing::Level::WARN, "skipping malformed line"));
+        let urls: Vec<_> = loaded.values().map(|site| site.url.as_str()).collect();
+        assert_eq!(urls, ["https://a.com/", "https://c.com/"]);
+        // Links to the lost site are dropped rather than dangling
+        assert!(loaded
+            .values()
+            .all(|site| site.connections.iter().all(|key| loaded.contains_key(*key))));
+    }
+
+    #[test]
+    fn hosts_extracted() {
+        assert_eq!(
+            host_of("https://en.Wikipedia.org/wiki/Rust").as_deref(),
+            Some("en.wikipedia.org")
+        );
+        assert_eq!(
+            host_of("en.wikipedia.org/wiki/Rust").as_deref(),
+            Some("en.wikipedia.org")
+        );
+        assert_eq!(
+            host_of("http://user:pw@example.com:8080/").as_deref(),
+            Some("example.com")
+        );
+        assert_eq!(host_of("localhost:3000/path").as_deref(), Some("localhost"));
+        assert_eq!(host_of("not a url"), None);
+    }
+
+    /// Events logged by this crate while it's the default subscriber, as their level and message
+    #[derive(Clone, Default)]
+    struct CapturedEvents(Arc<Mutex<Vec<(tracing::Level, String)>>>);
+
+    impl CapturedEvents {
+        fn logged(&self, level: tracing::Level, message: &str) -> bool {
+            self.0
+                .lock()
+                .expect("Lock events")
+                .iter()
+                .any(|(logged, text)| *logged == level && text == message)
+        }
+    }
+
+    impl tracing::Subscriber for CapturedEvents {
+        fn enabled(&self, metadata: &tracing::Metadata<'_>) -> bool {
+            metadata
+                .target()
+                .starts_with(module_path!().trim_end_matches("::tests"))
+        }
+
+        fn new_span(&self, _: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            tracing::span::Id::from_u64(1)
+        }
+
+        fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record<'_>) {}
+
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+        fn event(&self, event: &tracing::Event<'_>) {
+            struct Message(String);
+            impl tracing::field::Visit for Message {
+                fn record_debug(
+                    &mut self,
+                    field: &tracing::field::Field,
+                    value: &dyn std::fmt::Debug,
+                ) {
+                    if field.name() == "message" {
+                        self.0 = format!("{value:?}");
+                    }
+                }
+            }
+
+            let mut message = Message(String::new());
+            event.record(&mut message);
+            self.0
+                .lock()
+                .expect("Lock events")
+                .push((*event.metadata().level(), message.0));
+        }
+
+        fn enter(&self, _: &tracing::span::Id) {}
+
+        fn exit(&self, _: &tracing::span::Id) {}
+    }
+
+    #[tokio::test]
+    async fn crawl_decisions_logged() {
+        let (url, _) = serve_with_robots(Some("User-agent: *\nDisallow: /private")).await;
+        let root = serve(move |request| {
+            let body = if request.starts_with("GET /robots.txt") {
+                String::new()
+            } else {
+                format!(
+                    r#"<title>Root</title>
+                    <a href="{url}private/page">Private</a>
+                    <a href="/ad" rel="nofollow">Ad</a>
+                    <a href="http://127.0.0.1:1/down">Down</a>"#
+                )
+            };
+            http_response("200 OK", &[("Content-Type", "text/html")], body.as_bytes())
+        })
+        .await;
+
+        let events = CapturedEvents::default();
+        let _guard = tracing::subscriber::set_default(events.clone());
+
+        let mut crawler = WebCrawler::default();
+        crawler.policy.max_attempts = 1;
+        let key = crawler.enqueue(root);
+        crawler.parse_site(key).await.expect("Crawl root");
+        for key in crawler.site_queue.clone() {
+            crawler.parse_site(key).await.expect("Visit link");
+        }
+
+        assert!(events.logged(tracing::Level::DEBUG, "queued"));
+        assert!(events.logged(tracing::Level::INFO, "fetched"));
+        assert!(events.logged(tracing::Level::DEBUG, "link skipped, marked nofollow"));
+        assert!(events.logged(tracing::Level::DEBUG, "skipped, disallowed by robots.txt"));
+        assert!(events.logged(tracing::Level::WARN, "fetch failed"));
+    }
 }
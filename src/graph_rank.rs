@@ -1,12 +1,16 @@
 //! Given a graph of connections and probabilities that each node will move to a connected node,
 //! can find the steady state of the system and "rank" nodes
 
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use slotmap::{new_key_type, SlotMap};
 
 use crate::{
-    matrix::Matrix,
+    matrix::{Matrix, Stochastic},
     vector::{Probability, Vector},
 };
 
@@ -20,6 +24,12 @@ pub const RANDOM_WALK_CHANCE: f32 = 0.85;
 /// Probability the user may just click a random link instead
 pub const RANDOM_CLICK_AWAY_CHANCE: f32 = 0.15;
 
+/// Most power iteration steps taken before ranking is considered to have failed to converge
+pub const MAX_ITERATIONS: usize = 100;
+
+/// Total change in rank between steps under which power iteration has converged
+pub const CONVERGENCE_TOLERANCE: f32 = 1e-6;
+
 /// A graph holding connected nodes. Each node has a chance to move to another node or stay where
 /// it is, which can be represented as a stochastic matrix
 #[derive(Default)]
@@ -48,10 +58,8 @@ impl<ITEM: Default> ConnectionGraph<ITEM> {
         // Register all nodes to an ID
         let mut res = [Vector::default(); NODES];
         let mut indexes = HashMap::new();
-        let mut curr = 0;
-        for (key, _) in &self.nodes {
+        for (curr, key) in self.nodes.keys().enumerate() {
             indexes.insert(key, curr);
-            curr += 1
         }
 
         for (key, node) in &self.nodes {
@@ -59,25 +67,141 @@ impl<ITEM: Default> ConnectionGraph<ITEM> {
             let curr_vector = &mut res[curr_idx];
 
             for (conn_key, prob) in &node.connections {
-                curr_vector[indexes[&conn_key]] = *prob
+                curr_vector[indexes[conn_key]] = *prob
             }
         }
 
         Matrix::from_vectors(res)
     }
 
-    /// Gets the steady state solution to the stochastic representation of this graph
-    pub fn get_rank_vector<const NODES: usize>(&self) -> Option<Vector<NODES, Probability>> {
-        let matrix = (self.matrix_representation::<NODES>() * RANDOM_WALK_CHANCE
+    /// The stochastic matrix followed by a random surfer, who usually takes a link from the
+    /// current node but sometimes jumps to any node at random
+    pub fn damped_matrix<const NODES: usize>(&self) -> Option<Matrix<NODES, NODES, Stochastic>> {
+        (self.matrix_representation::<NODES>() * RANDOM_WALK_CHANCE
             + (Matrix::<NODES, NODES>::identity_filled(1f32 / NODES as f32)
                 * RANDOM_CLICK_AWAY_CHANCE))
-            .stochastic_matrix()?;
+            .stochastic_matrix()
+    }
+
+    /// Gets the steady state solution to the stochastic representation of this graph
+    pub fn get_rank_vector<const NODES: usize>(&self) -> Option<Vector<NODES, Probability>> {
+        self.damped_matrix::<NODES>()?.steady_state_solution()
+    }
 
-        matrix.steady_state_solution()
+    /// Approximates the steady state through power iteration seeded with a previous rank vector,
+    /// which converges in far fewer steps than starting from scratch when the graph has only
+    /// changed slightly since `prev` was computed. Returns the ranks and the steps taken
+    pub fn rank_vector_warm_start<const NODES: usize>(
+        &self,
+        prev: &Vector<NODES>,
+        iters: usize,
+        tolerance: f32,
+    ) -> Option<(Vector<NODES, Probability>, usize)> {
+        let start = (*prev * (1f32 / prev.sum())).probability_vector()?;
+
+        self.damped_matrix::<NODES>()?
+            .power_iteration(start, iters, tolerance)
+    }
+
+    /// Estimates how many steps a random surfer takes before where they are stops depending on
+    /// where they started. Power iteration is run from each node in turn, counting the steps until
+    /// the distribution is within `eps` of the steady state in total variation distance, and the
+    /// most steps taken from any node is returned. Returns `None` if there's no steady state or
+    /// some node takes more than [`MAX_ITERATIONS`] steps
+    pub fn mixing_time<const NODES: usize>(&self, eps: f32) -> Option<usize> {
+        let matrix = self.damped_matrix::<NODES>()?;
+        let steady_state = matrix.steady_state_solution()?;
+
+        let mut mixing_time = 0;
+        let mut next = Vector::zero_vector();
+        for start in 0..NODES {
+            let mut current: Vector<NODES> = Vector::zero_vector();
+            current[start] = 1f32;
+
+            let mut steps = 0;
+            while total_variation(&current.data, &steady_state.data) > eps {
+                if steps == MAX_ITERATIONS {
+                    return None;
+                }
+                matrix.gemv_into(&current, &mut next);
+                std::mem::swap(&mut current, &mut next);
+                steps += 1;
+            }
+            mixing_time = mixing_time.max(steps);
+        }
+
+        Some(mixing_time)
+    }
+
+    /// Estimates the steady state by simulating random surfers instead of solving for it. Each of
+    /// `walks` surfers starts on a random node and takes `walk_len` steps, following one of the
+    /// current node's links or jumping to any node at random, and a node's rank is the share of
+    /// steps that landed on it. Each step only looks at the current node's links, so no matrix is
+    /// built. Ranks are given in graph order, or `None` if no steps are taken or the graph isn't
+    /// stochastic
+    pub fn monte_carlo_rank(
+        &self,
+        walks: usize,
+        walk_len: usize,
+        seed: u64,
+    ) -> Option<Vec<(GraphKey, f32)>> {
+        let size = self.nodes.len();
+        if size == 0 || walks == 0 || walk_len == 0 || !self.is_stochastic() {
+            return None;
+        }
+
+        let indexes: HashMap<_, _> = self
+            .nodes
+            .keys()
+            .enumerate()
+            .map(|(idx, key)| (key, idx))
+            .collect();
+        let links: Vec<Vec<(usize, f32)>> = self
+            .nodes
+            .values()
+            .map(|node| {
+                node.connections
+                    .iter()
+                    .map(|(to, prob)| (indexes[to], *prob))
+                    .collect()
+            })
+            .collect();
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut visits = vec![0usize; size];
+        for _ in 0..walks {
+            let mut node = rng.gen_range(0..size);
+            for _ in 0..walk_len {
+                // Surfers on a node without links always jump
+                node = if links[node].is_empty() || !rng.gen_bool(RANDOM_WALK_CHANCE.into()) {
+                    rng.gen_range(0..size)
+                } else {
+                    follow(&links[node], &mut rng)
+                };
+                visits[node] += 1;
+            }
+        }
+
+        let steps = (walks * walk_len) as f32;
+        Some(
+            self.nodes
+                .keys()
+                .zip(visits)
+                .map(|(key, count)| (key, count as f32 / steps))
+                .collect(),
+        )
     }
 
     /// Returns a list from highest to lowest "rank" of nodes in the graph
     pub fn get_rankings<const NODES: usize>(&self) -> Option<Vec<GraphKey>> {
+        let res = self.get_scored_rankings::<NODES>()?;
+
+        Some(res.iter().map(|(key, _)| *key).collect())
+    }
+
+    /// Returns every node alongside its steady state probability, from highest to lowest with
+    /// ties in graph order
+    pub fn get_scored_rankings<const NODES: usize>(&self) -> Option<Vec<(GraphKey, f32)>> {
         let mut res = vec![];
         let rank_vector = self.get_rank_vector::<NODES>()?;
 
@@ -85,12 +209,172 @@ impl<ITEM: Default> ConnectionGraph<ITEM> {
             res.push((key, rank_vector[idx]));
         }
 
-        res.sort_by(|(_, prev_index), (_, index)| index.total_cmp(prev_index));
+        res.sort_by(|(prev_key, prev_index), (key, index)| {
+            index.total_cmp(prev_index).then_with(|| prev_key.cmp(key))
+        });
 
-        Some(res.iter().map(|(key, _)| *key).collect())
+        Some(res)
+    }
+
+    /// Finds the steady state of the graph through power iteration over its connections, for
+    /// graphs whose size is only known at runtime. Nodes without connections spread their rank
+    /// evenly across the graph. Returns every node with its rank in graph order, or `None` if a
+    /// node's connection probabilities don't sum to 1 or the ranks don't converge in time
+    pub fn power_iteration_ranks(
+        &self,
+        max_iterations: usize,
+        tolerance: f32,
+    ) -> Option<Vec<(GraphKey, f32)>> {
+        self.power_iteration_ranks_cancellable(max_iterations, tolerance, &AtomicBool::new(false))
+            .map(|(ranks, _)| ranks)
+    }
+
+    /// Whether every node with connections has connection probabilities summing to 1
+    pub fn is_stochastic(&self) -> bool {
+        self.nodes.values().all(|node| {
+            let total: f32 = node.connections.iter().map(|(_, prob)| prob).sum();
+            node.connections.is_empty() || (total - 1.0).abs() <= 0.01
+        })
+    }
+
+    /// Power iteration that gives up with `None` as soon as `cancel` is set, checked before every
+    /// step. Also returns how many steps it took to converge
+    pub fn power_iteration_ranks_cancellable(
+        &self,
+        max_iterations: usize,
+        tolerance: f32,
+        cancel: &AtomicBool,
+    ) -> Option<(Vec<(GraphKey, f32)>, usize)> {
+        let size = self.nodes.len();
+        self.iterate(
+            &vec![1f32 / size as f32; size],
+            max_iterations,
+            tolerance,
+            cancel,
+        )
+    }
+
+    /// Personalized PageRank, where random jumps and dangling nodes land on nodes in proportion to
+    /// their teleport weight rather than uniformly. Nodes not given a weight are never jumped to.
+    /// Returns `None` if no node has a positive weight, or for the same reasons as
+    /// [`ConnectionGraph::power_iteration_ranks`]
+    pub fn personalized_ranks(
+        &self,
+        teleport: &[(GraphKey, f32)],
+        max_iterations: usize,
+        tolerance: f32,
+    ) -> Option<Vec<(GraphKey, f32)>> {
+        let total: f32 = teleport.iter().map(|(_, weight)| weight.max(0f32)).sum();
+        if total <= 0f32 {
+            return None;
+        }
+
+        let weights: HashMap<_, _> = teleport.iter().copied().collect();
+        let teleport: Vec<_> = self
+            .nodes
+            .keys()
+            .map(|key| {
+                weights
+                    .get(&key)
+                    .map_or(0f32, |weight| weight.max(0f32) / total)
+            })
+            .collect();
+
+        self.iterate(
+            &teleport,
+            max_iterations,
+            tolerance,
+            &AtomicBool::new(false),
+        )
+        .map(|(ranks, _)| ranks)
+    }
+
+    /// A teleport distribution in graph order from scores keyed by node, such as topic weights.
+    /// Nodes without a score, or with a negative one, get none of the weight. Returns `None` if no
+    /// node has a positive score
+    pub fn teleport_from_scores<const NODES: usize>(
+        &self,
+        scores: &HashMap<GraphKey, f32>,
+    ) -> Option<Vector<NODES, Probability>> {
+        let mut teleport = Vector::<NODES>::zero_vector();
+        for (idx, key) in self.nodes.keys().take(NODES).enumerate() {
+            teleport[idx] = scores.get(&key).map_or(0f32, |score| score.max(0f32));
+        }
+
+        let total = teleport.sum();
+        if total <= 0f32 {
+            return None;
+        }
+
+        (teleport * (1f32 / total)).probability_vector()
+    }
+
+    /// Power iteration with random jumps landing on nodes by a teleport distribution given in
+    /// graph order
+    fn iterate(
+        &self,
+        teleport: &[f32],
+        max_iterations: usize,
+        tolerance: f32,
+        cancel: &AtomicBool,
+    ) -> Option<(Vec<(GraphKey, f32)>, usize)> {
+        if !self.is_stochastic() {
+            return None;
+        }
+
+        let size = self.nodes.len();
+        let indexes: HashMap<_, _> = self
+            .nodes
+            .keys()
+            .enumerate()
+            .map(|(idx, key)| (key, idx))
+            .collect();
+
+        let mut rank = vec![1f32 / size as f32; size];
+        for step in 1..=max_iterations {
+            if cancel.load(Ordering::Relaxed) {
+                return None;
+            }
+
+            let dangling: f32 = self
+                .nodes
+                .values()
+                .zip(&rank)
+                .filter(|(node, _)| node.connections.is_empty())
+                .map(|(_, rank)| rank)
+                .sum();
+
+            let jump = RANDOM_CLICK_AWAY_CHANCE + RANDOM_WALK_CHANCE * dangling;
+            let mut next: Vec<_> = teleport.iter().map(|weight| jump * weight).collect();
+
+            for (node, rank) in self.nodes.values().zip(&rank) {
+                for (to, prob) in &node.connections {
+                    next[indexes[to]] += RANDOM_WALK_CHANCE * rank * prob;
+                }
+            }
+
+            let change: f32 = next.iter().zip(&rank).map(|(a, b)| (a - b).abs()).sum();
+            rank = next;
+
+            if change < tolerance {
+                return Some((self.nodes.keys().zip(rank).collect(), step));
+            }
+        }
+
+        None
     }
 }
 
+/// Total variation distance between two distributions, half their total absolute difference
+fn total_variation(first: &[f32], second: &[f32]) -> f32 {
+    first
+        .iter()
+        .zip(second)
+        .map(|(first, second)| (first - second).abs())
+        .sum::<f32>()
+        / 2f32
+}
+
 /// A node in the graph containing probabilities that it moves to another node
 #[derive(Default)]
 pub struct Node<ITEM> {
@@ -98,11 +382,33 @@ pub struct Node<ITEM> {
     pub connections: Vec<(GraphKey, f32)>,
 }
 
+/// Picks one of a node's links at random, weighted by their probabilities
+fn follow<R: Rng>(links: &[(usize, f32)], rng: &mut R) -> usize {
+    let total: f32 = links.iter().map(|(_, prob)| prob).sum();
+    let mut target = rng.gen::<f32>() * total;
+    for (to, prob) in links {
+        if target < *prob {
+            return *to;
+        }
+        target -= prob;
+    }
+
+    // Rounding can leave a sliver past the last link
+    links[links.len() - 1].0
+}
+
 #[cfg(test)]
 mod tests {
+    use std::{
+        collections::HashMap,
+        sync::atomic::{AtomicBool, Ordering},
+        thread,
+        time::Duration,
+    };
+
     use crate::{matrix::Matrix, vector::Vector};
 
-    use super::ConnectionGraph;
+    use super::{ConnectionGraph, CONVERGENCE_TOLERANCE, MAX_ITERATIONS};
 
     #[test]
     fn graph_generates_proper_stochastic() {
@@ -188,4 +494,244 @@ mod tests {
 
         assert_eq!(rankings, &[b, c, a])
     }
+
+    #[test]
+    fn warm_start_converges_faster() {
+        let mut graph: ConnectionGraph<()> = ConnectionGraph::default();
+
+        let a = graph.register();
+        let b = graph.register();
+        let c = graph.register();
+
+        graph.connect(a, a, 0.5);
+        graph.connect(a, b, 0.25);
+        graph.connect(a, c, 0.25);
+
+        graph.connect(b, b, 0.8);
+        graph.connect(b, c, 0.2);
+
+        graph.connect(c, a, 0.35);
+        graph.connect(c, b, 0.65);
+
+        let uniform = Vector::from_data([1.0, 1.0, 1.0]);
+        let (previous, _) = graph
+            .rank_vector_warm_start::<3>(&uniform, 500, 1e-5)
+            .expect("Initial ranks");
+
+        // Add an edge from b back to a
+        graph.nodes[b].connections = vec![(b, 0.75), (c, 0.2), (a, 0.05)];
+
+        let (cold, cold_steps) = graph
+            .rank_vector_warm_start::<3>(&uniform, 500, 1e-5)
+            .expect("Cold start");
+        let (warm, warm_steps) = graph
+            .rank_vector_warm_start::<3>(&previous.resize(), 500, 1e-5)
+            .expect("Warm start");
+
+        assert!(warm_steps < cold_steps);
+        for i in 0..3 {
+            assert!((warm[i] - cold[i]).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn leaky_connections_rejected() {
+        let mut graph: ConnectionGraph<()> = ConnectionGraph::default();
+        let a = graph.register();
+        let b = graph.register();
+        graph.connect(a, b, 0.5);
+
+        assert!(!graph.is_stochastic());
+        assert!(graph.power_iteration_ranks(100, 1e-6).is_none());
+
+        graph.connect(a, a, 0.5);
+        assert!(graph.is_stochastic());
+    }
+
+    #[test]
+    fn cancelled_power_iteration_stops() {
+        let mut graph: ConnectionGraph<()> = ConnectionGraph::default();
+        let a = graph.register();
+        let b = graph.register();
+        graph.connect(a, b, 1.0);
+        graph.connect(b, a, 1.0);
+
+        let cancel = AtomicBool::new(true);
+        assert!(graph
+            .power_iteration_ranks_cancellable(100, 1e-6, &cancel)
+            .is_none());
+        assert!(graph.power_iteration_ranks(100, 1e-6).is_some());
+
+        // A tolerance of 0 is never reached, so only cancelling can end this many steps
+        let cancel = AtomicBool::new(false);
+        let stopped = thread::scope(|scope| {
+            let ranking =
+                scope.spawn(|| graph.power_iteration_ranks_cancellable(usize::MAX, 0.0, &cancel));
+            thread::sleep(Duration::from_millis(10));
+            cancel.store(true, Ordering::Relaxed);
+            ranking.join().expect("Ranking thread")
+        });
+        assert!(stopped.is_none());
+    }
+
+    #[test]
+    fn power_iteration_matches_steady_state() {
+        let mut graph: ConnectionGraph<()> = ConnectionGraph::default();
+
+        let a = graph.register();
+        let b = graph.register();
+        let c = graph.register();
+
+        graph.connect(a, a, 0.5);
+        graph.connect(a, b, 0.25);
+        graph.connect(a, c, 0.25);
+
+        graph.connect(b, b, 0.8);
+        graph.connect(b, c, 0.2);
+
+        graph.connect(c, a, 0.35);
+        graph.connect(c, b, 0.65);
+
+        let exact = graph.get_rank_vector::<3>().expect("Steady state");
+        let ranks = graph
+            .power_iteration_ranks(MAX_ITERATIONS, CONVERGENCE_TOLERANCE)
+            .expect("Converge");
+
+        for (idx, (_, rank)) in ranks.iter().enumerate() {
+            assert!((rank - exact[idx]).abs() < 1e-4)
+        }
+    }
+
+    #[test]
+    fn monte_carlo_approximates_steady_state() {
+        let mut graph: ConnectionGraph<()> = ConnectionGraph::default();
+
+        let a = graph.register();
+        let b = graph.register();
+        let c = graph.register();
+
+        graph.connect(a, a, 0.5);
+        graph.connect(a, b, 0.25);
+        graph.connect(a, c, 0.25);
+
+        graph.connect(b, b, 0.8);
+        graph.connect(b, c, 0.2);
+
+        graph.connect(c, a, 0.35);
+        graph.connect(c, b, 0.65);
+
+        let exact = graph.get_rank_vector::<3>().expect("Steady state");
+        let estimate = graph
+            .monte_carlo_rank(2_000, 50, 7)
+            .expect("Simulate surfers");
+
+        for (i, (_, rank)) in estimate.iter().enumerate() {
+            assert!(
+                (rank - exact[i]).abs() < 0.02,
+                "{rank} estimated against {}",
+                exact[i]
+            );
+        }
+        assert_eq!(graph.monte_carlo_rank(2_000, 50, 7), Some(estimate));
+        assert!(graph.monte_carlo_rank(0, 50, 7).is_none());
+
+        // Surfers stuck on a node without links jump anywhere, as power iteration assumes
+        let mut dangling: ConnectionGraph<()> = ConnectionGraph::default();
+        let a = dangling.register();
+        let b = dangling.register();
+        dangling.connect(a, b, 1.0);
+        let exact = dangling
+            .power_iteration_ranks(MAX_ITERATIONS, CONVERGENCE_TOLERANCE)
+            .expect("Converge");
+        let estimate = dangling
+            .monte_carlo_rank(2_000, 50, 7)
+            .expect("Simulate surfers");
+        for ((_, exact), (_, rank)) in exact.iter().zip(&estimate) {
+            assert!(
+                (rank - exact).abs() < 0.02,
+                "{rank} estimated against {exact}"
+            );
+        }
+    }
+
+    #[test]
+    fn mixing_time_reflects_periodicity() {
+        // Every node links to every node, so one step from anywhere lands on the steady state
+        let mut complete: ConnectionGraph<()> = ConnectionGraph::default();
+        let nodes: Vec<_> = (0..3).map(|_| complete.register()).collect();
+        for from in &nodes {
+            for to in &nodes {
+                complete.connect(*from, *to, 1f32 / 3f32);
+            }
+        }
+        let fast = complete.mixing_time::<3>(0.01).expect("Mixes");
+        assert!(fast <= 1);
+
+        // A surfer bounces between two nodes, only settling down through random jumps
+        let mut cycle: ConnectionGraph<()> = ConnectionGraph::default();
+        let a = cycle.register();
+        let b = cycle.register();
+        cycle.connect(a, b, 1f32);
+        cycle.connect(b, a, 1f32);
+        let slow = cycle.mixing_time::<2>(0.01).expect("Mixes");
+        assert!(slow > 10 * fast.max(1));
+
+        // Tighter bounds take longer to reach
+        assert!(cycle.mixing_time::<2>(0.001).expect("Mixes") > slow);
+        assert!(cycle.mixing_time::<2>(1e-12).is_none());
+    }
+
+    #[test]
+    fn personalized_ranks_favor_teleport_target() {
+        let mut graph: ConnectionGraph<()> = ConnectionGraph::default();
+        let a = graph.register();
+        let b = graph.register();
+        let c = graph.register();
+
+        // A symmetric ring ranks every node equally unless jumps favor one
+        graph.connect(a, b, 1.0);
+        graph.connect(b, c, 1.0);
+        graph.connect(c, a, 1.0);
+
+        let ranks = graph
+            .personalized_ranks(&[(a, 1.0)], MAX_ITERATIONS, CONVERGENCE_TOLERANCE)
+            .expect("Converge");
+        let rank = |node| ranks.iter().find(|(key, _)| *key == node).unwrap().1;
+
+        assert!(rank(a) > rank(b) && rank(b) > rank(c));
+        assert!((ranks.iter().map(|(_, rank)| rank).sum::<f32>() - 1.0).abs() < 1e-4);
+        assert!(graph
+            .personalized_ranks(&[], MAX_ITERATIONS, CONVERGENCE_TOLERANCE)
+            .is_none());
+    }
+
+    #[test]
+    fn teleport_built_from_scores() {
+        let mut graph: ConnectionGraph<()> = ConnectionGraph::default();
+        let nodes: Vec<_> = (0..4).map(|_| graph.register()).collect();
+
+        let scores = HashMap::from([(nodes[1], 3.0), (nodes[3], 1.0), (nodes[2], -2.0)]);
+        let teleport = graph
+            .teleport_from_scores::<4>(&scores)
+            .expect("Teleport vector");
+
+        assert!((teleport.sum() - 1.0).abs() < 1e-6);
+        assert_eq!(teleport.data, [0.0, 0.75, 0.0, 0.25]);
+        assert!(graph
+            .teleport_from_scores::<4>(&HashMap::from([(nodes[0], 0.0)]))
+            .is_none());
+    }
+
+    #[test]
+    fn power_iteration_rejects_invalid_probabilities() {
+        let mut graph: ConnectionGraph<()> = ConnectionGraph::default();
+
+        let a = graph.register();
+        let b = graph.register();
+        graph.connect(a, b, 0.5);
+
+        assert!(graph
+            .power_iteration_ranks(MAX_ITERATIONS, CONVERGENCE_TOLERANCE)
+            .is_none())
+    }
 }
@@ -0,0 +1,258 @@
+//! Inverted index from title tokens to the sites whose titles contain them, allowing term lookups
+//! without scanning the whole registry
+
+use std::{collections::HashMap, ops::Range};
+
+use serde::{Deserialize, Serialize};
+use slotmap::SlotMap;
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+use crate::crawler::{SiteKey, SiteLog};
+
+/// Folds text for case and accent insensitive comparison: compatibility decomposes it, strips
+/// combining accents and lowercases what's left, so "Zürich" and "ZURICH" both become "zurich"
+pub fn fold(text: &str) -> String {
+    let mut folded = String::with_capacity(text.len());
+    for c in text.chars() {
+        fold_char(c, &mut folded);
+    }
+    folded
+}
+
+/// Appends the folded form of a single character, which may be empty or several characters long
+pub fn fold_char(c: char, folded: &mut String) {
+    for decomposed in std::iter::once(c).nfkd().filter(|c| !is_combining_mark(*c)) {
+        match decomposed {
+            // Case folding rather than lowercasing, and Turkish dotless i is matched as a plain i
+            'ß' | 'ẞ' => folded.push_str("ss"),
+            'ı' => folded.push('i'),
+            _ => folded.extend(decomposed.to_lowercase()),
+        }
+    }
+}
+
+/// Folded text that remembers which original characters each part of it came from, so matches
+/// found in the folded text can be mapped back onto the original
+#[derive(Clone, Debug, PartialEq)]
+pub struct FoldedText {
+    pub folded: String,
+    /// Start in `folded` and byte range in the original text of every character that folds to
+    /// something, in order
+    origins: Vec<(usize, Range<usize>)>,
+}
+
+impl FoldedText {
+    pub fn new(text: &str) -> Self {
+        let mut folded = String::with_capacity(text.len());
+        let mut origins = vec![];
+
+        for (idx, c) in text.char_indices() {
+            let start = folded.len();
+            fold_char(c, &mut folded);
+            if folded.len() > start {
+                origins.push((start, idx..idx + c.len_utf8()));
+            }
+        }
+
+        Self { folded, origins }
+    }
+
+    /// Byte ranges in the original text of every non-overlapping occurrence of a folded needle
+    pub fn find_all(&self, needle: &str) -> Vec<Range<usize>> {
+        if needle.is_empty() {
+            return vec![];
+        }
+
+        self.folded
+            .match_indices(needle)
+            .map(|(start, found)| self.original(start..start + found.len()))
+            .collect()
+    }
+
+    /// Widens a folded byte range to cover every original character that produced it
+    fn original(&self, folded: Range<usize>) -> Range<usize> {
+        let first = self
+            .origins
+            .partition_point(|(start, _)| *start <= folded.start)
+            - 1;
+        let last = self
+            .origins
+            .partition_point(|(start, _)| *start < folded.end)
+            - 1;
+
+        self.origins[first].1.start..self.origins[last].1.end
+    }
+}
+
+/// Sorts ranges and merges any that overlap or touch
+pub fn merge_ranges(mut ranges: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    ranges.sort_by_key(|range| range.start);
+
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+
+    merged
+}
+
+/// Splits text into folded tokens, treating anything that isn't alphanumeric as a separator
+pub fn tokenize(text: &str) -> Vec<String> {
+    fold(text)
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|token| !token.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Maps every title token to the sites containing it, in registry order
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct InvertedIndex {
+    postings: HashMap<String, Vec<SiteKey>>,
+}
+
+impl InvertedIndex {
+    /// Builds an index over the titles of every site in a registry
+    pub fn build(sites: &SlotMap<SiteKey, SiteLog>) -> Self {
+        let mut index = Self::default();
+
+        for (key, site) in sites {
+            index.insert(key, &site.title);
+        }
+
+        index
+    }
+
+    /// Adds a site's title tokens to the index
+    pub fn insert(&mut self, key: SiteKey, title: &str) {
+        let mut tokens = tokenize(title);
+        tokens.sort_unstable();
+        tokens.dedup();
+
+        for token in tokens {
+            self.postings.entry(token).or_default().push(key);
+        }
+    }
+
+    /// Removes a site from the postings of its title's tokens, dropping tokens no site has left
+    pub fn remove(&mut self, key: SiteKey, title: &str) {
+        for token in tokenize(title) {
+            if let Some(keys) = self.postings.get_mut(&token) {
+                keys.retain(|posted| *posted != key);
+                if keys.is_empty() {
+                    self.postings.remove(&token);
+                }
+            }
+        }
+    }
+
+    /// Gets every site whose title contains the token
+    pub fn get(&self, token: &str) -> Option<&[SiteKey]> {
+        self.postings.get(token).map(Vec::as_slice)
+    }
+
+    /// Number of distinct tokens in the index
+    pub fn len(&self) -> usize {
+        self.postings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.postings.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use slotmap::SlotMap;
+
+    use crate::crawler::SiteLog;
+
+    use super::{fold, merge_ranges, tokenize, FoldedText, InvertedIndex};
+
+    #[test]
+    fn tokens_are_lowercased_and_stripped() {
+        assert_eq!(
+            tokenize("Rust (programming language) - Wikipedia"),
+            ["rust", "programming", "language", "wikipedia"]
+        )
+    }
+
+    #[test]
+    fn accents_and_case_folded() {
+        assert_eq!(fold("Zürich"), "zurich");
+        assert_eq!(fold("ZÜRICH"), "zurich");
+        assert_eq!(fold("Café Crème"), "cafe creme");
+        assert_eq!(fold("İstanbul"), "istanbul");
+        assert_eq!(fold("Diyarbakır"), "diyarbakir");
+        assert_eq!(fold("Straße"), "strasse");
+        assert_eq!(fold("ﬁle"), "file");
+    }
+
+    #[test]
+    fn ascii_folding_unchanged() {
+        let text = "Rust (Programming Language) 2024!";
+        assert_eq!(fold(text), text.to_lowercase());
+    }
+
+    #[test]
+    fn folded_matches_map_to_original_bytes() {
+        let text = "Straße in ZÜRICH";
+        let folded = FoldedText::new(text);
+
+        let zurich = folded.find_all("zurich");
+        assert_eq!(zurich.len(), 1);
+        assert_eq!(&text[zurich[0].clone()], "ZÜRICH");
+
+        // Half of a character folding to several still covers the whole character
+        let sharp = folded.find_all("s");
+        assert_eq!(&text[sharp[1].clone()], "ß");
+    }
+
+    #[test]
+    fn overlapping_ranges_merged() {
+        assert_eq!(merge_ranges(vec![5..8, 0..2, 1..4, 8..9]), [0..4, 5..9]);
+    }
+
+    #[test]
+    fn index_maps_tokens_to_sites() {
+        let mut sites = SlotMap::default();
+        let rust = sites.insert(SiteLog {
+            title: "Rust, Rust and more Rust".to_string(),
+            ..Default::default()
+        });
+        let python = sites.insert(SiteLog {
+            title: "Python and Rust".to_string(),
+            ..Default::default()
+        });
+
+        let index = InvertedIndex::build(&sites);
+
+        assert_eq!(index.get("rust"), Some(&[rust, python][..]));
+        assert_eq!(index.get("python"), Some(&[python][..]));
+        assert_eq!(index.get("java"), None);
+        assert_eq!(index.len(), 4);
+    }
+
+    #[test]
+    fn removed_sites_leave_index() {
+        let mut sites = SlotMap::default();
+        let rust = sites.insert(SiteLog {
+            title: "Rust".to_string(),
+            ..Default::default()
+        });
+        let python = sites.insert(SiteLog {
+            title: "Python and Rust".to_string(),
+            ..Default::default()
+        });
+        let mut index = InvertedIndex::build(&sites);
+
+        index.remove(python, "Python and Rust");
+
+        assert_eq!(index.get("rust"), Some(&[rust][..]));
+        assert_eq!(index.get("python"), None);
+        assert_eq!(index.len(), 1);
+    }
+}
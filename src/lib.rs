@@ -1,7 +1,15 @@
 //! Linear Algebra and web crawling definitions
 
+pub mod config;
 pub mod crawler;
 pub mod graph_rank;
+pub mod index;
 pub mod matrix;
 pub mod page_rank;
+pub mod query;
+pub mod relevance;
+pub mod robots;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod snippet;
 pub mod vector;
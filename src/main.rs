@@ -3,16 +3,60 @@ use crossterm::{
     execute,
     terminal::{disable_raw_mode, enable_raw_mode},
 };
-use page_rank_from_scratch::{crawler::WebCrawler, page_rank::PageRanker};
+use page_rank_from_scratch::{
+    crawler::{content_hash, host_of, SiteKey, WebCrawler},
+    page_rank::{
+        PageRanker, SearchError, SearchHandle, SearchOptions, SearchOutcome, SearchResult,
+    },
+    snippet::DESCRIPTION_LEN,
+};
 use ratatui::{
     backend::CrosstermBackend,
     layout::{Alignment, Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
-    text::Text,
+    text::{Line, Span, Text},
     widgets::{Block, Borders, Paragraph},
     Terminal,
 };
-use std::{io, rc::Rc};
+use std::{collections::HashSet, io, ops::Range, rc::Rc, sync::Arc, time::Duration};
+
+/// How many related sites are shown for a result
+const RELATED_TO_SHOW: usize = 25;
+
+/// Border colors results are given by host, leaving out the colors marking selected and marked
+/// results
+const HOST_PALETTE: [Color; 6] = [
+    Color::Yellow,
+    Color::Green,
+    Color::Cyan,
+    Color::LightRed,
+    Color::LightGreen,
+    Color::LightCyan,
+];
+
+/// The contents of a result box
+pub struct ResultEntry {
+    /// The site shown, or `None` for a placeholder message
+    key: Option<SiteKey>,
+    title: String,
+    /// Parts of the title that matched the search
+    highlights: Vec<Range<usize>>,
+    url: String,
+    /// A line describing the site
+    summary: String,
+}
+
+impl From<&SearchResult<'_>> for ResultEntry {
+    fn from(result: &SearchResult<'_>) -> Self {
+        Self {
+            key: Some(result.key),
+            title: result.site.title.clone(),
+            highlights: result.title_highlights.clone(),
+            url: result.site.url.clone(),
+            summary: result.summary(DESCRIPTION_LEN).unwrap_or_default(),
+        }
+    }
+}
 
 pub enum Mode {
     Normal,
@@ -22,7 +66,7 @@ pub enum Mode {
 fn main() -> io::Result<()> {
     let page_registry = WebCrawler::load("10_000_wiki_entries.json")
         .expect("Failed to load page registry from file");
-    let pageranker = PageRanker::from_registry(page_registry);
+    let pageranker = Arc::new(PageRanker::from_registry(page_registry));
 
     let title_card = r#"
   _                       _      
@@ -41,11 +85,44 @@ fn main() -> io::Result<()> {
     let mut terminal = Terminal::new(backend)?;
 
     let mut input = String::new();
-    let mut search_results: Vec<(&str, &str)> = vec![];
+    let mut search_results: Vec<ResultEntry> = vec![];
+    let mut search: Option<SearchHandle> = None;
     let mut selected = 0;
+    let mut marked: HashSet<usize> = HashSet::new();
+    let mut pending_open: Vec<String> = vec![];
+    let mut status = String::new();
     let mut mode = Mode::Normal;
+    let mut color_hosts = false;
 
     loop {
+        if let Some(outcome) = search.as_mut().and_then(SearchHandle::try_poll) {
+            search_results = match outcome {
+                SearchOutcome::Ranked { results, .. }
+                | SearchOutcome::GlobalTop { results, .. }
+                    if !results.is_empty() =>
+                {
+                    mode = Mode::Normal;
+                    selected = 0;
+                    results.iter().map(ResultEntry::from).collect()
+                }
+                // Searches that match nothing finish with no results rather than `NoMatches`
+                SearchOutcome::Ranked { .. }
+                | SearchOutcome::GlobalTop { .. }
+                | SearchOutcome::Failed(SearchError::NoMatches) => {
+                    message("No results found", "Try a different query.")
+                }
+                SearchOutcome::Failed(err @ SearchError::RankingFailed(_)) => {
+                    message("Ranking failed", &err.to_string())
+                }
+                SearchOutcome::Failed(err @ SearchError::InvalidQuery { .. }) => {
+                    message("Invalid search", &err.to_string())
+                }
+                SearchOutcome::Cancelled => message("Search cancelled", ""),
+            };
+            marked.clear();
+            search = None;
+        }
+
         terminal.draw(|frame| {
             let size = frame.area();
 
@@ -56,6 +133,7 @@ fn main() -> io::Result<()> {
                         Constraint::Length(9), // Title
                         Constraint::Length(3), // Input
                         Constraint::Min(1),    // Results
+                        Constraint::Length(1), // Status
                     ]
                     .as_ref(),
                 )
@@ -71,6 +149,14 @@ fn main() -> io::Result<()> {
                 .alignment(Alignment::Center);
             frame.render_widget(title, chunks[0]);
 
+            let result_count = search_results
+                .iter()
+                .filter(|entry| entry.key.is_some())
+                .count();
+            let status_bar = Paragraph::new(status_bar(&mode, result_count, marked.len(), &status))
+                .style(Style::default().fg(Color::White).bg(Color::DarkGray));
+            frame.render_widget(status_bar, chunks[3]);
+
             let result_area = chunks[2];
             let visible_results = 8;
             let total_results = search_results.len();
@@ -89,27 +175,50 @@ fn main() -> io::Result<()> {
 
             let result_chunks = create_fixed_chunks(visible_results, result_area);
 
-            for (i, (title, url)) in results_to_display.iter().enumerate() {
-                let box_content = format!("{}\n{}", title, url);
+            for (i, entry) in results_to_display.iter().enumerate() {
+                let box_content = Text::from(vec![
+                    highlighted_line(&entry.title, &entry.highlights),
+                    Line::from(entry.url.as_str()),
+                    Line::from(entry.summary.as_str()),
+                ]);
 
+                let host = host_of(&entry.url).filter(|_| color_hosts && entry.key.is_some());
                 let color = if i + start == selected {
                     Color::Blue
+                } else if marked.contains(&(i + start)) {
+                    Color::Magenta
                 } else {
-                    Color::Yellow
+                    host.as_deref().map_or(Color::Yellow, host_color)
                 };
 
-                let result_box = Paragraph::new(Text::from(box_content)).block(
+                let result_box = Paragraph::new(box_content).block(
                     Block::default()
                         .borders(Borders::ALL)
-                        .title("Result")
+                        .title(host.unwrap_or_else(|| String::from("Result")))
                         .style(Style::default().fg(color).add_modifier(Modifier::BOLD)),
                 );
                 frame.render_widget(result_box, result_chunks[i]);
             }
         })?;
 
-        // Handle events
+        // Handle events, waking up regularly to check on a running search
+        if !event::poll(Duration::from_millis(50))? {
+            continue;
+        }
+
         if let Event::Key(key) = event::read()? {
+            // Any key besides `y` backs out of opening sites
+            if !pending_open.is_empty() {
+                status = if key.code == KeyCode::Char('y') {
+                    open_all(&pending_open, |url| open::that(url))
+                } else {
+                    String::from("Cancelled opening")
+                };
+                pending_open.clear();
+                continue;
+            }
+            status.clear();
+
             match mode {
                 Mode::Insert => match key.code {
                     KeyCode::Char(c) => input.push(c),
@@ -117,50 +226,46 @@ fn main() -> io::Result<()> {
                         input.pop();
                     }
                     KeyCode::Enter => {
-                        if let Some(rankings) = pageranker.search(input.trim()) {
-                            search_results = rankings
-                                .iter()
-                                .map(|site| (site.title.as_str(), site.url.as_str()))
-                                .collect();
-
-                            mode = Mode::Normal;
-                            selected = 0;
-                        } else {
-                            search_results = vec![("No results found", "Try a different query.")];
-                        }
-                    }
-                    KeyCode::Up => {
-                        if selected > 0 {
-                            selected -= 1;
-                        }
-                    }
-                    KeyCode::Down => {
-                        if selected < search_results.len() {
-                            selected += 1;
-                        }
+                        // Replacing a running search drops, and so cancels, its handle
+                        search =
+                            Some(pageranker.search_spawn(input.trim(), SearchOptions::default()));
+                        search_results = message("Searching...", "");
+                        marked.clear();
+                        selected = 0;
                     }
+                    KeyCode::Up => selected = selected.saturating_sub(1),
+                    KeyCode::Down if selected < search_results.len() => selected += 1,
                     KeyCode::Esc => mode = Mode::Normal,
                     _ => {}
                 },
                 Mode::Normal => match key.code {
                     KeyCode::Char(c) => match c {
                         'q' => break,
-                        'j' => {
-                            if selected < search_results.len() - 1 {
-                                selected += 1;
-                            }
-                        }
-                        'k' => {
-                            if selected > 0 {
-                                selected -= 1;
+                        'j' if selected + 1 < search_results.len() => selected += 1,
+                        'k' => selected = selected.saturating_sub(1),
+                        'i' => mode = Mode::Insert,
+                        'c' => color_hosts = !color_hosts,
+                        ' ' if selected < search_results.len() => toggle(&mut marked, selected),
+                        'r' if search.is_none() => {
+                            if let Some(key) = search_results.get(selected).and_then(|e| e.key) {
+                                let related = pageranker.related(key, RELATED_TO_SHOW);
+                                search_results = if related.is_empty() {
+                                    message("No related sites", "This site isn't linked with any.")
+                                } else {
+                                    related.iter().map(ResultEntry::from).collect()
+                                };
+                                marked.clear();
+                                selected = 0;
                             }
                         }
-                        'i' => mode = Mode::Insert,
                         _ => {}
                     },
-                    KeyCode::Enter => {
-                        let url = &search_results[selected].1;
-                        open::that(url).expect("Failed to open");
+                    KeyCode::Enter if search.is_none() => {
+                        pending_open = urls_to_open(&search_results, &marked, selected);
+                        if !pending_open.is_empty() {
+                            status =
+                                format!("Open {} in browser? (y/n)", sites(pending_open.len()));
+                        }
                     }
                     _ => {}
                 },
@@ -176,6 +281,134 @@ fn main() -> io::Result<()> {
     Ok(())
 }
 
+/// Keys that do something in a mode
+fn key_hints(mode: &Mode) -> &'static str {
+    match mode {
+        Mode::Normal => {
+            "i: search  j/k: move  space: mark  enter: open  r: related  c: color hosts  q: quit"
+        }
+        Mode::Insert => "type to search  enter: run search  up/down: move  esc: stop typing",
+    }
+}
+
+/// The status bar's text: the mode, how many results are showing and any message, or the mode's
+/// key hints when there's nothing to report
+fn status_bar(mode: &Mode, results: usize, marked: usize, message: &str) -> String {
+    let mode_name = match mode {
+        Mode::Normal => "NORMAL",
+        Mode::Insert => "INSERT",
+    };
+    let mut count = match results {
+        1 => String::from("1 result"),
+        _ => format!("{results} results"),
+    };
+    if marked > 0 {
+        count.push_str(&format!(" ({marked} marked)"));
+    }
+    let detail = if message.is_empty() {
+        key_hints(mode)
+    } else {
+        message
+    };
+
+    format!(" {mode_name} | {count} | {detail}")
+}
+
+/// Marks a result if it isn't already, and unmarks it otherwise
+fn toggle(marked: &mut HashSet<usize>, idx: usize) {
+    if !marked.remove(&idx) {
+        marked.insert(idx);
+    }
+}
+
+/// URLs of the marked results in order, or of the selected result if none are marked.
+/// Placeholder messages are never opened
+fn urls_to_open(results: &[ResultEntry], marked: &HashSet<usize>, selected: usize) -> Vec<String> {
+    let chosen: Vec<_> = if marked.is_empty() {
+        results.get(selected).into_iter().collect()
+    } else {
+        results
+            .iter()
+            .enumerate()
+            .filter(|(idx, _)| marked.contains(idx))
+            .map(|(_, entry)| entry)
+            .collect()
+    };
+
+    chosen
+        .into_iter()
+        .filter(|entry| entry.key.is_some())
+        .map(|entry| entry.url.clone())
+        .collect()
+}
+
+/// Opens every URL with an opener, carrying on past failures, and describes how it went
+fn open_all<F>(urls: &[String], mut opener: F) -> String
+where
+    F: FnMut(&str) -> io::Result<()>,
+{
+    let failures: Vec<_> = urls
+        .iter()
+        .filter_map(|url| opener(url).err().map(|err| format!("{url}: {err}")))
+        .collect();
+
+    match failures.as_slice() {
+        [] => format!("Opened {}", sites(urls.len())),
+        [failure] => format!("Failed to open {failure}"),
+        [failure, ..] => format!(
+            "Failed to open {} of {}, including {failure}",
+            failures.len(),
+            sites(urls.len())
+        ),
+    }
+}
+
+/// A count of sites with the right plural
+fn sites(count: usize) -> String {
+    if count == 1 {
+        String::from("1 site")
+    } else {
+        format!("{count} sites")
+    }
+}
+
+/// A single placeholder result box explaining why there are no results to show
+fn message(title: &str, detail: &str) -> Vec<ResultEntry> {
+    vec![ResultEntry {
+        key: None,
+        title: title.to_string(),
+        highlights: vec![],
+        url: detail.to_string(),
+        summary: String::new(),
+    }]
+}
+
+/// Underlines the highlighted byte ranges of a line of text
+fn highlighted_line<'a>(text: &'a str, highlights: &[Range<usize>]) -> Line<'a> {
+    let mut spans = vec![];
+    let mut written = 0;
+
+    for range in highlights {
+        spans.push(Span::raw(&text[written..range.start]));
+        spans.push(Span::styled(
+            &text[range.clone()],
+            Style::default().add_modifier(Modifier::UNDERLINED),
+        ));
+        written = range.end;
+    }
+    spans.push(Span::raw(&text[written..]));
+
+    Line::from(spans)
+}
+
+/// The border color of results from a host, picked by hashing the host so it's the same on every
+/// run
+fn host_color(host: &str) -> Color {
+    // The content hash is fixed between releases, unlike the standard library's hasher
+    let hash = content_hash(host);
+    HOST_PALETTE[(hash % HOST_PALETTE.len() as u64) as usize]
+}
+
 /// Creates fixed chunks for a fixed number of results
 fn create_fixed_chunks(visible_results: usize, area: Rect) -> Rc<[Rect]> {
     Layout::default()
@@ -183,3 +416,103 @@ fn create_fixed_chunks(visible_results: usize, area: Rect) -> Rc<[Rect]> {
         .constraints(vec![Constraint::Length(7); visible_results])
         .split(area)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, io};
+
+    use ratatui::style::Color;
+    use slotmap::KeyData;
+
+    use super::{
+        host_color, key_hints, message, open_all, status_bar, urls_to_open, Mode, ResultEntry,
+        HOST_PALETTE,
+    };
+
+    fn entries(urls: &[&str]) -> Vec<ResultEntry> {
+        urls.iter()
+            .enumerate()
+            .map(|(idx, url)| ResultEntry {
+                key: Some(KeyData::from_ffi(idx as u64 + 1).into()),
+                title: String::new(),
+                highlights: vec![],
+                url: url.to_string(),
+                summary: String::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn status_bar_hints_per_mode() {
+        assert_eq!(
+            status_bar(&Mode::Normal, 12, 0, ""),
+            format!(" NORMAL | 12 results | {}", key_hints(&Mode::Normal))
+        );
+        assert!(status_bar(&Mode::Insert, 1, 0, "").starts_with(" INSERT | 1 result | "));
+        assert!(key_hints(&Mode::Insert).contains("esc"));
+        assert_ne!(key_hints(&Mode::Normal), key_hints(&Mode::Insert));
+    }
+
+    #[test]
+    fn status_messages_replace_hints() {
+        assert_eq!(
+            status_bar(&Mode::Normal, 3, 2, "Opened 2 sites"),
+            " NORMAL | 3 results (2 marked) | Opened 2 sites"
+        );
+    }
+
+    #[test]
+    fn marked_results_opened_instead_of_selected() {
+        let results = entries(&["https://a.com", "https://b.com", "https://c.com"]);
+
+        assert_eq!(
+            urls_to_open(&results, &HashSet::new(), 1),
+            ["https://b.com"]
+        );
+        assert_eq!(
+            urls_to_open(&results, &HashSet::from([2, 0]), 1),
+            ["https://a.com", "https://c.com"]
+        );
+        assert!(urls_to_open(&message("No results found", ""), &HashSet::new(), 0).is_empty());
+    }
+
+    #[test]
+    fn hosts_colored_consistently() {
+        let hosts = [
+            "en.wikipedia.org",
+            "rust-lang.org",
+            "doc.rust-lang.org",
+            "github.com",
+            "example.com",
+            "news.ycombinator.com",
+        ];
+        let colors: HashSet<_> = hosts.iter().map(|host| host_color(host)).collect();
+
+        assert_eq!(host_color("rust-lang.org"), host_color("rust-lang.org"));
+        assert!(colors.len() > 1);
+        assert!(colors.iter().all(|color| HOST_PALETTE.contains(color)));
+        assert!(!colors.contains(&Color::Blue) && !colors.contains(&Color::Magenta));
+    }
+
+    #[test]
+    fn open_failures_reported_not_panicked() {
+        let urls = ["https://a.com", "https://b.com"].map(String::from);
+
+        let mut opened = vec![];
+        let status = open_all(&urls, |url| {
+            opened.push(url.to_string());
+            Ok(())
+        });
+        assert_eq!(status, "Opened 2 sites");
+        assert_eq!(opened, urls);
+
+        let status = open_all(&urls, |url| {
+            if url.contains('b') {
+                Err(io::Error::new(io::ErrorKind::NotFound, "no browser"))
+            } else {
+                Ok(())
+            }
+        });
+        assert_eq!(status, "Failed to open https://b.com: no browser");
+    }
+}
@@ -1,12 +1,12 @@
 //! Matrix struct definition and operator implementation methods
 
 use std::{
-    fmt::Debug,
+    fmt::{self, Debug},
     marker::PhantomData,
     ops::{Index, IndexMut},
 };
 
-use crate::vector::{Probability, Vector};
+use crate::vector::{LengthMismatch, Probability, Vector};
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct General;
@@ -72,6 +72,89 @@ impl<const M: usize, const N: usize, TYPE: Debug + Copy, OTHER: Debug + Copy>
     }
 }
 
+/// A stochastic matrix maps probability vectors to probability vectors, as every column sums to 1
+impl<const N: usize> std::ops::Mul<Vector<N, Probability>> for Matrix<N, N, Stochastic> {
+    type Output = Vector<N, Probability>;
+    fn mul(self, rhs: Vector<N, Probability>) -> Self::Output {
+        Vector::from_raw(self.multiply_vector(&rhs).data)
+    }
+}
+
+/// Rows of values had a different shape than the matrix they were converted to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ShapeMismatch {
+    /// There were the wrong number of rows
+    Rows(LengthMismatch),
+    /// A row had the wrong number of columns
+    Columns {
+        row: usize,
+        mismatch: LengthMismatch,
+    },
+}
+
+impl fmt::Display for ShapeMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Rows(mismatch) => write!(
+                f,
+                "expected {} rows, found {}",
+                mismatch.expected, mismatch.found
+            ),
+            Self::Columns { row, mismatch } => write!(f, "row {row}: {mismatch}"),
+        }
+    }
+}
+
+impl std::error::Error for ShapeMismatch {}
+
+/// A column that doesn't sum to 1, keeping a matrix from being stochastic
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BadColumn {
+    pub column: usize,
+    pub sum: f32,
+}
+
+/// Every column keeping a matrix from being stochastic, in column order
+#[derive(Clone, Debug, PartialEq)]
+pub struct NotStochastic {
+    pub columns: Vec<BadColumn>,
+}
+
+impl fmt::Display for NotStochastic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "columns not summing to 1:")?;
+        for (idx, bad) in self.columns.iter().enumerate() {
+            let sep = if idx == 0 { " " } else { ", " };
+            write!(f, "{sep}{} (sums to {})", bad.column, bad.sum)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for NotStochastic {}
+
+impl<const M: usize, const N: usize> TryFrom<Vec<Vec<f32>>> for Matrix<M, N, General> {
+    type Error = ShapeMismatch;
+    fn try_from(rows: Vec<Vec<f32>>) -> Result<Self, Self::Error> {
+        if rows.len() != M {
+            return Err(ShapeMismatch::Rows(LengthMismatch {
+                expected: M,
+                found: rows.len(),
+            }));
+        }
+
+        let mut mat = Self::zero_matrix();
+        for (idx, row) in rows.into_iter().enumerate() {
+            let row = Vector::<N>::try_from(row)
+                .map_err(|mismatch| ShapeMismatch::Columns { row: idx, mismatch })?;
+            mat[idx].copy_from_slice(&row.data);
+        }
+
+        Ok(mat)
+    }
+}
+
 impl<const M: usize, const N: usize> Matrix<M, N, General> {
     pub fn from_vectors(vecs: [Vector<M>; N]) -> Self {
         let mut mat = Self::zero_matrix();
@@ -109,6 +192,40 @@ impl<const M: usize, const N: usize> Matrix<M, N, General> {
     }
 }
 
+impl<const M: usize, const N: usize> Matrix<M, N, General> {
+    /// Adds another matrix into this one without making a new matrix
+    pub fn add_assign<OTHER: Debug>(&mut self, other: &Matrix<M, N, OTHER>) {
+        for row in 0..M {
+            for col in 0..N {
+                self[row][col] += other[row][col];
+            }
+        }
+    }
+
+    /// Scales this matrix without making a new matrix
+    pub fn scalar_mul_assign(&mut self, k: f32) {
+        for row in self.data.iter_mut() {
+            for val in row {
+                *val *= k;
+            }
+        }
+    }
+}
+
+impl<const M: usize, const N: usize, OTHER: Debug> std::ops::AddAssign<Matrix<M, N, OTHER>>
+    for Matrix<M, N, General>
+{
+    fn add_assign(&mut self, rhs: Matrix<M, N, OTHER>) {
+        Matrix::add_assign(self, &rhs)
+    }
+}
+
+impl<const M: usize, const N: usize> std::ops::MulAssign<f32> for Matrix<M, N, General> {
+    fn mul_assign(&mut self, rhs: f32) {
+        self.scalar_mul_assign(rhs)
+    }
+}
+
 impl<const M: usize> Matrix<M, M, General> {
     pub fn identity() -> Matrix<M, M> {
         let mut mat: Matrix<M, M> = Matrix::default();
@@ -139,15 +256,31 @@ impl<const M: usize, const N: usize, TYPE: Debug + Copy> Matrix<M, N, TYPE> {
     }
 
     pub fn stochastic_matrix(&self) -> Option<Matrix<M, N, Stochastic>> {
-        let columns = self.column_vectors();
-        let mut stochastic: Matrix<M, N, Stochastic> = Matrix::default();
-        stochastic.data = self.data.clone();
+        self.try_stochastic_matrix().ok()
+    }
 
-        for vector in columns {
-            let _ = vector.probability_vector()?;
+    /// Views this matrix as stochastic, or reports every column that doesn't sum to 1 and what it
+    /// sums to instead
+    pub fn try_stochastic_matrix(&self) -> Result<Matrix<M, N, Stochastic>, NotStochastic> {
+        let columns: Vec<_> = self
+            .column_vectors()
+            .iter()
+            .enumerate()
+            .filter(|(_, vector)| vector.probability_vector().is_none())
+            .map(|(column, vector)| BadColumn {
+                column,
+                sum: vector.sum(),
+            })
+            .collect();
+
+        if !columns.is_empty() {
+            return Err(NotStochastic { columns });
         }
 
-        Some(stochastic)
+        Ok(Matrix {
+            data: self.data,
+            phantom_type: PhantomData,
+        })
     }
 
     pub fn column_vectors(&self) -> [Vector<M, crate::vector::General>; N] {
@@ -232,35 +365,67 @@ impl<const M: usize, const N: usize, TYPE: Debug + Copy> Matrix<M, N, TYPE> {
     }
 
     pub fn scalar_multiply(&self, k: f32) -> Matrix<M, N, General> {
-        let mut mat = *self;
-
-        for row in 0..M {
-            for col in 0..N {
-                mat[row][col] *= k;
-            }
-        }
-
-        let mut result = Matrix::default();
-        result.data = mat.data;
-
-        result
+        let mut mat = self.general();
+        mat.scalar_mul_assign(k);
+        mat
     }
 
     pub fn matrix_addition<LEFT: Debug>(
         &self,
         other: &Matrix<M, N, LEFT>,
     ) -> Matrix<M, N, General> {
-        let mut result = Matrix::default();
+        let mut result = self.general();
+        result.add_assign(other);
+        result
+    }
+
+    /// Multiplies this matrix by a column vector
+    pub fn multiply_vector<OTHER: Debug>(&self, vector: &Vector<N, OTHER>) -> Vector<M> {
+        let mut result = Vector::zero_vector();
+        self.gemv_into(vector, &mut result);
+        result
+    }
 
+    /// Writes the product of this matrix and a vector into `out`, overwriting whatever it held, so
+    /// one buffer can be reused across repeated products
+    pub fn gemv_into<OTHER: Debug>(&self, x: &Vector<N, OTHER>, out: &mut Vector<M>) {
         for row in 0..M {
-            for col in 0..N {
-                result[row][col] = self[row][col] + other[row][col];
+            out[row] = self[row].iter().zip(x.data).map(|(a, b)| a * b).sum();
+        }
+    }
+
+    /// A copy of this matrix without its type's guarantees
+    fn general(&self) -> Matrix<M, N, General> {
+        Matrix {
+            data: self.data,
+            phantom_type: PhantomData,
+        }
+    }
+
+    /// Copies this matrix into one of a different size, truncating extra rows and columns or
+    /// padding with zeroes
+    pub fn resize<const P: usize, const Q: usize>(&self) -> Matrix<P, Q> {
+        let mut result = Matrix::zero_matrix();
+
+        for row in 0..M.min(P) {
+            for col in 0..N.min(Q) {
+                result[row][col] = self[row][col];
             }
         }
 
         result
     }
 
+    /// Square root of the sum of every squared entry
+    pub fn frobenius_norm(&self) -> f32 {
+        self.data
+            .iter()
+            .flatten()
+            .map(|val| val.powi(2))
+            .sum::<f32>()
+            .sqrt()
+    }
+
     pub fn identity_filled(num: f32) -> Matrix<M, N, TYPE> {
         Matrix {
             data: [[num; N]; M],
@@ -269,6 +434,72 @@ impl<const M: usize, const N: usize, TYPE: Debug + Copy> Matrix<M, N, TYPE> {
     }
 }
 
+impl<const M: usize, TYPE: Debug + Copy> Matrix<M, M, TYPE> {
+    /// The entries along the main diagonal
+    pub fn diagonal(&self) -> Vector<M> {
+        let mut diagonal = Vector::zero_vector();
+
+        for idx in 0..M {
+            diagonal[idx] = self[idx][idx];
+        }
+
+        diagonal
+    }
+
+    /// Inverts this matrix by Gauss-Jordan elimination with partial pivoting, returning `None` if
+    /// it's singular
+    pub fn inverse(&self) -> Option<Matrix<M, M>> {
+        let mut reduced: Matrix<M, M> = self.resize();
+        let mut inverse: Matrix<M, M> = Matrix::identity();
+
+        for col in 0..M {
+            let pivot = (col..M)
+                .max_by(|a, b| reduced[*a][col].abs().total_cmp(&reduced[*b][col].abs()))?;
+            if reduced[pivot][col].abs() <= 1e-6 {
+                return None;
+            }
+            reduced.data.swap(col, pivot);
+            inverse.data.swap(col, pivot);
+
+            let scale = 1f32 / reduced[col][col];
+            for idx in 0..M {
+                reduced[col][idx] *= scale;
+                inverse[col][idx] *= scale;
+            }
+
+            for row in 0..M {
+                let factor = reduced[row][col];
+                if row != col && factor != 0f32 {
+                    for idx in 0..M {
+                        reduced[row][idx] -= factor * reduced[col][idx];
+                        inverse[row][idx] -= factor * inverse[col][idx];
+                    }
+                }
+            }
+        }
+
+        Some(inverse)
+    }
+
+    /// Estimates how much solving against this matrix amplifies errors as `||A|| * ||A⁻¹||` in
+    /// the Frobenius norm, under which the identity scores `M` rather than 1. Returns `None` if
+    /// the matrix is singular
+    pub fn condition_number(&self) -> Option<f32> {
+        Some(self.frobenius_norm() * self.inverse()?.frobenius_norm())
+    }
+
+    /// Copies this matrix with its main diagonal replaced
+    pub fn with_diagonal<OTHER: Debug>(&self, diagonal: &Vector<M, OTHER>) -> Matrix<M, M> {
+        let mut result: Matrix<M, M> = self.resize();
+
+        for idx in 0..M {
+            result[idx][idx] = diagonal[idx];
+        }
+
+        result
+    }
+}
+
 impl<const M: usize> Matrix<M, M, Stochastic> {
     pub fn steady_state_solution(&self) -> Option<Vector<M, Probability>> {
         let identity_matrix: Matrix<M, M> = Matrix::identity();
@@ -276,7 +507,7 @@ impl<const M: usize> Matrix<M, M, Stochastic> {
 
         // Select first vector from the null space
         let steady_state = self_minus_e1.null_space();
-        if steady_state.len() > 0 {
+        if !steady_state.is_empty() {
             let selected_steady_state = steady_state[0];
             let scale = 1f32 / selected_steady_state.sum();
 
@@ -288,13 +519,144 @@ impl<const M: usize> Matrix<M, M, Stochastic> {
             None
         }
     }
+
+    /// Repeatedly applies this matrix to a starting distribution until successive distributions
+    /// differ by less than `tolerance` in total, returning the converged distribution and the
+    /// number of steps taken. Returns `None` if it doesn't converge within `iters` steps
+    pub fn power_iteration(
+        &self,
+        start: Vector<M, Probability>,
+        iters: usize,
+        tolerance: f32,
+    ) -> Option<(Vector<M, Probability>, usize)> {
+        let mut current = start;
+        let mut next = Vector::zero_vector();
+
+        for step in 1..=iters {
+            self.gemv_into(&current, &mut next);
+            let change: f32 = next
+                .data
+                .iter()
+                .zip(current.data)
+                .map(|(next, current)| (next - current).abs())
+                .sum();
+            current = Vector::from_raw(next.data);
+
+            if change < tolerance {
+                return Some((current, step));
+            }
+        }
+
+        None
+    }
+
+    /// Estimates the magnitude of the second largest eigenvalue, which governs how quickly power
+    /// iteration converges. The steady state is deflated out of the matrix and power iteration is
+    /// run on what's left for `iters` steps
+    pub fn second_eigenvalue_estimate(&self, iters: usize) -> Option<f32> {
+        let steady_state = self.steady_state_solution()?;
+
+        let mut current = Vector::zero_vector();
+        for i in 0..M {
+            current[i] = (i + 1) as f32;
+        }
+        current = current * (1f32 / current.magnitude());
+
+        let mut estimate = 0f32;
+        for _ in 0..iters {
+            // Column sums of a stochastic matrix are all 1, so subtracting (steady state * sum)
+            // removes the eigenvalue 1 component
+            let next = self.multiply_vector(&current) - steady_state * current.sum();
+            estimate = next.magnitude();
+
+            if estimate <= f32::EPSILON {
+                return Some(0f32);
+            }
+
+            current = next * (1f32 / estimate);
+        }
+
+        Some(estimate)
+    }
+
+    /// Estimates how many power iterations are needed to converge within `tolerance`, based on
+    /// the spectral gap
+    pub fn estimated_iterations(&self, tolerance: f32, iters: usize) -> Option<usize> {
+        let second = self.second_eigenvalue_estimate(iters)?;
+
+        if second <= f32::EPSILON {
+            Some(1)
+        } else if second >= 1f32 {
+            None
+        } else {
+            Some((tolerance.ln() / second.ln()).ceil() as usize)
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::vector::Vector;
+    use crate::vector::{LengthMismatch, Vector};
+
+    use super::{BadColumn, Matrix, NotStochastic, ShapeMismatch};
+
+    #[test]
+    fn nested_vecs_convert_to_matrix() {
+        let matrix = Matrix::<2, 3>::try_from(vec![vec![1.0, 2.0, 3.0], vec![4.0, 5.0, 6.0]])
+            .expect("Convert matrix");
+        assert_eq!(
+            Some(matrix),
+            Matrix::from_data(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0])
+        );
 
-    use super::Matrix;
+        assert_eq!(
+            Matrix::<2, 3>::try_from(vec![vec![1.0, 2.0, 3.0]]),
+            Err(ShapeMismatch::Rows(LengthMismatch {
+                expected: 2,
+                found: 1
+            }))
+        );
+        assert_eq!(
+            Matrix::<2, 3>::try_from(vec![vec![1.0, 2.0, 3.0], vec![4.0, 5.0]]),
+            Err(ShapeMismatch::Columns {
+                row: 1,
+                mismatch: LengthMismatch {
+                    expected: 3,
+                    found: 2
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn every_bad_column_reported() {
+        let matrix = Matrix::from_vectors([
+            Vector::from_data([0.5, 0.0, 0.0]),
+            Vector::from_data([0.2, 0.3, 0.5]),
+            Vector::from_data([1.0, 1.0, 0.0]),
+        ]);
+
+        let err = matrix.try_stochastic_matrix().expect_err("Not stochastic");
+        assert_eq!(
+            err,
+            NotStochastic {
+                columns: vec![
+                    BadColumn {
+                        column: 0,
+                        sum: 0.5
+                    },
+                    BadColumn {
+                        column: 2,
+                        sum: 2.0
+                    },
+                ]
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "columns not summing to 1: 0 (sums to 0.5), 2 (sums to 2)"
+        );
+    }
 
     #[test]
     pub fn column_vectors() {
@@ -361,6 +723,33 @@ mod tests {
         )
     }
 
+    #[test]
+    fn in_place_ops_match_allocating_ops() {
+        let matrix = Matrix::<2, 3>::from_data(&[1.0, -2.0, 3.0, 0.5, 4.0, -1.5]).expect("Matrix");
+        let other = Matrix::<2, 3>::from_data(&[0.5, 1.0, -1.0, 2.0, 0.0, 3.0]).expect("Matrix");
+
+        let mut summed = matrix;
+        summed.add_assign(&other);
+        assert_eq!(summed, matrix + other);
+        let mut summed = matrix;
+        summed += other;
+        assert_eq!(summed, matrix + other);
+
+        let mut scaled = matrix;
+        scaled.scalar_mul_assign(-2.5);
+        assert_eq!(scaled, matrix * -2.5);
+        let mut scaled = matrix;
+        scaled *= -2.5;
+        assert_eq!(scaled, matrix * -2.5);
+
+        // The output buffer's old contents are overwritten, not accumulated
+        let x = Vector::from_data([1.0, 2.0, 3.0]);
+        let mut out = Vector::from_data([100.0, 100.0]);
+        matrix.gemv_into(&x, &mut out);
+        assert_eq!(out, matrix.multiply_vector(&x));
+        assert_eq!(out, Vector::from_data([6.0, 4.0]));
+    }
+
     #[test]
     pub fn matrix_subtraction() {
         let matrix = Matrix::from_vectors([
@@ -385,6 +774,68 @@ mod tests {
         assert_eq!(identity.data, [[1f32, 0f32], [0f32, 1f32]])
     }
 
+    #[test]
+    pub fn identity_diagonal_is_ones() {
+        let identity: Matrix<3, 3, _> = Matrix::identity();
+
+        assert_eq!(identity.diagonal(), Vector::from_data([1f32, 1f32, 1f32]));
+    }
+
+    #[test]
+    pub fn diagonal_replaced() {
+        let input = Matrix::from_data(&[1f32, 2f32, 3f32, 4f32]).expect("Create matrix");
+        let diagonal = Vector::from_data([5f32, 6f32]);
+
+        let replaced: Matrix<2, 2> = input.with_diagonal(&diagonal);
+
+        assert_eq!(replaced.diagonal(), diagonal);
+        assert_eq!(replaced[0][1], input[0][1]);
+        assert_eq!(replaced[1][0], input[1][0]);
+        assert_eq!(replaced.with_diagonal(&input.diagonal()), input);
+    }
+
+    #[test]
+    pub fn inverse_undoes_matrix() {
+        let input: Matrix<2, 2> =
+            Matrix::from_data(&[0f32, 2f32, 1f32, 3f32]).expect("Create matrix");
+        let inverse = input.inverse().expect("Invertible");
+
+        assert_eq!(inverse.data, [[-1.5, 1f32], [0.5, 0f32]]);
+
+        let singular: Matrix<2, 2> =
+            Matrix::from_data(&[1f32, 2f32, 2f32, 4f32]).expect("Create matrix");
+        assert!(singular.inverse().is_none());
+        assert!(singular.condition_number().is_none());
+    }
+
+    #[test]
+    pub fn condition_number_estimated() {
+        let identity: Matrix<1, 1, _> = Matrix::identity();
+        assert!((identity.condition_number().expect("Invertible") - 1f32).abs() < 1e-6);
+
+        // The Frobenius norm of an M x M identity is sqrt(M)
+        let identity: Matrix<3, 3, _> = Matrix::identity();
+        assert!((identity.condition_number().expect("Invertible") - 3f32).abs() < 1e-5);
+
+        let near_singular: Matrix<2, 2> =
+            Matrix::from_data(&[1f32, 1f32, 1f32, 1.001]).expect("Create matrix");
+        assert!(near_singular.condition_number().expect("Invertible") > 1000f32);
+    }
+
+    #[test]
+    pub fn matrix_resized() {
+        let identity: Matrix<2, 2, _> = Matrix::identity();
+        let padded: Matrix<3, 3> = identity.resize();
+
+        assert_eq!(
+            padded.data,
+            [[1f32, 0f32, 0f32], [0f32, 1f32, 0f32], [0f32, 0f32, 0f32]]
+        );
+
+        let truncated: Matrix<1, 2> = padded.resize();
+        assert_eq!(truncated.data, [[1f32, 0f32]]);
+    }
+
     #[test]
     pub fn reduce_row_echelon_form() {
         let input = Matrix::from_vectors([
@@ -444,4 +895,56 @@ mod tests {
         assert!((steady_state[0] - 0.4).abs() < 1e-6);
         assert!((steady_state[1] - 0.6).abs() < 1e-6);
     }
+
+    #[test]
+    pub fn stochastic_preserves_probability() {
+        let matrix = Matrix::from_vectors([
+            Vector::from_data([0.5, 0.25, 0.25]),
+            Vector::from_data([0.0, 0.8, 0.2]),
+            Vector::from_data([0.35, 0.65, 0.0]),
+        ])
+        .stochastic_matrix()
+        .expect("Stochastic matrix");
+        let probability = Vector::from_data([0.2, 0.3, 0.5])
+            .probability_vector()
+            .expect("Probability vector");
+
+        let result = matrix * probability;
+
+        assert!((result.sum() - 1.0).abs() < 1e-6);
+        assert!((result[0] - 0.275).abs() < 1e-6);
+    }
+
+    #[test]
+    pub fn power_iteration_finds_steady_state() {
+        let input =
+            Matrix::from_vectors([Vector::from_data([0.7, 0.3]), Vector::from_data([0.2, 0.8])])
+                .stochastic_matrix()
+                .expect("Stochastic matrix");
+        let start = Vector::from_data([0.5, 0.5])
+            .probability_vector()
+            .expect("Probability vector");
+
+        let (steady_state, steps) = input.power_iteration(start, 100, 1e-6).expect("Converge");
+
+        assert!((steady_state[0] - 0.4).abs() < 1e-5);
+        assert!((steady_state[1] - 0.6).abs() < 1e-5);
+        assert!(steps > 1);
+        assert!(input.power_iteration(start, 1, 1e-6).is_none());
+    }
+
+    #[test]
+    pub fn second_eigenvalue_estimated() {
+        // Eigenvalues of this matrix are 1 and 0.5
+        let input =
+            Matrix::from_vectors([Vector::from_data([0.7, 0.3]), Vector::from_data([0.2, 0.8])])
+                .stochastic_matrix()
+                .expect("Stochastic matrix");
+
+        let second = input.second_eigenvalue_estimate(50).expect("Estimate");
+        assert!((second - 0.5).abs() < 1e-3);
+
+        let iterations = input.estimated_iterations(1e-6, 50).expect("Iterations");
+        assert_eq!(iterations, 20);
+    }
 }
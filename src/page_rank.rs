@@ -1,94 +1,3791 @@
 //! Primary PageRank implementation that uses a page resgistry and constructs a stochastic travel
 //! matrix based on results that match a search
 
-use std::collections::HashMap;
+use std::{
+    borrow::Cow,
+    cmp::Ordering as CmpOrdering,
+    collections::{HashMap, HashSet},
+    fmt,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    ops::Range,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, TryRecvError},
+        Arc, Mutex, OnceLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
 
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 use slotmap::SlotMap;
 
+#[cfg(feature = "embeddings")]
+use crate::vector::Vector;
 use crate::{
-    crawler::{SiteKey, SiteLog},
-    graph_rank::ConnectionGraph,
+    crawler::{host_of, unix_now, SiteKey, SiteLog},
+    graph_rank::{ConnectionGraph, GraphKey, CONVERGENCE_TOLERANCE, MAX_ITERATIONS},
+    index::{fold, merge_ranges, FoldedText, InvertedIndex},
+    query::{on_domain, Query},
+    relevance::{inverse_document_frequency, title_match, FieldWeights, TitleMatch},
+    snippet::{snippet, truncate_words, Snippet},
 };
 
 /// Show the top {this number} results when searching for a topic
 pub const RESULTS_TO_SHOW: usize = 250;
 
+/// Default weight of PageRank against text relevance when blending scores
+pub const DEFAULT_ALPHA: f32 = 0.7;
+
+/// Default multiplier for sites whose title is exactly the search term
+pub const DEFAULT_EXACT_TITLE_BOOST: f32 = 2.0;
+
+/// Default multiplier for sites whose title starts with the search term
+pub const DEFAULT_TITLE_PREFIX_BOOST: f32 = 1.25;
+
+/// Default multiplier for sites whose title contains the search term as complete words
+pub const DEFAULT_TITLE_WORD_BOOST: f32 = 1.1;
+
+/// Cancellation flag for searches that can't be cancelled
+static NOT_CANCELLED: AtomicBool = AtomicBool::new(false);
+
+/// Version of the saved ranker format, bumped whenever it changes incompatibly
+pub const RANKER_FORMAT_VERSION: u32 = 1;
+
+/// Tunable parameters for a ranked search
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchOptions {
+    /// Scores are blended as `alpha * rank + (1 - alpha) * relevance`
+    pub alpha: f32,
+    /// Multiplies the blended score of sites titled exactly the search term
+    pub exact_title_boost: f32,
+    /// Multiplies the blended score of sites whose title starts with the search term
+    pub title_prefix_boost: f32,
+    /// Multiplies the blended score of sites whose title contains the search term as complete
+    /// words anywhere else
+    pub title_word_boost: f32,
+    /// Only match sites hosted on one of these domains or their subdomains, if any are given
+    pub include_domains: Vec<String>,
+    /// Never match sites hosted on these domains or their subdomains
+    pub exclude_domains: Vec<String>,
+    /// Most power iteration steps spent ranking matches before the search fails
+    pub max_iterations: usize,
+    /// Total change in rank between steps under which ranking has converged
+    pub tolerance: f32,
+    /// How much matches in each field of a site count towards its relevance
+    pub field_weights: FieldWeights,
+    /// When ranking the matches fails, rank them by backlink count instead of failing the search
+    pub fallback_ranking: bool,
+    /// Drops results scoring below this fraction of the best result's score
+    pub min_score: Option<f32>,
+    /// Keeps at most this many of the best results, which can't be 0
+    pub max_results: Option<usize>,
+    /// Whether matches are scored across threads, when built with the `parallel` feature. The
+    /// results are the same either way
+    pub parallel_scoring: bool,
+    /// Most links followed out of each site when ranking, keeping those to the sites with the
+    /// most backlinks. Rank flows evenly along the kept links
+    pub max_outdegree: Option<usize>,
+    /// Whether every site links to itself when ranking. Without self links, rank only flows to
+    /// sites that are genuinely linked to
+    pub self_loops: bool,
+    /// Shows only the best result from each host
+    pub collapse_domains: bool,
+    /// Halves the score of ranked matches for every half-life since they were crawled. Sites
+    /// never crawled score zero but still match
+    pub recency_half_life: Option<Duration>,
+    /// Scales scores so the best result scores 1, making them comparable between searches
+    pub normalize_scores: bool,
+    /// Ranks only the most linked to of the matches sharing a content hash, so mirrored pages
+    /// don't crowd the results
+    pub collapse_duplicates: bool,
+    /// Follows links in both directions when ranking, so linking to a site counts as much as
+    /// being linked to by it
+    pub undirected: bool,
+    /// How results with equal scores are ordered, most relevant to the query first by default
+    pub tie_break: TieBreak,
+    /// Share of the usual rank passed along links between sites on the same host when ranking,
+    /// self links included, from 0 to 1. The rest is spread evenly across every match, so a host
+    /// can't rank its own pages up through internal links alone
+    pub internal_link_weight: f32,
+}
+
+/// Order given to search results with equal scores, each falling back to URL order so results
+/// are always in the same order
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TieBreak {
+    /// Most relevant to the query first, then alphabetically by title
+    #[default]
+    Relevance,
+    /// Most linked to first
+    Backlinks,
+    /// Alphabetically by title, ignoring case
+    Title,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            alpha: DEFAULT_ALPHA,
+            exact_title_boost: DEFAULT_EXACT_TITLE_BOOST,
+            title_prefix_boost: DEFAULT_TITLE_PREFIX_BOOST,
+            title_word_boost: DEFAULT_TITLE_WORD_BOOST,
+            include_domains: vec![],
+            exclude_domains: vec![],
+            max_iterations: MAX_ITERATIONS,
+            tolerance: CONVERGENCE_TOLERANCE,
+            field_weights: FieldWeights::default(),
+            fallback_ranking: false,
+            min_score: None,
+            max_results: None,
+            parallel_scoring: true,
+            max_outdegree: None,
+            self_loops: true,
+            collapse_domains: false,
+            recency_half_life: None,
+            normalize_scores: false,
+            collapse_duplicates: false,
+            undirected: false,
+            tie_break: TieBreak::default(),
+            internal_link_weight: 1f32,
+        }
+    }
+}
+
+/// A site matching a search, alongside the normalized scores that placed it
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchResult<'a> {
+    pub key: SiteKey,
+    pub site: &'a SiteLog,
+    /// PageRank within the query subgraph, relative to the best ranked match
+    pub rank: f32,
+    /// Title relevance to the query, relative to the most relevant match
+    pub relevance: f32,
+    /// Blend of rank and relevance that results are ordered by, multiplied by any title match
+    /// boost
+    pub score: f32,
+    /// How many crawled sites link to this one
+    pub backlinks: usize,
+    /// Byte ranges of the title that matched the query, merged where they overlap
+    pub title_highlights: Vec<Range<usize>>,
+    /// Excerpt of the site's text around the first query match, if it has text that matches
+    pub snippet: Option<Snippet>,
+    /// The site's crawled meta description
+    pub description: Option<&'a str>,
+}
+
+impl SearchResult<'_> {
+    /// A line describing the result in at most `max_chars` characters, cut at a word boundary.
+    /// Uses the site's description, falling back to the snippet and then the start of its text
+    pub fn summary(&self, max_chars: usize) -> Option<String> {
+        let text = self
+            .description
+            .or(self.snippet.as_ref().map(|snippet| snippet.text.as_str()))
+            .or(self.site.text.as_deref())
+            .filter(|text| !text.trim().is_empty())?;
+
+        Some(truncate_words(text, max_chars))
+    }
+}
+
+/// One page of a search's results
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchPage<'a> {
+    pub results: Vec<SearchResult<'a>>,
+    /// How many sites matched the search across every page
+    pub total_matches: usize,
+    /// Zero based index of this page
+    pub page: usize,
+    pub total_pages: usize,
+    /// Whether `min_score` or `max_results` cut off any results
+    pub truncated: bool,
+}
+
+/// Why a site ranks where it does within the subgraph of a query's matches
+#[derive(Clone, Debug, PartialEq)]
+pub struct RankExplanation {
+    pub site: SiteKey,
+    /// Steady state rank within the query subgraph, or `None` if the site doesn't match the query
+    /// or the matches couldn't be ranked
+    pub rank: Option<f32>,
+    /// Other matching sites that link to this one
+    pub inbound: Vec<SiteKey>,
+    /// Chance of a random walk moving from this site to each matching site it links to, including
+    /// the implicit link back to itself
+    pub outbound: Vec<(SiteKey, f32)>,
+}
+
+/// The parts of a search result's score and how they combine
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScoreBreakdown {
+    /// PageRank within the query subgraph, relative to the best ranked match
+    pub rank: f32,
+    /// Text relevance to the query, relative to the most relevant match
+    pub relevance: f32,
+    /// How many crawled sites link to this one, which only affects fallback ranking
+    pub backlinks: usize,
+    /// Weight of rank against relevance
+    pub alpha: f32,
+    /// Multiplier for matching the query as a whole title or title prefix, and for how recently
+    /// the site was crawled
+    pub boost: f32,
+    /// The final score the result was ordered by
+    pub score: f32,
+}
+
+impl ScoreBreakdown {
+    /// Recombines the components into a score, which matches `score`
+    pub fn combined(&self) -> f32 {
+        (self.alpha * self.rank + (1f32 - self.alpha) * self.relevance) * self.boost
+    }
+}
+
+/// Where time went during a search
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SearchMetrics {
+    /// Sites matching the term before ranking
+    pub candidates: usize,
+    /// Time finding the candidates
+    pub reduce_time: Duration,
+    /// Time building the link graph between candidates
+    pub graph_build_time: Duration,
+    /// Time ranking the link graph
+    pub rank_time: Duration,
+    /// Power iteration steps taken to rank the link graph
+    pub iterations: usize,
+    /// Whether `min_score` or `max_results` cut off any results
+    pub truncated: bool,
+}
+
+/// How a search ended
+#[derive(Clone, Debug, PartialEq)]
+pub enum SearchOutcome<'a> {
+    /// Matches ordered by their blended score, as from [`PageRanker::search_ranked`]
+    Ranked {
+        results: Vec<SearchResult<'a>>,
+        metrics: SearchMetrics,
+    },
+    /// The term was blank, so these are the top sites overall by global rank
+    GlobalTop {
+        results: Vec<SearchResult<'a>>,
+        metrics: SearchMetrics,
+    },
+    /// The search couldn't be carried out
+    Failed(SearchError),
+    /// The search was cancelled through its handle
+    Cancelled,
+}
+
+impl<'a> SearchOutcome<'a> {
+    /// Results of a finished search, which are empty if it failed or was cancelled
+    pub fn results(&self) -> &[SearchResult<'a>] {
+        match self {
+            Self::Ranked { results, .. } | Self::GlobalTop { results, .. } => results,
+            Self::Failed(_) | Self::Cancelled => &[],
+        }
+    }
+
+    /// Metrics of a finished search
+    pub fn metrics(&self) -> Option<&SearchMetrics> {
+        match self {
+            Self::Ranked { metrics, .. } | Self::GlobalTop { metrics, .. } => Some(metrics),
+            Self::Failed(_) | Self::Cancelled => None,
+        }
+    }
+}
+
+/// Why a search couldn't give any results
+#[derive(Clone, Debug, PartialEq)]
+pub enum SearchError {
+    /// Nothing matched the term
+    NoMatches,
+    /// The matches were found but couldn't be ranked
+    RankingFailed(RankingFailure),
+    /// The term couldn't be parsed, or the search options can't be used. The position is the byte
+    /// offset in the term where parsing failed
+    InvalidQuery {
+        position: Option<usize>,
+        message: String,
+    },
+}
+
+/// Why ranking the link graph between matches failed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RankingFailure {
+    /// Some site's outgoing link probabilities don't sum to 1
+    InvalidGraph,
+    /// The ranks were still changing after every allowed power iteration step
+    NotConverged { iterations: usize },
+    /// Ranking stopped early, because the search was cancelled or its thread died
+    Aborted,
+}
+
+impl fmt::Display for SearchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoMatches => write!(f, "no sites match the search"),
+            Self::RankingFailed(RankingFailure::InvalidGraph) => {
+                write!(f, "the link graph between matches is invalid")
+            }
+            Self::RankingFailed(RankingFailure::NotConverged { iterations }) => {
+                write!(f, "ranking didn't converge within {iterations} iterations")
+            }
+            Self::RankingFailed(RankingFailure::Aborted) => write!(f, "ranking stopped early"),
+            Self::InvalidQuery {
+                position: Some(position),
+                message,
+            } => write!(f, "invalid search at position {position}: {message}"),
+            Self::InvalidQuery {
+                position: None,
+                message,
+            } => write!(f, "invalid search: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for SearchError {}
+
+/// A search running on another thread. Dropping the handle cancels the search
+pub struct SearchHandle {
+    ranker: Arc<PageRanker>,
+    cancel: Arc<AtomicBool>,
+    receiver: Receiver<Result<Scored, SearchError>>,
+    /// Whether the term was blank, making the results the global top sites
+    blank: bool,
+    /// Scored matches, or why there aren't any, once the search has finished
+    finished: Option<Result<Scored, SearchError>>,
+}
+
+impl SearchHandle {
+    /// Checks on the search without blocking, returning `None` while it's still running
+    pub fn try_poll(&mut self) -> Option<SearchOutcome<'_>> {
+        if self.cancel.load(Ordering::Relaxed) {
+            return Some(SearchOutcome::Cancelled);
+        }
+
+        if self.finished.is_none() {
+            match self.receiver.try_recv() {
+                Ok(scored) => self.finished = Some(scored),
+                Err(TryRecvError::Empty) => return None,
+                Err(TryRecvError::Disconnected) => {
+                    self.finished = Some(Err(SearchError::RankingFailed(RankingFailure::Aborted)))
+                }
+            }
+        }
+
+        Some(self.outcome())
+    }
+
+    /// Blocks until the search finishes
+    pub fn wait(&mut self) -> SearchOutcome<'_> {
+        if self.finished.is_none() && !self.cancel.load(Ordering::Relaxed) {
+            self.finished = Some(
+                self.receiver
+                    .recv()
+                    .unwrap_or(Err(SearchError::RankingFailed(RankingFailure::Aborted))),
+            );
+        }
+
+        self.outcome()
+    }
+
+    /// Stops the search as soon as possible. Every later poll reports it as cancelled
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+
+    fn outcome(&self) -> SearchOutcome<'_> {
+        if self.cancel.load(Ordering::Relaxed) {
+            return SearchOutcome::Cancelled;
+        }
+
+        match &self.finished {
+            Some(Ok(scored)) => self.ranker.outcome(scored, self.blank),
+            Some(Err(err)) => SearchOutcome::Failed(err.clone()),
+            None => SearchOutcome::Failed(SearchError::RankingFailed(RankingFailure::Aborted)),
+        }
+    }
+}
+
+impl Drop for SearchHandle {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+/// Why a saved ranker couldn't be written or read back
+#[derive(Debug)]
+pub enum PersistError {
+    Io(io::Error),
+    /// The file isn't a well formed saved ranker
+    Format(serde_json::Error),
+    /// The file was saved by an incompatible version of the format
+    Version {
+        found: u32,
+        expected: u32,
+    },
+}
+
+impl fmt::Display for PersistError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "failed to access saved ranker: {err}"),
+            Self::Format(err) => write!(f, "saved ranker is malformed: {err}"),
+            Self::Version { found, expected } => write!(
+                f,
+                "saved ranker has format version {found}, but version {expected} is required"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PersistError {}
+
+impl From<io::Error> for PersistError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for PersistError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Format(err)
+    }
+}
+
+/// Just enough of a saved ranker to check its version before reading the rest
+#[derive(Deserialize)]
+struct SavedVersion {
+    version: u32,
+}
+
+/// A ranker as written to disk
+#[derive(Serialize)]
+struct SavingRanker<'a> {
+    version: u32,
+    sites: &'a SlotMap<SiteKey, SiteLog>,
+    index: &'a InvertedIndex,
+    /// Stored as pairs since JSON object keys must be strings
+    global_rank: Option<Vec<(SiteKey, f32)>>,
+}
+
+/// A site's text as exported for embedding
+#[cfg(feature = "embeddings")]
+#[derive(Serialize)]
+struct Document<'a> {
+    key: SiteKey,
+    url: &'a str,
+    text: &'a str,
+}
+
+/// A ranker as read from disk
+#[derive(Deserialize)]
+struct SavedRanker {
+    sites: SlotMap<SiteKey, SiteLog>,
+    index: InvertedIndex,
+    global_rank: Option<Vec<(SiteKey, f32)>>,
+}
+
+/// Every scored match of a search with the metrics gathered scoring them
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Scored {
+    sites: Vec<ScoredSite>,
+    metrics: SearchMetrics,
+}
+
+/// The scores of a matching site, detached from the registry so they can be cached
+#[derive(Clone, Debug, PartialEq)]
+struct ScoredSite {
+    key: SiteKey,
+    rank: f32,
+    relevance: f32,
+    alpha: f32,
+    boost: f32,
+    score: f32,
+    title_highlights: Vec<Range<usize>>,
+    snippet: Option<Snippet>,
+}
+
 /// Struct responsible for creating stochastic matrices that represent sites that appear
 pub struct PageRanker {
     /// The site registry
     sites: SlotMap<SiteKey, SiteLog>,
+    /// Title token index over the registry
+    index: InvertedIndex,
+    /// Sites linking to each site, excluding its link to itself
+    backlinks: HashMap<SiteKey, Vec<SiteKey>>,
+    /// Scored matches of the most recently paged query, so flipping pages doesn't re-rank
+    page_cache: Mutex<Option<(String, SearchOptions, Scored)>>,
+    /// PageRank over the entire registry, computed on first use
+    global_rank: OnceLock<HashMap<SiteKey, f32>>,
+    /// Sites with fewer characters than this in their title never match a search
+    min_title_len: usize,
+    /// Precomputed embedding of each site's text
+    #[cfg(feature = "embeddings")]
+    embeddings: HashMap<SiteKey, Vec<f32>>,
 }
 
 impl PageRanker {
-    /// Creates a new PageRanker based on
+    /// Creates a new PageRanker based on a site registry, indexing every title
     pub fn from_registry(sites: SlotMap<SiteKey, SiteLog>) -> Self {
-        Self { sites }
+        let index = InvertedIndex::build(&sites);
+        let backlinks = build_backlinks(&sites);
+        Self {
+            sites,
+            index,
+            backlinks,
+            page_cache: Mutex::default(),
+            global_rank: OnceLock::new(),
+            min_title_len: 0,
+            #[cfg(feature = "embeddings")]
+            embeddings: HashMap::new(),
+        }
     }
 
-    pub fn search(&self, term: &str) -> Option<Vec<&SiteLog>> {
-        let mut site_key_to_graph_keys = HashMap::new();
-        let mut graph: ConnectionGraph<Option<SiteKey>> = ConnectionGraph::default();
+    /// Excludes sites whose trimmed titles are shorter than `len` characters from searches, such
+    /// as untitled error and redirect pages. Defaults to 0, matching every title
+    pub fn set_min_title_len(&mut self, len: usize) {
+        self.min_title_len = len;
+        *self.page_cache.get_mut().expect("Page cache poisoned") = None;
+    }
 
-        let within_term = self.reduce_registry_by_term(term);
-        if within_term.len() == 0 {
-            return None;
+    /// Saves the registry with its index and global rank, if it's been computed, as a JSON file
+    pub fn save<P: Into<PathBuf>>(&self, file: P) -> Result<(), PersistError> {
+        let mut writer = BufWriter::new(File::create(file.into())?);
+        serde_json::to_writer(
+            &mut writer,
+            &SavingRanker {
+                version: RANKER_FORMAT_VERSION,
+                sites: &self.sites,
+                index: &self.index,
+                global_rank: self
+                    .global_rank
+                    .get()
+                    .map(|rank| rank.iter().map(|(key, rank)| (*key, *rank)).collect()),
+            },
+        )?;
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Loads a ranker saved with [`PageRanker::save`], without re-indexing or re-ranking
+    pub fn load<P: Into<PathBuf>>(file: P) -> Result<Self, PersistError> {
+        let mut buf = String::new();
+        BufReader::new(File::open(file.into())?).read_to_string(&mut buf)?;
+
+        let SavedVersion { version } = serde_json::from_str(&buf)?;
+        if version != RANKER_FORMAT_VERSION {
+            return Err(PersistError::Version {
+                found: version,
+                expected: RANKER_FORMAT_VERSION,
+            });
+        }
+
+        let saved: SavedRanker = serde_json::from_str(&buf)?;
+        let global_rank = OnceLock::new();
+        if let Some(rank) = saved.global_rank {
+            let _ = global_rank.set(rank.into_iter().collect());
+        }
+
+        Ok(Self {
+            backlinks: build_backlinks(&saved.sites),
+            sites: saved.sites,
+            index: saved.index,
+            page_cache: Mutex::default(),
+            global_rank,
+            min_title_len: 0,
+            #[cfg(feature = "embeddings")]
+            embeddings: HashMap::new(),
+        })
+    }
+
+    /// Writes every site's text as newline delimited JSON of `{key, url, text}`, ready to be
+    /// embedded. Sites whose text wasn't kept while crawling are exported with their title
+    #[cfg(feature = "embeddings")]
+    pub fn export_documents<P: Into<PathBuf>>(&self, file: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(file.into())?);
+
+        for (key, site) in &self.sites {
+            let document = Document {
+                key,
+                url: &site.url,
+                text: site.text.as_deref().unwrap_or(&site.title),
+            };
+            serde_json::to_writer(&mut writer, &document)?;
+            writer.write_all(b"\n")?;
+        }
+
+        writer.flush()
+    }
+
+    /// Stores a precomputed embedding for a site, replacing any it had. Ignored for sites that
+    /// aren't in the registry
+    #[cfg(feature = "embeddings")]
+    pub fn set_embedding<const N: usize>(&mut self, key: SiteKey, embedding: Vector<N>) {
+        if self.sites.contains_key(key) {
+            self.embeddings.insert(key, embedding.data.to_vec());
+        }
+    }
+
+    /// A site's stored embedding, if it has one with `N` dimensions
+    #[cfg(feature = "embeddings")]
+    pub fn embedding<const N: usize>(&self, key: SiteKey) -> Option<Vector<N>> {
+        let embedding = self.embeddings.get(&key)?;
+        Vector::try_from(embedding.clone()).ok()
+    }
+
+    /// The `top_k` sites whose embeddings are most similar to a query's by cosine similarity, from
+    /// most to least similar. `alpha` blends in global PageRank, relative to the best ranked
+    /// candidate, the same way it weighs rank against relevance in searches. Sites without an
+    /// embedding of `N` dimensions are skipped
+    #[cfg(feature = "embeddings")]
+    pub fn search_semantic<const N: usize>(
+        &self,
+        query_embedding: &Vector<N>,
+        top_k: usize,
+        alpha: f32,
+    ) -> Vec<(&SiteLog, f32)> {
+        let similar: Vec<_> = self
+            .embeddings
+            .keys()
+            .filter_map(|key| {
+                let similarity = self
+                    .embedding::<N>(*key)?
+                    .cosine_similarity(query_embedding);
+                Some((*key, similarity))
+            })
+            .collect();
+
+        let global_rank = if alpha > 0f32 {
+            self.global_rank()
+        } else {
+            &HashMap::new()
+        };
+        let rank = |key| global_rank.get(&key).copied().unwrap_or_default();
+        let max_rank = similar
+            .iter()
+            .map(|(key, _)| rank(*key))
+            .fold(0f32, f32::max);
+
+        similar
+            .into_iter()
+            .map(|(key, similarity)| {
+                let rank = if max_rank > 0f32 {
+                    rank(key) / max_rank
+                } else {
+                    0f32
+                };
+                (key, alpha * rank + (1f32 - alpha) * similarity)
+            })
+            .sorted_by(|(prev_key, prev), (key, score)| {
+                score
+                    .total_cmp(prev)
+                    .then_with(|| self.tie_break(*prev_key, *key))
+            })
+            .take(top_k)
+            .map(|(key, score)| (&self.sites[key], score))
+            .collect()
+    }
+
+    /// Adds a site to the registry, indexing it and recording it as a backlink of every site it
+    /// links to. Cached ranks are discarded
+    pub fn add_site(&mut self, site: SiteLog) -> SiteKey {
+        let key = self.sites.insert(site);
+        let site = &self.sites[key];
+
+        self.index.insert(key, &site.title);
+        for target in site.connections.iter().unique() {
+            if *target != key {
+                self.backlinks.entry(*target).or_default().push(key);
+            }
+        }
+
+        self.invalidate();
+        key
+    }
+
+    /// Removes a site from the registry, its index and backlinks, along with every link to it.
+    /// Cached ranks are discarded
+    pub fn remove_site(&mut self, key: SiteKey) -> Option<SiteLog> {
+        let site = self.sites.remove(key)?;
+        #[cfg(feature = "embeddings")]
+        self.embeddings.remove(&key);
+
+        self.index.remove(key, &site.title);
+        for target in &site.connections {
+            if let Some(linking) = self.backlinks.get_mut(target) {
+                linking.retain(|linking| *linking != key);
+            }
+        }
+        for linking in self.backlinks.remove(&key).unwrap_or_default() {
+            if let Some(linking) = self.sites.get_mut(linking) {
+                linking.connections.retain(|target| *target != key);
+            }
+        }
+
+        self.invalidate();
+        Some(site)
+    }
+
+    /// Discards ranks computed before the registry changed
+    fn invalidate(&mut self) {
+        self.global_rank = OnceLock::new();
+        *self.page_cache.get_mut().expect("Page cache poisoned") = None;
+    }
+
+    /// Sites that link to a site, not counting its link to itself
+    pub fn backlinks(&self, key: SiteKey) -> &[SiteKey] {
+        self.backlinks.get(&key).map_or(&[], Vec::as_slice)
+    }
+
+    /// How many sites link to a site, not counting its link to itself
+    pub fn backlink_count(&self, key: SiteKey) -> usize {
+        self.backlinks(key).len()
+    }
+
+    /// Fraction of links between distinct sites that are returned by a link back, ignoring each
+    /// site's link to itself. A registry without such links has a reciprocity of 0
+    pub fn link_reciprocity(&self) -> f32 {
+        let links: HashSet<_> = self
+            .sites
+            .iter()
+            .flat_map(|(key, site)| site.connections.iter().map(move |target| (key, *target)))
+            .filter(|(from, to)| from != to && self.sites.contains_key(*to))
+            .collect();
+
+        if links.is_empty() {
+            return 0f32;
         }
 
-        for site_key in &within_term {
-            site_key_to_graph_keys.insert(site_key, graph.register());
-            graph.set_val(site_key_to_graph_keys[&site_key], Some(*site_key));
+        let reciprocated = links
+            .iter()
+            .filter(|(from, to)| links.contains(&(*to, *from)))
+            .count();
+
+        reciprocated as f32 / links.len() as f32
+    }
+
+    /// The title token index used for term reduction
+    pub fn index(&self) -> &InvertedIndex {
+        &self.index
+    }
+
+    /// PageRank of every site over the link graph of the entire registry. This is computed the
+    /// first time it's needed and reused after, and is empty if ranking fails to converge
+    pub fn global_rank(&self) -> &HashMap<SiteKey, f32> {
+        self.global_rank.get_or_init(|| {
+            let keys: Vec<_> = self.sites.keys().collect();
+            self.rank_subgraph(
+                &keys,
+                &SearchOptions::default(),
+                &NOT_CANCELLED,
+                &mut SearchMetrics::default(),
+            )
+            .unwrap_or_default()
+        })
+    }
+
+    /// The `n` highest globally ranked sites, from highest to lowest
+    pub fn top_sites(&self, n: usize) -> Vec<&SiteLog> {
+        self.globally_ranked()
+            .into_iter()
+            .take(n)
+            .map(|(key, _)| &self.sites[key])
+            .collect()
+    }
+
+    /// Every host in the registry with the total global rank of its sites and how many sites it
+    /// has, from highest to lowest total rank. Sites without a readable host are left out
+    pub fn domain_ranking(&self) -> Vec<(String, f32, usize)> {
+        let global_rank = self.global_rank();
+        let mut domains: HashMap<String, (f32, usize)> = HashMap::new();
+
+        for (key, site) in &self.sites {
+            let (Some(host), Some(rank)) = (host_of(&site.url), global_rank.get(&key)) else {
+                continue;
+            };
+            let (total, pages) = domains.entry(host).or_default();
+            *total += rank;
+            *pages += 1;
         }
 
-        for _ in 0..(RESULTS_TO_SHOW - within_term.len()) {
-            let empty = graph.register();
-            graph.set_val(empty, None);
-            graph.connect(empty, empty, 1.0);
+        domains
+            .into_iter()
+            .map(|(host, (total, pages))| (host, total, pages))
+            .sorted_by(|(prev_host, prev, _), (host, total, _)| {
+                total.total_cmp(prev).then_with(|| prev_host.cmp(host))
+            })
+            .collect()
+    }
+
+    /// Keeps only the first result from each host in results ordered from best to worst. Results
+    /// without a readable host are all kept
+    fn collapse_domains(&self, results: &mut Vec<ScoredSite>) {
+        let mut seen = HashSet::new();
+        results.retain(|result| {
+            host_of(&self.sites[result.key].url).is_none_or(|host| seen.insert(host))
+        });
+    }
+
+    /// Drops every site sharing a content hash with a site that's more linked to
+    fn collapse_duplicates(&self, keys: &mut Vec<SiteKey>) {
+        let mut best: HashMap<u64, SiteKey> = HashMap::new();
+        for key in keys.iter() {
+            if let Some(hash) = self.sites[*key].content_hash {
+                best.entry(hash)
+                    .and_modify(|best| {
+                        if self.tie_break(*key, *best).is_lt() {
+                            *best = *key;
+                        }
+                    })
+                    .or_insert(*key);
+            }
         }
 
-        for (site_key, graph_key) in &site_key_to_graph_keys {
-            let mut connections: Vec<_> = self.sites[**site_key]
+        keys.retain(|key| {
+            self.sites[*key]
+                .content_hash
+                .is_none_or(|hash| best[&hash] == *key)
+        });
+    }
+
+    /// PageRank within the subgraph induced by a set of sites, following only links between them,
+    /// from highest to lowest. Keys missing from the registry are skipped, and `None` means the
+    /// subgraph couldn't be ranked
+    pub fn rank_subset(&self, keys: &[SiteKey]) -> Option<Vec<(&SiteLog, f32)>> {
+        let mut seen = HashSet::new();
+        let within: Vec<_> = keys
+            .iter()
+            .copied()
+            .filter(|key| self.sites.contains_key(*key) && seen.insert(*key))
+            .collect();
+
+        let ranks = self
+            .rank_subgraph(
+                &within,
+                &SearchOptions::default(),
+                &NOT_CANCELLED,
+                &mut SearchMetrics::default(),
+            )
+            .ok()?;
+
+        Some(
+            ranks
+                .into_iter()
+                .sorted_by(|(prev_key, prev), (key, rank)| {
+                    rank.total_cmp(prev)
+                        .then_with(|| self.tie_break(*prev_key, *key))
+                })
+                .map(|(key, rank)| (&self.sites[key], rank))
+                .collect(),
+        )
+    }
+
+    /// Sites related to a site by personalized PageRank over its two hop link neighborhood, with
+    /// every random jump landing back on the site. Returns the top `n` besides the site itself,
+    /// or nothing if the site is unknown or links to and from nowhere
+    pub fn related(&self, key: SiteKey, n: usize) -> Vec<SearchResult<'_>> {
+        if !self.sites.contains_key(key) {
+            return vec![];
+        }
+        let neighbors = |key: SiteKey| {
+            self.sites[key]
                 .connections
                 .iter()
-                .filter(|key| within_term.contains(key))
-                .unique()
-                .collect();
-            if !connections.contains(site_key) {
-                connections.push(site_key);
-            }
+                .chain(self.backlinks(key))
+                .copied()
+                .filter(move |neighbor| *neighbor != key && self.sites.contains_key(*neighbor))
+        };
+        if neighbors(key).next().is_none() {
+            return vec![];
+        }
 
-            let prob = 1f32 / connections.len() as f32;
+        let mut within = vec![key];
+        let mut seen: HashSet<_> = within.iter().copied().collect();
+        let direct: Vec<_> = neighbors(key)
+            .filter(|neighbor| seen.insert(*neighbor))
+            .collect();
+        within.extend(&direct);
+        for neighbor in direct {
+            within.extend(neighbors(neighbor).filter(|second| seen.insert(*second)));
+        }
 
+        let options = SearchOptions::default();
+        let mut graph: ConnectionGraph<SiteKey> = ConnectionGraph::default();
+        let graph_keys: HashMap<_, _> = within
+            .iter()
+            .map(|site_key| {
+                let graph_key = graph.register();
+                graph.set_val(graph_key, *site_key);
+                (*site_key, graph_key)
+            })
+            .collect();
+        for site_key in &within {
+            let connections =
+                self.connections_within(*site_key, |key| seen.contains(key), &options);
+            let prob = 1f32 / connections.len() as f32;
             for connection in connections {
-                graph.connect(*graph_key, site_key_to_graph_keys[connection], prob);
+                graph.connect(graph_keys[site_key], graph_keys[&connection], prob);
             }
         }
 
-        let rankings = graph.get_rankings::<RESULTS_TO_SHOW>()?;
+        let Some(ranks) = graph.personalized_ranks(
+            &[(graph_keys[&key], 1f32)],
+            options.max_iterations,
+            options.tolerance,
+        ) else {
+            return vec![];
+        };
+
+        let mut ranked: Vec<_> = ranks
+            .into_iter()
+            .map(|(node, rank)| (graph.nodes[node].item, rank))
+            .filter(|(site_key, _)| *site_key != key)
+            .collect();
+        ranked.sort_by(|(prev_key, prev), (site_key, rank)| {
+            rank.total_cmp(prev)
+                .then_with(|| self.tie_break(*prev_key, *site_key))
+        });
+        ranked.truncate(n);
 
-        let top_sites: Vec<_> = rankings
+        let max_rank = ranked.first().map_or(0f32, |(_, rank)| *rank);
+        let scored: Vec<_> = ranked
             .into_iter()
-            .filter_map(|key| graph.nodes[key].item)
-            .map(|key| &self.sites[key])
+            .map(|(site_key, rank)| {
+                let rank = normalize(rank, max_rank);
+                ScoredSite {
+                    key: site_key,
+                    rank,
+                    relevance: 0f32,
+                    alpha: 1f32,
+                    boost: 1f32,
+                    score: rank,
+                    title_highlights: vec![],
+                    snippet: None,
+                }
+            })
             .collect();
 
-        Some(top_sites)
+        self.materialize(&scored)
     }
 
-    /// Creates a reduced slotmap based on titles that match a search term
-    fn reduce_registry_by_term(&self, term: &str) -> Vec<SiteKey> {
-        let valid = self
-            .sites
-            .clone()
+    /// Orders sites with equal scores by how many sites link to them, most first, and then by URL
+    fn tie_break(&self, first: SiteKey, second: SiteKey) -> CmpOrdering {
+        self.backlink_count(second)
+            .cmp(&self.backlink_count(first))
+            .then_with(|| self.sites[first].url.cmp(&self.sites[second].url))
+    }
+
+    /// Orders scored results with equal scores by the given criterion
+    fn break_tie(
+        &self,
+        first: &ScoredSite,
+        second: &ScoredSite,
+        tie_break: TieBreak,
+    ) -> CmpOrdering {
+        let by_title = || {
+            let first = &self.sites[first.key];
+            let second = &self.sites[second.key];
+            first
+                .title
+                .to_lowercase()
+                .cmp(&second.title.to_lowercase())
+                .then_with(|| first.url.cmp(&second.url))
+        };
+
+        match tie_break {
+            TieBreak::Backlinks => self.tie_break(first.key, second.key),
+            TieBreak::Relevance => second
+                .relevance
+                .total_cmp(&first.relevance)
+                .then_with(by_title),
+            TieBreak::Title => by_title(),
+        }
+    }
+
+    /// Every globally ranked site from highest to lowest, with ties broken deterministically
+    fn globally_ranked(&self) -> Vec<(SiteKey, f32)> {
+        let global_rank = self.global_rank();
+
+        self.sites
+            .keys()
+            .filter_map(|key| global_rank.get(&key).map(|rank| (key, *rank)))
+            .sorted_by(|(prev_key, prev), (key, rank)| {
+                rank.total_cmp(prev)
+                    .then_with(|| self.tie_break(*prev_key, *key))
+            })
+            .collect()
+    }
+
+    /// The highest globally ranked sites that pass the search filters, scored by global rank
+    /// alone. Used in place of matches for blank queries
+    fn global_top(&self, options: &SearchOptions) -> Vec<ScoredSite> {
+        let ranked: Vec<_> = self
+            .globally_ranked()
             .into_iter()
-            .filter(|(_, site)| site.title.to_lowercase().contains(&term.to_lowercase()))
-            .map(|(key, _)| key)
-            .collect::<Vec<_>>();
+            .filter(|(key, _)| self.passes_filters(*key, options))
+            .take(RESULTS_TO_SHOW)
+            .collect();
+        let max_rank = ranked.first().map_or(0f32, |(_, rank)| *rank);
+
+        ranked
+            .into_iter()
+            .map(|(key, rank)| {
+                let rank = normalize(rank, max_rank);
+                ScoredSite {
+                    key,
+                    rank,
+                    relevance: 0f32,
+                    alpha: 1f32,
+                    boost: 1f32,
+                    score: rank,
+                    title_highlights: vec![],
+                    snippet: None,
+                }
+            })
+            .collect()
+    }
+
+    /// Searches for sites matching a term, ordered by their blended score with default options.
+    /// Blank terms give the top sites overall. Returns an empty list when nothing matches, and
+    /// `None` when the term can't be parsed or ranking the matches fails. Use
+    /// [`PageRanker::try_search`] to tell these apart
+    pub fn search(&self, term: &str) -> Option<Vec<&SiteLog>> {
+        match self.search_outcome(term, &SearchOptions::default()) {
+            SearchOutcome::Ranked { results, .. } | SearchOutcome::GlobalTop { results, .. } => {
+                Some(results.into_iter().map(|result| result.site).collect())
+            }
+            SearchOutcome::Failed(_) | SearchOutcome::Cancelled => None,
+        }
+    }
+
+    /// Searches for sites matching a term like [`PageRanker::search_outcome`], but reports a
+    /// term matching nothing or a failed search as an error saying why
+    pub fn try_search(
+        &self,
+        term: &str,
+        options: &SearchOptions,
+    ) -> Result<SearchOutcome<'_>, SearchError> {
+        match self.search_outcome(term, options) {
+            SearchOutcome::Failed(err) => Err(err),
+            outcome if outcome.results().is_empty() => Err(SearchError::NoMatches),
+            outcome => Ok(outcome),
+        }
+    }
+
+    /// Runs [`PageRanker::search`] for every term, sharing the index and global rank between them.
+    /// With the `parallel` feature the terms are searched across threads
+    pub fn search_batch(&self, terms: &[&str]) -> Vec<Option<Vec<&SiteLog>>> {
+        #[cfg(feature = "parallel")]
+        {
+            use rayon::prelude::*;
+            terms.par_iter().map(|term| self.search(term)).collect()
+        }
+
+        #[cfg(not(feature = "parallel"))]
+        {
+            terms.iter().map(|term| self.search(term)).collect()
+        }
+    }
+
+    /// Searches for sites matching a term like [`PageRanker::search_ranked`], flagging whether
+    /// the term was blank and the top sites overall were given instead of matches
+    pub fn search_outcome(&self, term: &str, options: &SearchOptions) -> SearchOutcome<'_> {
+        let blank = is_blank(term);
+
+        match self.score_matches(term, options, &NOT_CANCELLED) {
+            Ok(scored) => self.outcome(&scored, blank),
+            Err(err) => SearchOutcome::Failed(err),
+        }
+    }
+
+    /// Attaches the registry entries to a finished search
+    fn outcome(&self, scored: &Scored, blank: bool) -> SearchOutcome<'_> {
+        let results = self.materialize(&scored.sites);
+        let metrics = scored.metrics;
 
-        if valid.len() < RESULTS_TO_SHOW {
-            valid
+        if blank {
+            SearchOutcome::GlobalTop { results, metrics }
         } else {
-            valid[..RESULTS_TO_SHOW].to_vec()
+            SearchOutcome::Ranked { results, metrics }
+        }
+    }
+
+    /// Breaks down the score of the site at a URL for a search term with default options, or
+    /// `None` if no site there matches the term
+    pub fn explain_score(&self, term: &str, url: &str) -> Option<ScoreBreakdown> {
+        let key = self.sites.iter().find(|(_, site)| site.url == url)?.0;
+        let scored = self
+            .score_matches(term, &SearchOptions::default(), &NOT_CANCELLED)
+            .ok()?;
+        let site = scored.sites.into_iter().find(|scored| scored.key == key)?;
+
+        Some(ScoreBreakdown {
+            rank: site.rank,
+            relevance: site.relevance,
+            backlinks: self.backlink_count(key),
+            alpha: site.alpha,
+            boost: site.boost,
+            score: site.score,
+        })
+    }
+
+    /// Searches for sites matching a term, blending each match's PageRank within the query
+    /// subgraph with its text relevance. Blank terms give the top sites overall by global rank.
+    /// Returns an empty list when nothing matches, and `None` when the term can't be parsed, the
+    /// options can't be used or ranking the matches fails
+    pub fn search_ranked(
+        &self,
+        term: &str,
+        options: &SearchOptions,
+    ) -> Option<Vec<SearchResult<'_>>> {
+        let scored = self.score_matches(term, options, &NOT_CANCELLED).ok()?;
+
+        Some(self.materialize(&scored.sites))
+    }
+
+    /// Runs a ranked search on another thread, returning a handle to poll for its outcome so
+    /// interactive callers aren't blocked while the matches are ranked
+    pub fn search_spawn(self: &Arc<Self>, term: &str, options: SearchOptions) -> SearchHandle {
+        let (sender, receiver) = mpsc::channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+
+        let blank = is_blank(term);
+
+        let ranker = Arc::clone(self);
+        let term = term.to_string();
+        let cancelled = Arc::clone(&cancel);
+        thread::spawn(move || {
+            let scored = ranker.score_matches(&term, &options, &cancelled);
+            // The handle may have been dropped, in which case nobody wants the outcome
+            let _ = sender.send(scored);
+        });
+
+        SearchHandle {
+            ranker: Arc::clone(self),
+            cancel,
+            receiver,
+            blank,
+            finished: None,
+        }
+    }
+
+    /// Gets a single zero based page of a search's results with default options. The full match
+    /// set is only ranked once per query, with later pages sliced from a cache. Returns `None`
+    /// when ranking fails or `per_page` is 0, and an empty page past the last one
+    pub fn search_page(&self, term: &str, page: usize, per_page: usize) -> Option<SearchPage<'_>> {
+        self.search_page_with(term, &SearchOptions::default(), page, per_page)
+    }
+
+    /// Gets a single zero based page of a search's results, like [`PageRanker::search_page`]
+    /// with custom options
+    pub fn search_page_with(
+        &self,
+        term: &str,
+        options: &SearchOptions,
+        page: usize,
+        per_page: usize,
+    ) -> Option<SearchPage<'_>> {
+        if per_page == 0 {
+            return None;
+        }
+
+        let mut cache = self.page_cache.lock().expect("Page cache poisoned");
+        let scored = match cache.as_ref() {
+            Some((cached_term, cached_options, scored))
+                if cached_term == term && cached_options == options =>
+            {
+                scored
+            }
+            _ => {
+                let scored = self.score_matches(term, options, &NOT_CANCELLED).ok()?;
+                &cache.insert((term.to_string(), options.clone(), scored)).2
+            }
+        };
+
+        let total_matches = scored.sites.len();
+        let start = page.saturating_mul(per_page).min(total_matches);
+        let end = start.saturating_add(per_page).min(total_matches);
+
+        Some(SearchPage {
+            results: self.materialize(&scored.sites[start..end]),
+            total_matches,
+            page,
+            total_pages: total_matches.div_ceil(per_page),
+            truncated: scored.metrics.truncated,
+        })
+    }
+
+    /// Explains a site's rank for a search term through the links between it and the other
+    /// matches. Sites that don't match the term are explained with no rank or links
+    pub fn explain(&self, term: &str, site: SiteKey) -> RankExplanation {
+        self.explain_with(term, site, &SearchOptions::default())
+    }
+
+    /// Explains a site's rank like [`PageRanker::explain`], building the link graph with custom
+    /// options
+    pub fn explain_with(
+        &self,
+        term: &str,
+        site: SiteKey,
+        options: &SearchOptions,
+    ) -> RankExplanation {
+        let within_term = match Query::parse(term) {
+            Ok(query) => self.reduce_registry_by_term(&query, options),
+            Err(_) => vec![],
+        };
+
+        if !within_term.contains(&site) {
+            return RankExplanation {
+                site,
+                rank: None,
+                inbound: vec![],
+                outbound: vec![],
+            };
         }
+
+        let members: HashSet<_> = within_term.iter().copied().collect();
+        let is_member = |key: &SiteKey| members.contains(key);
+        let inbound = within_term
+            .iter()
+            .filter(|key| {
+                **key != site
+                    && self
+                        .connections_within(**key, is_member, options)
+                        .contains(&site)
+            })
+            .copied()
+            .collect();
+
+        let outbound = self.connections_within(site, is_member, options);
+
+        RankExplanation {
+            site,
+            rank: self
+                .rank_subgraph(
+                    &within_term,
+                    options,
+                    &NOT_CANCELLED,
+                    &mut SearchMetrics::default(),
+                )
+                .ok()
+                .map(|ranks| ranks[&site]),
+            inbound,
+            outbound: self.transitions(site, outbound, options).0,
+        }
+    }
+
+    /// Attaches the registry entries to scored matches
+    fn materialize(&self, scored: &[ScoredSite]) -> Vec<SearchResult<'_>> {
+        scored
+            .iter()
+            .map(|scored| SearchResult {
+                key: scored.key,
+                site: &self.sites[scored.key],
+                rank: scored.rank,
+                relevance: scored.relevance,
+                score: scored.score,
+                backlinks: self.backlink_count(scored.key),
+                title_highlights: scored.title_highlights.clone(),
+                snippet: scored.snippet.clone(),
+                description: self.sites[scored.key].description.as_deref(),
+            })
+            .collect()
+    }
+
+    /// Finds every site matching a term with its blended score, from highest to lowest. Any
+    /// `site:domain` tokens in the term are treated as included domains. Terms matching nothing
+    /// give no sites rather than an error
+    fn score_matches(
+        &self,
+        term: &str,
+        options: &SearchOptions,
+        cancel: &AtomicBool,
+    ) -> Result<Scored, SearchError> {
+        validate_options(options)?;
+        let mut metrics = SearchMetrics::default();
+
+        let reduce_start = Instant::now();
+        let query = Query::parse(term).map_err(|err| SearchError::InvalidQuery {
+            position: Some(err.position),
+            message: err.message,
+        })?;
+        if query.is_blank() {
+            // Site filters on their own narrow down the global top sites
+            let mut options = options.clone();
+            options.include_domains.extend(query.sites());
+
+            let mut sites = self.global_top(&options);
+            metrics.candidates = sites.len();
+            if options.collapse_domains {
+                self.collapse_domains(&mut sites);
+            }
+            if options.normalize_scores {
+                normalize_scores(&mut sites);
+            }
+            metrics.reduce_time = reduce_start.elapsed();
+            metrics.truncated = cut_off(&mut sites, &options);
+            return Ok(Scored { sites, metrics });
+        }
+
+        let within_term = self.reduce_registry_by_term(&query, options);
+        metrics.candidates = within_term.len();
+        metrics.reduce_time = reduce_start.elapsed();
+        if within_term.is_empty() {
+            return Ok(Scored {
+                sites: vec![],
+                metrics,
+            });
+        }
+
+        if cancel.load(Ordering::Relaxed) {
+            return Err(SearchError::RankingFailed(RankingFailure::Aborted));
+        }
+
+        let ranks = match self.rank_subgraph(&within_term, options, cancel, &mut metrics) {
+            Ok(ranks) => ranks,
+            Err(RankingFailure::Aborted) => {
+                return Err(SearchError::RankingFailed(RankingFailure::Aborted))
+            }
+            Err(_) if options.fallback_ranking => self.backlink_ranks(&within_term),
+            Err(failure) => return Err(SearchError::RankingFailed(failure)),
+        };
+        let tokens = query.positive_tokens();
+        let relevance = self.text_relevance(
+            &tokens,
+            &within_term,
+            &options.field_weights,
+            options.parallel_scoring,
+        );
+
+        let max_rank = ranks.values().copied().fold(0f32, f32::max);
+        let max_relevance = relevance.values().copied().fold(0f32, f32::max);
+
+        let now = unix_now();
+        let mut results = map_candidates(&within_term, options.parallel_scoring, |key| {
+            let rank = normalize(ranks[key], max_rank);
+            let relevance = normalize(relevance[key], max_relevance);
+            let title_boost = match title_match(&self.sites[*key].title, &tokens) {
+                TitleMatch::Exact => options.exact_title_boost,
+                TitleMatch::Prefix => options.title_prefix_boost,
+                TitleMatch::Word => options.title_word_boost,
+                TitleMatch::Partial => 1f32,
+            };
+            let boost = options.recency_half_life.map_or(title_boost, |half_life| {
+                title_boost * recency(self.sites[*key].last_crawled, now, half_life)
+            });
+
+            ScoredSite {
+                key: *key,
+                rank,
+                relevance,
+                alpha: options.alpha,
+                boost,
+                score: (options.alpha * rank + (1f32 - options.alpha) * relevance) * boost,
+                title_highlights: title_highlights(&self.sites[*key].title, &tokens),
+                snippet: self.sites[*key]
+                    .text
+                    .as_deref()
+                    .and_then(|text| snippet(text, &tokens)),
+            }
+        });
+
+        results.sort_by(|prev, result| {
+            result
+                .score
+                .total_cmp(&prev.score)
+                .then_with(|| self.break_tie(prev, result, options.tie_break))
+        });
+        if options.collapse_domains {
+            self.collapse_domains(&mut results);
+        }
+        if options.normalize_scores {
+            normalize_scores(&mut results);
+        }
+        metrics.truncated = cut_off(&mut results, options);
+
+        Ok(Scored {
+            sites: results,
+            metrics,
+        })
+    }
+
+    /// Builds the link graph between a set of sites and finds each site's steady state rank,
+    /// recording how long each part took
+    fn rank_subgraph(
+        &self,
+        within_term: &[SiteKey],
+        options: &SearchOptions,
+        cancel: &AtomicBool,
+        metrics: &mut SearchMetrics,
+    ) -> Result<HashMap<SiteKey, f32>, RankingFailure> {
+        // No links can change the ranking of a single site, so there's nothing to solve
+        if let [] | [_] = within_term {
+            return Ok(within_term.iter().map(|key| (*key, 1f32)).collect());
+        }
+
+        let build_start = Instant::now();
+        let mut graph_keys = HashMap::with_capacity(within_term.len());
+        let mut graph: ConnectionGraph<SiteKey> = ConnectionGraph::default();
+        let mut spreader = None;
+
+        for site_key in within_term {
+            let graph_key = graph.register();
+            graph.set_val(graph_key, *site_key);
+            graph_keys.insert(*site_key, graph_key);
+        }
+
+        // The graph keys double as the set of sites within the subgraph
+        for site_key in within_term {
+            let connections =
+                self.connections_within(*site_key, |key| graph_keys.contains_key(key), options);
+
+            let from = graph_keys[site_key];
+            let (transitions, held_back) = self.transitions(*site_key, connections, options);
+            for (connection, prob) in transitions {
+                graph.connect(from, graph_keys[&connection], prob);
+            }
+
+            // Rank held back from internal links goes to a node without links of its own, which
+            // spreads it evenly like any other dangling node
+            if held_back > 0f32 {
+                let spreader = *spreader.get_or_insert_with(|| graph.register());
+                graph.connect(from, spreader, held_back);
+            }
+        }
+
+        metrics.graph_build_time = build_start.elapsed();
+        let rankings = solve(&graph, options, cancel, metrics)?;
+
+        // The spreader's own rank is shared back out, so the sites' ranks still sum to 1
+        let spread = spreader.map_or(0f32, |spreader| {
+            rankings
+                .iter()
+                .find(|(key, _)| *key == spreader)
+                .map_or(0f32, |(_, rank)| *rank)
+        });
+        Ok(rankings
+            .into_iter()
+            .filter(|(key, _)| Some(*key) != spreader)
+            .map(|(key, rank)| (graph.nodes[key].item, rank / (1f32 - spread)))
+            .collect())
+    }
+
+    /// Ranks sites by how many crawled sites link to them, a cheap stand in for PageRank
+    fn backlink_ranks(&self, within_term: &[SiteKey]) -> HashMap<SiteKey, f32> {
+        within_term
+            .iter()
+            .map(|key| (*key, self.backlink_count(*key) as f32))
+            .collect()
+    }
+
+    /// The distinct sites within a set that a site links to in key order, along with those linking
+    /// to it if `undirected` is set. Capped at `max_outdegree` of the most linked to, and followed
+    /// by itself if `self_loops` is set
+    fn connections_within<F>(
+        &self,
+        site: SiteKey,
+        is_member: F,
+        options: &SearchOptions,
+    ) -> Vec<SiteKey>
+    where
+        F: Fn(&SiteKey) -> bool,
+    {
+        let incoming = if options.undirected {
+            self.backlinks(site)
+        } else {
+            &[]
+        };
+        let mut connections: Vec<_> = self.sites[site]
+            .connections
+            .iter()
+            .chain(incoming)
+            .filter(|key| **key != site && is_member(key))
+            .copied()
+            .collect();
+        // Sorting lets duplicates be dropped without hashing every link
+        connections.sort_unstable();
+        connections.dedup();
+
+        if let Some(max_outdegree) = options.max_outdegree {
+            if connections.len() > max_outdegree {
+                connections.sort_by(|first, second| self.tie_break(*first, *second));
+                connections.truncate(max_outdegree);
+            }
+        }
+
+        if options.self_loops {
+            connections.push(site);
+        }
+
+        connections
+    }
+
+    /// The chance of following each of a site's links when ranking, split evenly apart from links
+    /// within the site's own host, which only get `internal_link_weight` of their share. Also gives
+    /// the chance held back from those links, which is 0 unless `internal_link_weight` is below 1
+    fn transitions(
+        &self,
+        site: SiteKey,
+        connections: Vec<SiteKey>,
+        options: &SearchOptions,
+    ) -> (Vec<(SiteKey, f32)>, f32) {
+        let share = 1f32 / connections.len() as f32;
+        let host = host_of(&self.sites[site].url).filter(|_| options.internal_link_weight < 1f32);
+        let mut internal = 0;
+
+        let transitions = connections
+            .into_iter()
+            .map(|key| match &host {
+                Some(host) if host_of(&self.sites[key].url).as_ref() == Some(host) => {
+                    internal += 1;
+                    (key, share * options.internal_link_weight)
+                }
+                _ => (key, share),
+            })
+            .collect();
+
+        let held_back = internal as f32 * share * (1f32 - options.internal_link_weight);
+        (transitions, held_back)
+    }
+
+    /// Scores each matching site's weighted fields against the query tokens, weighting tokens by
+    /// how rare they are across the registry
+    fn text_relevance(
+        &self,
+        tokens: &[String],
+        within_term: &[SiteKey],
+        weights: &FieldWeights,
+        parallel: bool,
+    ) -> HashMap<SiteKey, f32> {
+        let idf: Vec<_> = tokens
+            .iter()
+            .map(|token| {
+                let containing = match self.index.get(token) {
+                    Some(keys) => keys.len(),
+                    None => self.scan_titles(token).len(),
+                };
+                inverse_document_frequency(self.sites.len(), containing)
+            })
+            .collect();
+
+        map_candidates(within_term, parallel, |key| {
+            (*key, weights.relevance(&self.sites[*key], tokens, &idf))
+        })
+        .into_iter()
+        .collect()
+    }
+
+    /// Finds the sites whose titles, or other weighted fields, match a query and that pass the
+    /// title length and domain filters. Past `RESULTS_TO_SHOW` matches only the highest globally
+    /// ranked are kept, best first
+    fn reduce_registry_by_term(&self, query: &Query, options: &SearchOptions) -> Vec<SiteKey> {
+        let eval = |key: &SiteKey| {
+            let site = &self.sites[*key];
+            if options.field_weights.beyond_title() {
+                let fields: Vec<_> = options
+                    .field_weights
+                    .fields(site)
+                    .into_iter()
+                    .map(|(text, _)| text)
+                    .collect();
+                query.eval_fields(&fields, &site.url)
+            } else {
+                query.eval(&site.title, &site.url)
+            }
+        };
+
+        let mut valid: Vec<_> = match self.candidates(query) {
+            Some((keys, exact)) if !options.field_weights.beyond_title() => keys
+                .iter()
+                .filter(|key| (exact || eval(key)) && self.passes_filters(**key, options))
+                .copied()
+                .collect(),
+            _ => self
+                .sites
+                .keys()
+                .filter(|key| eval(key) && self.passes_filters(*key, options))
+                .collect(),
+        };
+
+        if options.collapse_duplicates {
+            self.collapse_duplicates(&mut valid);
+        }
+
+        // Keep the most important matches rather than whichever were crawled first
+        if valid.len() > RESULTS_TO_SHOW {
+            let global_rank = self.global_rank();
+            let rank = |key: &SiteKey| global_rank.get(key).copied().unwrap_or_default();
+            valid.sort_by(|prev, key| {
+                rank(key)
+                    .total_cmp(&rank(prev))
+                    .then_with(|| self.tie_break(*prev, *key))
+            });
+            valid.truncate(RESULTS_TO_SHOW);
+        }
+
+        valid
+    }
+
+    /// Narrows down the sites that could match a query through the index, in registry order, and
+    /// whether they all certainly match. `None` means any site could match. Single terms borrow
+    /// their postings from the index
+    fn candidates(&self, query: &Query) -> Option<(Cow<'_, [SiteKey]>, bool)> {
+        let postings = |token: &String| match self.index.get(token) {
+            Some(keys) => Cow::Borrowed(keys),
+            None => Cow::Owned(self.scan_titles(token)),
+        };
+
+        match query {
+            Query::Term(token) => Some((postings(token), true)),
+            Query::Phrase(phrase) => Some((
+                Cow::Owned(intersect(phrase.iter().map(postings).collect())),
+                false,
+            )),
+            Query::And(parts) => {
+                let narrowed: Vec<_> = parts.iter().map(|part| self.candidates(part)).collect();
+                let exact = narrowed.iter().all(|part| matches!(part, Some((_, true))));
+                let known: Vec<_> = narrowed
+                    .into_iter()
+                    .flatten()
+                    .map(|(keys, _)| keys)
+                    .collect();
+
+                (!known.is_empty()).then(|| (Cow::Owned(intersect(known)), exact))
+            }
+            Query::Or(parts) => {
+                let mut exact = true;
+                let mut union = vec![];
+                for part in parts {
+                    let (keys, part_exact) = self.candidates(part)?;
+                    exact &= part_exact;
+                    union.extend_from_slice(&keys);
+                }
+                union.sort_unstable();
+                union.dedup();
+
+                Some((Cow::Owned(union), exact))
+            }
+            Query::Not(_) | Query::Field(..) => None,
+        }
+    }
+
+    /// Whether a site passes the title length and domain filters
+    fn passes_filters(&self, key: SiteKey, options: &SearchOptions) -> bool {
+        let site = &self.sites[key];
+        if self.min_title_len > 0 && site.title.trim().chars().count() < self.min_title_len {
+            return false;
+        }
+
+        if options.include_domains.is_empty() && options.exclude_domains.is_empty() {
+            return true;
+        }
+
+        let host = host_of(&site.url);
+        let on_any = |domains: &[String]| {
+            host.as_deref()
+                .is_some_and(|host| domains.iter().any(|domain| on_domain(host, domain)))
+        };
+
+        (options.include_domains.is_empty() || on_any(&options.include_domains))
+            && !on_any(&options.exclude_domains)
+    }
+
+    /// Brute-force scan for titles containing a substring, used for tokens the index doesn't know
+    fn scan_titles(&self, needle: &str) -> Vec<SiteKey> {
+        let needle = fold(needle);
+        self.sites
+            .iter()
+            .filter(|(_, site)| fold(&site.title).contains(&needle))
+            .map(|(key, _)| key)
+            .collect()
+    }
+}
+
+/// Maps every site to the distinct sites linking to it, in registry order, ignoring self links
+fn build_backlinks(sites: &SlotMap<SiteKey, SiteLog>) -> HashMap<SiteKey, Vec<SiteKey>> {
+    let mut backlinks: HashMap<_, Vec<_>> = HashMap::new();
+
+    for (key, site) in sites {
+        for target in site.connections.iter().unique() {
+            if *target != key {
+                backlinks.entry(*target).or_default().push(key);
+            }
+        }
+    }
+
+    backlinks
+}
+
+/// Finds the steady state rank of every node in a link graph, recording how long it took and how
+/// many steps. Graphs whose links don't each sum to 1 are rejected before any ranking is done
+fn solve(
+    graph: &ConnectionGraph<SiteKey>,
+    options: &SearchOptions,
+    cancel: &AtomicBool,
+    metrics: &mut SearchMetrics,
+) -> Result<Vec<(GraphKey, f32)>, RankingFailure> {
+    if !graph.is_stochastic() {
+        return Err(RankingFailure::InvalidGraph);
+    }
+
+    let rank_start = Instant::now();
+    let ranked =
+        graph.power_iteration_ranks_cancellable(options.max_iterations, options.tolerance, cancel);
+    metrics.rank_time = rank_start.elapsed();
+    let (rankings, iterations) = ranked.ok_or(if cancel.load(Ordering::Relaxed) {
+        RankingFailure::Aborted
+    } else {
+        RankingFailure::NotConverged {
+            iterations: options.max_iterations,
+        }
+    })?;
+    metrics.iterations = iterations;
+
+    Ok(rankings)
+}
+
+/// Whether a term parses to a query with nothing to match on, so the top sites overall are given
+fn is_blank(term: &str) -> bool {
+    Query::parse(term).is_ok_and(|query| query.is_blank())
+}
+
+/// Applies the `min_score` and `max_results` cutoffs to results ordered from best to worst,
+/// always keeping the best result, as `max_results` is never 0. Returns whether any results were
+/// cut
+fn cut_off(results: &mut Vec<ScoredSite>, options: &SearchOptions) -> bool {
+    let matched = results.len();
+    let best = results.first().map_or(0f32, |result| result.score);
+
+    if let Some(min_score) = options.min_score {
+        let kept = results
+            .iter()
+            .take_while(|result| normalize(result.score, best) >= min_score)
+            .count();
+        results.truncate(kept.max(1));
+    }
+
+    if let Some(max_results) = options.max_results {
+        results.truncate(max_results);
+    }
+
+    results.len() < matched
+}
+
+/// Scales the scores of results ordered from best to worst relative to the best
+fn normalize_scores(results: &mut [ScoredSite]) {
+    let best = results.first().map_or(0f32, |result| result.score);
+    for result in results {
+        result.score = normalize(result.score, best);
+    }
+}
+
+/// Maps every candidate in order, across threads if `parallel` is set and the `parallel` feature
+/// is enabled
+#[cfg_attr(not(feature = "parallel"), allow(unused_variables))]
+fn map_candidates<T, F>(candidates: &[SiteKey], parallel: bool, map: F) -> Vec<T>
+where
+    T: Send,
+    F: Fn(&SiteKey) -> T + Sync + Send,
+{
+    #[cfg(feature = "parallel")]
+    if parallel {
+        use rayon::prelude::*;
+        return candidates.par_iter().map(map).collect();
+    }
+
+    candidates.iter().map(map).collect()
+}
+
+/// Exponential decay by how long ago a site was crawled, from 1 when crawled now down towards 0.
+/// Sites never crawled are as stale as can be
+fn recency(last_crawled: Option<u64>, now: u64, half_life: Duration) -> f32 {
+    let Some(crawled) = last_crawled else {
+        return 0f32;
+    };
+
+    let age = now.saturating_sub(crawled) as f64;
+    0.5f64.powf(age / half_life.as_secs_f64()) as f32
+}
+
+/// Rejects options that can't give a meaningful ranking
+fn validate_options(options: &SearchOptions) -> Result<(), SearchError> {
+    if !(0f32..=1f32).contains(&options.alpha) {
+        Err(SearchError::InvalidQuery {
+            position: None,
+            message: format!("alpha must be between 0 and 1, not {}", options.alpha),
+        })
+    } else if options.tolerance.is_nan() || options.tolerance <= 0f32 {
+        Err(SearchError::InvalidQuery {
+            position: None,
+            message: "tolerance must be positive".to_string(),
+        })
+    } else if options.max_results == Some(0) {
+        Err(SearchError::InvalidQuery {
+            position: None,
+            message: "max results must be at least 1".to_string(),
+        })
+    } else if options.recency_half_life == Some(Duration::ZERO) {
+        Err(SearchError::InvalidQuery {
+            position: None,
+            message: "recency half-life must be positive".to_string(),
+        })
+    } else if !(0f32..=1f32).contains(&options.internal_link_weight) {
+        Err(SearchError::InvalidQuery {
+            position: None,
+            message: format!(
+                "internal link weight must be between 0 and 1, not {}",
+                options.internal_link_weight
+            ),
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// Byte ranges of a title matching any query token, ignoring case and accents
+fn title_highlights(title: &str, tokens: &[String]) -> Vec<Range<usize>> {
+    let folded = FoldedText::new(title);
+    merge_ranges(
+        tokens
+            .iter()
+            .flat_map(|token| folded.find_all(token))
+            .collect(),
+    )
+}
+
+/// The sites in every list, in the order of the first
+fn intersect(lists: Vec<Cow<'_, [SiteKey]>>) -> Vec<SiteKey> {
+    let Some((first, rest)) = lists.split_first() else {
+        return vec![];
+    };
+
+    let rest: Vec<HashSet<_>> = rest
+        .iter()
+        .map(|keys| keys.iter().copied().collect())
+        .collect();
+    first
+        .iter()
+        .filter(|key| rest.iter().all(|keys| keys.contains(key)))
+        .copied()
+        .collect()
+}
+
+/// Scales a score relative to the largest score in its set
+fn normalize(score: f32, max: f32) -> f32 {
+    if max > 0f32 {
+        score / max
+    } else {
+        0f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        borrow::Cow,
+        io::Write,
+        sync::{atomic::AtomicBool, Arc},
+        time::{Duration, Instant},
+    };
+
+    use itertools::Itertools;
+    use slotmap::SlotMap;
+
+    use crate::{
+        crawler::{host_of, unix_now, SiteKey, SiteLog},
+        graph_rank::{ConnectionGraph, CONVERGENCE_TOLERANCE, MAX_ITERATIONS},
+        relevance::FieldWeights,
+    };
+
+    use super::{
+        solve, PageRanker, PersistError, Query, RankingFailure, SearchError, SearchMetrics,
+        SearchOptions, SearchOutcome, SearchResult, TieBreak, DEFAULT_TITLE_WORD_BOOST,
+        RANKER_FORMAT_VERSION, RESULTS_TO_SHOW,
+    };
+
+    fn query(term: &str) -> Query {
+        Query::parse(term).expect("Parse query")
+    }
+
+    #[cfg(feature = "embeddings")]
+    #[test]
+    fn documents_exported_one_per_line() {
+        let mut sites = registry(&["Rust", "Go"]);
+        let keys: Vec<_> = sites.keys().collect();
+        sites[keys[0]].text = Some("Rust empowers everyone".to_string());
+        let mut ranker = PageRanker::from_registry(sites);
+        let file = tempfile::NamedTempFile::new().expect("Create export file");
+
+        ranker
+            .export_documents(file.path())
+            .expect("Export documents");
+
+        let contents = std::fs::read_to_string(file.path()).expect("Read export");
+        let documents: Vec<serde_json::Value> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("Parse document"))
+            .collect();
+        assert_eq!(documents.len(), 2);
+        assert_eq!(documents[0]["url"], "https://example.com/Rust");
+        assert_eq!(documents[0]["text"], "Rust empowers everyone");
+        assert_eq!(documents[1]["text"], "Go");
+        let key: SiteKey =
+            serde_json::from_value(documents[1]["key"].clone()).expect("Parse document key");
+        assert_eq!(key, keys[1]);
+
+        ranker.set_embedding(keys[0], crate::vector::Vector::from_data([0.6, 0.8]));
+        assert_eq!(
+            ranker.embedding::<2>(keys[0]),
+            Some(crate::vector::Vector::from_data([0.6, 0.8]))
+        );
+        assert_eq!(ranker.embedding::<3>(keys[0]), None);
+        assert_eq!(ranker.embedding::<2>(keys[1]), None);
+    }
+
+    #[cfg(feature = "embeddings")]
+    #[test]
+    fn most_similar_embedding_ranks_first() {
+        use crate::vector::Vector;
+
+        let mut sites = registry(&["Rust", "Go", "Cooking", "Unembedded"]);
+        let keys: Vec<_> = sites.keys().collect();
+        // Everything links to cooking, so blending in rank lifts it
+        for key in &keys {
+            sites[*key].connections = vec![keys[2], *key];
+        }
+        let mut ranker = PageRanker::from_registry(sites);
+        ranker.set_embedding(keys[0], Vector::from_data([0.9, 0.1, 0.0]));
+        ranker.set_embedding(keys[1], Vector::from_data([0.6, 0.4, 0.0]));
+        ranker.set_embedding(keys[2], Vector::from_data([0.0, 0.1, 0.9]));
+
+        let query = Vector::from_data([1.0, 0.0, 0.0]);
+        let results = ranker.search_semantic(&query, 10, 0.0);
+        let titles: Vec<_> = results
+            .iter()
+            .map(|(site, _)| site.title.as_str())
+            .collect();
+        assert_eq!(titles, ["Rust", "Go", "Cooking"]);
+        assert_eq!(ranker.search_semantic(&query, 1, 0.0).len(), 1);
+
+        let blended = ranker.search_semantic(&query, 10, 0.9);
+        assert_eq!(blended[0].0.title, "Cooking");
+    }
+
+    fn registry(titles: &[&str]) -> SlotMap<SiteKey, SiteLog> {
+        let mut sites = SlotMap::default();
+        for title in titles {
+            sites.insert(SiteLog {
+                url: format!("https://example.com/{}", title.replace(' ', "_")),
+                title: title.to_string(),
+                ..Default::default()
+            });
+        }
+        sites
+    }
+
+    #[test]
+    fn index_lookup_matches_brute_force_scan() {
+        let ranker = PageRanker::from_registry(registry(&[
+            "Rust (programming language)",
+            "Rust",
+            "Iron oxide and rust",
+            "Python (programming language)",
+            "Go",
+        ]));
+
+        for term in ["rust", "Python", "programming", "go", "language"] {
+            assert_eq!(
+                ranker.reduce_registry_by_term(&query(term), &SearchOptions::default()),
+                ranker.scan_titles(term),
+                "{term}"
+            );
+        }
+    }
+
+    #[test]
+    fn multi_token_queries_intersect() {
+        let sites = registry(&[
+            "Rust (programming language)",
+            "Rust",
+            "Python (programming language)",
+        ]);
+        let keys: Vec<_> = sites.keys().collect();
+        let ranker = PageRanker::from_registry(sites);
+
+        assert_eq!(
+            ranker.reduce_registry_by_term(&query("language rust"), &SearchOptions::default()),
+            &[keys[0]]
+        );
+    }
+
+    #[test]
+    fn unindexed_tokens_fall_back_to_substring_scan() {
+        let sites = registry(&["Rust (programming language)", "Python"]);
+        let keys: Vec<_> = sites.keys().collect();
+        let ranker = PageRanker::from_registry(sites);
+
+        assert!(ranker.index().get("progr").is_none());
+        assert_eq!(
+            ranker.reduce_registry_by_term(&query("progr"), &SearchOptions::default()),
+            &[keys[0]]
+        );
+    }
+
+    #[test]
+    fn large_registry_lookup_served_from_index() {
+        let mut sites = SlotMap::default();
+        for i in 0..100_000 {
+            sites.insert(SiteLog {
+                url: format!("https://example.com/{i}"),
+                title: format!("Site {i} topic{}", i % 1_000),
+                ..Default::default()
+            });
+        }
+        let ranker = PageRanker::from_registry(sites);
+
+        // A whole word is looked up in the index rather than scanned for across every title
+        let topic = query("topic42");
+        assert!(matches!(
+            ranker.candidates(&topic),
+            Some((Cow::Borrowed(keys), true)) if keys.len() == 100
+        ));
+        let matches = ranker.reduce_registry_by_term(&topic, &SearchOptions::default());
+        assert_eq!(matches.len(), 100);
+    }
+
+    #[test]
+    fn large_registry_ranks_match_reference_graph() {
+        // Sites only link to others on the same topic, and one link appears twice
+        let size = 100_000;
+        let mut sites = SlotMap::default();
+        let keys: Vec<_> = (0..size)
+            .map(|i| {
+                sites.insert(SiteLog {
+                    url: format!("https://example.com/{i}"),
+                    title: format!("Site {i} topic{}", i % 1_000),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        for (idx, key) in keys.iter().enumerate() {
+            for step in [1, 2, 3, 1] {
+                sites[*key]
+                    .connections
+                    .push(keys[(idx + step * 1_000 * (idx % 7 + 1)) % size]);
+            }
+        }
+
+        let members: Vec<_> = (42..size).step_by(1_000).map(|idx| keys[idx]).collect();
+        let mut graph: ConnectionGraph<SiteKey> = ConnectionGraph::default();
+        let nodes: Vec<_> = members.iter().map(|_| graph.register()).collect();
+        for (from, key) in members.iter().enumerate() {
+            graph.set_val(nodes[from], *key);
+            let mut targets: Vec<_> = sites[*key]
+                .connections
+                .iter()
+                .filter(|target| *target != key && members.contains(target))
+                .unique()
+                .copied()
+                .collect();
+            targets.push(*key);
+            for target in &targets {
+                let to = members
+                    .iter()
+                    .position(|key| key == target)
+                    .expect("Link target is a member");
+                graph.connect(nodes[from], nodes[to], 1.0 / targets.len() as f32);
+            }
+        }
+        let expected = graph
+            .power_iteration_ranks(MAX_ITERATIONS, CONVERGENCE_TOLERANCE)
+            .expect("Rank reference graph");
+        let max_rank = expected.iter().map(|(_, rank)| *rank).fold(0f32, f32::max);
+
+        let ranker = PageRanker::from_registry(sites);
+        let results = ranker
+            .search_ranked(
+                "topic42",
+                &SearchOptions {
+                    alpha: 1f32,
+                    ..Default::default()
+                },
+            )
+            .expect("Search");
+
+        assert_eq!(results.len(), members.len());
+        for (node, rank) in expected {
+            let key = graph.nodes[node].item;
+            let result = results
+                .iter()
+                .find(|result| result.key == key)
+                .expect("Reference node in results");
+            assert!((result.rank - rank / max_rank).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn duplicate_content_collapsed() {
+        let mut sites = registry(&["Rust", "Rust Mirror", "Rust Blog", "Rust Book"]);
+        let keys: Vec<_> = sites.keys().collect();
+        for key in &keys[..2] {
+            sites[*key].content_hash = Some(7);
+        }
+        sites[keys[2]].content_hash = Some(8);
+        sites[keys[2]].connections = vec![keys[1]];
+
+        let ranker = PageRanker::from_registry(sites);
+        let options = SearchOptions {
+            collapse_duplicates: true,
+            ..Default::default()
+        };
+
+        let all = ranker.reduce_registry_by_term(&query("rust"), &SearchOptions::default());
+        assert_eq!(all, keys);
+
+        // The mirror has a backlink, so it's kept over the original
+        let collapsed = ranker.reduce_registry_by_term(&query("rust"), &options);
+        assert_eq!(collapsed, [keys[1], keys[2], keys[3]]);
+    }
+
+    #[test]
+    fn subset_ranked_by_links_among_itself() {
+        let mut sites = registry(&["Rust", "Cargo", "Crates", "Python", "Pip"]);
+        let keys: Vec<_> = sites.keys().collect();
+        // Python's links into the subset don't count, as Python isn't in it
+        let links = [(0, 1), (2, 1), (1, 0), (3, 2), (3, 2), (4, 2)];
+        for (from, to) in links {
+            sites[keys[from]].connections.push(keys[to]);
+        }
+        let mut ranker = PageRanker::from_registry(sites);
+        let removed = ranker.add_site(SiteLog::default());
+        ranker.remove_site(removed);
+
+        let ranked = ranker
+            .rank_subset(&[keys[2], keys[0], keys[1], removed, keys[0]])
+            .expect("Rank subset");
+        let titles: Vec<_> = ranked.iter().map(|(site, _)| site.title.as_str()).collect();
+
+        assert_eq!(titles, ["Cargo", "Rust", "Crates"]);
+        assert!((ranked.iter().map(|(_, rank)| rank).sum::<f32>() - 1.0).abs() < 1e-4);
+        assert_eq!(ranker.rank_subset(&[]), Some(vec![]));
+    }
+
+    #[test]
+    fn undirected_ranking_lifts_chain_start() {
+        let mut sites = registry(&["Chain A", "Chain B", "Chain C"]);
+        let keys: Vec<_> = sites.keys().collect();
+        sites[keys[0]].connections.push(keys[1]);
+        sites[keys[1]].connections.push(keys[2]);
+        let ranker = PageRanker::from_registry(sites);
+
+        let ranks = |undirected| {
+            let options = SearchOptions {
+                undirected,
+                ..Default::default()
+            };
+            ranker
+                .rank_subgraph(
+                    &keys,
+                    &options,
+                    &AtomicBool::new(false),
+                    &mut SearchMetrics::default(),
+                )
+                .expect("Rank chain")
+        };
+        let directed = ranks(false);
+        let undirected = ranks(true);
+
+        // Directed, rank drains down the chain, while undirected the ends mirror each other
+        assert!(directed[&keys[0]] < directed[&keys[1]] && directed[&keys[1]] < directed[&keys[2]]);
+        assert!(undirected[&keys[0]] > directed[&keys[0]]);
+        assert!((undirected[&keys[0]] - undirected[&keys[2]]).abs() < 1e-4);
+        assert!(undirected[&keys[1]] > undirected[&keys[0]]);
+    }
+
+    #[test]
+    fn tiny_subgraphs_skip_ranking() {
+        let mut sites = registry(&["Rust", "Cargo", "Crates"]);
+        let keys: Vec<_> = sites.keys().collect();
+        sites[keys[1]].connections = vec![keys[0], keys[2]];
+        let ranker = PageRanker::from_registry(sites);
+        // Ranking anything that needs solving fails without a single iteration to do it in
+        let options = SearchOptions {
+            max_iterations: 0,
+            ..Default::default()
+        };
+
+        let outcome = ranker.search_outcome("cargo", &options);
+        let SearchOutcome::Ranked { results, metrics } = &outcome else {
+            panic!("Expected a ranked search, got {outcome:?}");
+        };
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].site.title, "Cargo");
+        assert_eq!(results[0].rank, 1.0);
+        assert_eq!(metrics.iterations, 0);
+
+        assert!(ranker
+            .search_outcome("python", &options)
+            .results()
+            .is_empty());
+        assert_eq!(ranker.search("python"), Some(vec![]));
+        assert!(matches!(
+            ranker.try_search("python", &options),
+            Err(SearchError::NoMatches)
+        ));
+        assert_eq!(ranker.rank_subset(&[]), Some(vec![]));
+        assert!(matches!(
+            ranker.search_outcome("c", &options),
+            SearchOutcome::Failed(SearchError::RankingFailed(_))
+        ));
+    }
+
+    #[test]
+    fn full_internal_link_weight_leaves_ranks_unchanged() {
+        let mut sites = registry(&["Rust Home", "Rust Blog", "Rust Book"]);
+        let keys: Vec<_> = sites.keys().collect();
+        // The book links nowhere, so it stays dangling rather than leaking rank
+        sites[keys[0]].connections = vec![keys[1]];
+        sites[keys[1]].connections = vec![keys[0], keys[2]];
+        let ranker = PageRanker::from_registry(sites);
+        let options = SearchOptions {
+            self_loops: false,
+            ..Default::default()
+        };
+
+        let ranks = ranker
+            .rank_subgraph(
+                &keys,
+                &options,
+                &AtomicBool::new(false),
+                &mut SearchMetrics::default(),
+            )
+            .expect("Rank subgraph");
+
+        let mut graph = ConnectionGraph::default();
+        let nodes: Vec<_> = keys
+            .iter()
+            .map(|key| {
+                let node = graph.register();
+                graph.set_val(node, *key);
+                node
+            })
+            .collect();
+        graph.connect(nodes[0], nodes[1], 1.0);
+        graph.connect(nodes[1], nodes[0], 0.5);
+        graph.connect(nodes[1], nodes[2], 0.5);
+        let (expected, _) = graph
+            .power_iteration_ranks_cancellable(
+                options.max_iterations,
+                options.tolerance,
+                &AtomicBool::new(false),
+            )
+            .expect("Rank reference graph");
+
+        for (node, rank) in expected {
+            assert_eq!(ranks[&graph.nodes[node].item], rank);
+        }
+        assert!((ranks.values().sum::<f32>() - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn internal_link_spam_discounted() {
+        let mut sites = SlotMap::default();
+        let mut add = |url: &str, title: &str| {
+            sites.insert(SiteLog {
+                url: url.to_string(),
+                title: title.to_string(),
+                ..Default::default()
+            })
+        };
+        let spam: Vec<_> = (0..4)
+            .map(|page| add(&format!("https://spam.com/{page}"), "Rust deals"))
+            .collect();
+        let genuine = [
+            add("https://guide.org/", "Rust guide"),
+            add("https://book.org/", "Rust book"),
+            add("https://docs.org/", "Rust docs"),
+            add("https://blog.org/", "Rust blog"),
+        ];
+
+        // Every spam page links to every other, and one stray link leads in from the rest
+        for page in &spam {
+            sites[*page].connections = spam.clone();
+        }
+        sites[genuine[0]].connections = vec![genuine[1], genuine[2]];
+        sites[genuine[1]].connections = vec![genuine[2], spam[0]];
+        sites[genuine[2]].connections = vec![genuine[0]];
+        sites[genuine[3]].connections = genuine[..3].to_vec();
+        let ranker = PageRanker::from_registry(sites);
+
+        let top_hosts = |internal_link_weight| {
+            let options = SearchOptions {
+                alpha: 1.0,
+                max_results: Some(4),
+                internal_link_weight,
+                ..Default::default()
+            };
+            ranker
+                .search_outcome("rust", &options)
+                .results()
+                .iter()
+                .map(|result| host_of(&result.site.url).expect("Host"))
+                .collect::<Vec<_>>()
+        };
+
+        assert!(top_hosts(1.0).iter().all(|host| host == "spam.com"));
+        let discounted = top_hosts(0.1);
+        assert!(discounted.iter().filter(|host| *host == "spam.com").count() <= 1);
+        assert_eq!(discounted[0], "docs.org");
+
+        let invalid = SearchOptions {
+            internal_link_weight: -1.0,
+            ..Default::default()
+        };
+        assert!(matches!(
+            ranker.search_outcome("rust", &invalid),
+            SearchOutcome::Failed(SearchError::InvalidQuery { .. })
+        ));
+    }
+
+    #[test]
+    fn related_sites_found_near_site() {
+        let mut sites = registry(&[
+            "Rust", "Cargo", "Crates", "Clippy", "Python", "Pip", "Alone",
+        ]);
+        let keys: Vec<_> = sites.keys().collect();
+        let links = [(0, 1), (1, 2), (2, 0), (3, 0), (4, 5), (5, 4)];
+        for (from, to) in links {
+            sites[keys[from]].connections.push(keys[to]);
+        }
+        sites[keys[6]].connections.push(keys[6]);
+        let ranker = PageRanker::from_registry(sites);
+
+        let related: Vec<_> = ranker
+            .related(keys[0], 5)
+            .into_iter()
+            .map(|result| result.key)
+            .collect();
+        // Cargo is a link away and Crates two, while Clippy only links in
+        assert_eq!(related, [keys[1], keys[2], keys[3]]);
+
+        assert_eq!(ranker.related(keys[0], 1).len(), 1);
+        assert_eq!(ranker.related(keys[0], 1)[0].score, 1f32);
+        assert!(ranker.related(keys[6], 5).is_empty());
+    }
+
+    #[test]
+    fn relevance_blend_promotes_on_topic_page() {
+        let mut sites = registry(&[
+            "Rust",
+            "Rust belt economic history and overview",
+            "Rust belt cities",
+            "Rust belt towns",
+        ]);
+        let keys: Vec<_> = sites.keys().collect();
+        sites[keys[2]].connections.push(keys[1]);
+        sites[keys[3]].connections.push(keys[1]);
+        let ranker = PageRanker::from_registry(sites);
+
+        let link_only = ranker
+            .search_ranked(
+                "rust",
+                &SearchOptions {
+                    alpha: 1.0,
+                    ..Default::default()
+                },
+            )
+            .expect("Search");
+        assert_eq!(link_only[0].key, keys[1]);
+        assert_eq!(link_only[0].rank, 1.0);
+
+        let text_heavy = ranker
+            .search_ranked(
+                "rust",
+                &SearchOptions {
+                    alpha: 0.2,
+                    ..Default::default()
+                },
+            )
+            .expect("Search");
+        assert_eq!(text_heavy[0].key, keys[0]);
+        assert_eq!(text_heavy[0].relevance, 1.0);
+        assert!(text_heavy
+            .iter()
+            .all(|result| (0.0..=1.0).contains(&result.relevance)));
+    }
+
+    #[test]
+    fn last_page_is_partial() {
+        let ranker = PageRanker::from_registry(registry(&[
+            "Rust one",
+            "Rust two",
+            "Rust three",
+            "Rust four",
+            "Rust five",
+        ]));
+
+        let first = ranker.search_page("rust", 0, 2).expect("First page");
+        assert_eq!(first.results.len(), 2);
+        assert_eq!(first.total_matches, 5);
+        assert_eq!(first.total_pages, 3);
+
+        let last = ranker.search_page("rust", 2, 2).expect("Last page");
+        assert_eq!(last.results.len(), 1);
+        assert_eq!(last.page, 2);
+
+        let all = ranker
+            .search_ranked("rust", &SearchOptions::default())
+            .expect("Search");
+        assert_eq!(last.results[0], all[4]);
+    }
+
+    #[test]
+    fn out_of_range_page_is_empty() {
+        let ranker = PageRanker::from_registry(registry(&["Rust one", "Rust two"]));
+
+        let page = ranker.search_page("rust", 7, 2).expect("Valid page");
+        assert!(page.results.is_empty());
+        assert_eq!(page.total_matches, 2);
+        assert_eq!(page.total_pages, 1);
+    }
+
+    #[test]
+    fn zero_per_page_is_rejected() {
+        let ranker = PageRanker::from_registry(registry(&["Rust one", "Rust two"]));
+
+        assert!(ranker.search_page("rust", 0, 0).is_none());
+    }
+
+    #[test]
+    fn exact_title_outranks_better_linked_substring_match() {
+        let mut sites = registry(&["Rust", "History of rust", "Corrosion and rust"]);
+        let keys: Vec<_> = sites.keys().collect();
+        sites[keys[2]].connections.push(keys[1]);
+        let ranker = PageRanker::from_registry(sites);
+
+        let unboosted = ranker
+            .search_ranked(
+                "rust",
+                &SearchOptions {
+                    exact_title_boost: 1.0,
+                    ..Default::default()
+                },
+            )
+            .expect("Search");
+        assert_eq!(unboosted[0].key, keys[1]);
+        assert!(unboosted[0].rank > unboosted[1].rank);
+
+        let boosted = ranker
+            .search_ranked("rust", &SearchOptions::default())
+            .expect("Search");
+        assert_eq!(boosted[0].key, keys[0]);
+    }
+
+    #[test]
+    fn whole_word_titles_boosted() {
+        // "Trusty tools" is only found through its body, and its title contains rust mid word
+        let mut sites = registry(&["History of rust", "Trusty tools"]);
+        let keys: Vec<_> = sites.keys().collect();
+        sites[keys[1]].text = Some("Keeps rust away".to_string());
+        let ranker = PageRanker::from_registry(sites);
+
+        let scores = |title_word_boost| {
+            let options = SearchOptions {
+                title_word_boost,
+                field_weights: FieldWeights {
+                    body: 1f32,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let results = ranker.search_ranked("rust", &options).expect("Search");
+            keys.iter()
+                .map(|key| {
+                    results
+                        .iter()
+                        .find(|result| result.key == *key)
+                        .expect("Result")
+                        .score
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let unboosted = scores(1f32);
+        let boosted = scores(DEFAULT_TITLE_WORD_BOOST);
+        assert_eq!(boosted[0], unboosted[0] * DEFAULT_TITLE_WORD_BOOST);
+        assert_eq!(boosted[1], unboosted[1]);
+    }
+
+    #[test]
+    fn global_rank_matches_connection_graph() {
+        let mut sites = registry(&["Home", "About", "Blog", "Contact"]);
+        let keys: Vec<_> = sites.keys().collect();
+        let links = [(0, 1), (0, 2), (1, 0), (2, 0), (2, 1), (3, 0)];
+        for (from, to) in links {
+            sites[keys[from]].connections.push(keys[to]);
+        }
+
+        let mut graph: ConnectionGraph<()> = ConnectionGraph::default();
+        let nodes: Vec<_> = keys.iter().map(|_| graph.register()).collect();
+        for (from, key) in keys.iter().enumerate() {
+            let mut targets: Vec<_> = sites[*key].connections.clone();
+            targets.push(*key);
+            for target in &targets {
+                let to = keys.iter().position(|key| key == target).unwrap();
+                graph.connect(nodes[from], nodes[to], 1.0 / targets.len() as f32);
+            }
+        }
+        let expected: Vec<_> = graph
+            .get_rankings::<4>()
+            .expect("Rank graph")
+            .into_iter()
+            .map(|node| keys[nodes.iter().position(|n| *n == node).unwrap()])
+            .collect();
+
+        let ranker = PageRanker::from_registry(sites);
+        let ordered: Vec<_> = ranker
+            .top_sites(4)
+            .into_iter()
+            .map(|site| &site.url)
+            .collect();
+        let expected: Vec<_> = expected.iter().map(|key| &ranker.sites[*key].url).collect();
+
+        assert_eq!(ordered, expected);
+        assert_eq!(ranker.global_rank().len(), 4);
+        assert_eq!(ranker.top_sites(2).len(), 2);
+    }
+
+    #[test]
+    fn explanation_lists_subgraph_edges() {
+        let mut sites = registry(&["Rust Home", "Rust Blog", "Rust Book", "Python Home"]);
+        let keys: Vec<_> = sites.keys().collect();
+        let links = [(0, 1), (0, 3), (1, 0), (2, 0), (2, 2), (3, 0)];
+        for (from, to) in links {
+            sites[keys[from]].connections.push(keys[to]);
+        }
+        let ranker = PageRanker::from_registry(sites);
+
+        let home = ranker.explain("rust", keys[0]);
+        assert_eq!(home.inbound, [keys[1], keys[2]]);
+        assert_eq!(home.outbound, [(keys[1], 0.5), (keys[0], 0.5)]);
+        let ranks = ranker
+            .rank_subgraph(
+                &keys[..3],
+                &SearchOptions::default(),
+                &AtomicBool::new(false),
+                &mut SearchMetrics::default(),
+            )
+            .expect("Rank subgraph");
+        assert_eq!(home.rank, Some(ranks[&keys[0]]));
+
+        let book = ranker.explain("rust", keys[2]);
+        assert!(book.inbound.is_empty());
+        assert_eq!(book.outbound, [(keys[0], 0.5), (keys[2], 0.5)]);
+
+        let python = ranker.explain("rust", keys[3]);
+        assert_eq!(python.rank, None);
+        assert!(python.inbound.is_empty() && python.outbound.is_empty());
+    }
+
+    #[test]
+    fn saved_ranker_searches_identically() {
+        let mut sites = registry(&["Rust Home", "Rust Blog", "Rust Book", "Python Home"]);
+        let keys: Vec<_> = sites.keys().collect();
+        for (from, to) in [(0, 1), (1, 0), (2, 0), (3, 0)] {
+            sites[keys[from]].connections.push(keys[to]);
+        }
+        let ranker = PageRanker::from_registry(sites);
+        ranker.global_rank();
+
+        let file = tempfile::NamedTempFile::new().expect("Create ranker file");
+        ranker.save(file.path()).expect("Save ranker");
+        let loaded = PageRanker::load(file.path()).expect("Load ranker");
+
+        assert_eq!(loaded.index(), ranker.index());
+        assert_eq!(loaded.global_rank.get(), ranker.global_rank.get());
+        for term in ["rust", "home", "rust book", "java"] {
+            assert_eq!(
+                loaded.search_ranked(term, &SearchOptions::default()),
+                ranker.search_ranked(term, &SearchOptions::default())
+            );
+        }
+    }
+
+    #[test]
+    fn corrupted_ranker_file_is_rejected() {
+        let mut file = tempfile::NamedTempFile::new().expect("Create ranker file");
+        write!(
+            file,
+            "{{\"version\": {RANKER_FORMAT_VERSION}, \"sites\": [1, 2"
+        )
+        .expect("Write");
+
+        let err = PageRanker::load(file.path())
+            .err()
+            .expect("Reject corrupted file");
+        assert!(matches!(err, PersistError::Format(_)));
+        assert!(err.to_string().starts_with("saved ranker is malformed"));
+    }
+
+    #[test]
+    fn mismatched_ranker_version_is_rejected() {
+        let mut file = tempfile::NamedTempFile::new().expect("Create ranker file");
+        write!(file, "{{\"version\": 0, \"sites\": []}}").expect("Write");
+
+        let err = PageRanker::load(file.path())
+            .err()
+            .expect("Reject old version");
+        assert!(matches!(err, PersistError::Version { found: 0, .. }));
+    }
+
+    #[test]
+    fn short_titles_excluded_when_minimum_raised() {
+        let sites = registry(&["", "R", "R language"]);
+        let keys: Vec<_> = sites.keys().collect();
+        let mut ranker = PageRanker::from_registry(sites);
+        let options = SearchOptions::default();
+
+        let everything = ranker.reduce_registry_by_term(&query(""), &options);
+        assert_eq!(everything, keys);
+
+        ranker.set_min_title_len(1);
+        let titled = ranker.reduce_registry_by_term(&query(""), &options);
+        assert_eq!(titled, &keys[1..]);
+
+        ranker.set_min_title_len(2);
+        let results = ranker.search("r").expect("Search");
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].title, "R language");
+    }
+
+    /// A registry of `size` sites titled "Site {n}" that each link to the next few sites
+    fn linked_registry(size: usize) -> SlotMap<SiteKey, SiteLog> {
+        let titles: Vec<_> = (0..size).map(|n| format!("Site {n}")).collect();
+        let titles: Vec<_> = titles.iter().map(String::as_str).collect();
+        let mut sites = registry(&titles);
+        let keys: Vec<_> = sites.keys().collect();
+        for (idx, key) in keys.iter().enumerate() {
+            for offset in 1..=3 {
+                sites[*key]
+                    .connections
+                    .push(keys[(idx * 7 + offset) % size]);
+            }
+        }
+        sites
+    }
+
+    #[test]
+    fn popular_queries_keep_best_linked_match() {
+        let titles: Vec<_> = (0..300).map(|n| format!("Rust {n}")).collect();
+        let titles: Vec<_> = titles.iter().map(String::as_str).collect();
+        let mut sites = registry(&titles);
+        let keys: Vec<_> = sites.keys().collect();
+        let best = keys[299];
+        for key in &keys[..100] {
+            sites[*key].connections.push(best);
+        }
+        let ranker = PageRanker::from_registry(sites);
+
+        let results = ranker
+            .search_ranked("rust", &SearchOptions::default())
+            .expect("Search");
+
+        assert_eq!(results.len(), RESULTS_TO_SHOW);
+        assert_eq!(results[0].key, best);
+    }
+
+    #[test]
+    fn parallel_scoring_matches_serial() {
+        // A fixed linear congruential generator keeps the synthetic registry the same every run
+        let mut seed = 0x2545_f491_u64;
+        let mut next = move |bound: u64| {
+            seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+            (seed >> 33) % bound
+        };
+
+        let words = [
+            "rust", "iron", "oxide", "metal", "history", "language", "guide",
+        ];
+        let mut sites = SlotMap::with_key();
+        for n in 0..50_000 {
+            let title = (0..3)
+                .map(|_| words[next(words.len() as u64) as usize])
+                .join(" ");
+            sites.insert(SiteLog {
+                url: format!("https://example.com/{n}"),
+                text: Some(format!("{title} page number {n}")),
+                title,
+                ..Default::default()
+            });
+        }
+        let keys: Vec<_> = sites.keys().collect();
+        for key in &keys {
+            for _ in 0..3 {
+                let target = keys[next(keys.len() as u64) as usize];
+                sites[*key].connections.push(target);
+            }
+        }
+        let ranker = PageRanker::from_registry(sites);
+        // Rank globally up front so neither search pays for it
+        ranker.global_rank();
+
+        let search = |parallel_scoring| {
+            let options = SearchOptions {
+                parallel_scoring,
+                ..Default::default()
+            };
+            let start = Instant::now();
+            let results = ranker
+                .search_ranked("rust guide", &options)
+                .expect("Search");
+            (results, start.elapsed())
+        };
+
+        let (serial, serial_time) = search(false);
+        let (parallel, parallel_time) = search(true);
+        println!("Scored serially in {serial_time:?} and in parallel in {parallel_time:?}");
+
+        assert!(!serial.is_empty());
+        assert_eq!(serial, parallel);
+    }
+
+    #[test]
+    fn batch_matches_individual_searches() {
+        let ranker = PageRanker::from_registry(linked_registry(200));
+        let terms = ["site", "site 1", "", "missing", "7"];
+
+        let batch = ranker.search_batch(&terms);
+
+        assert_eq!(batch.len(), terms.len());
+        for (term, results) in terms.iter().zip(batch) {
+            assert_eq!(results, ranker.search(term));
+        }
+    }
+
+    #[test]
+    fn spawned_search_matches_blocking_search() {
+        let ranker = Arc::new(PageRanker::from_registry(linked_registry(1_000)));
+        let options = SearchOptions::default();
+
+        let mut handle = ranker.search_spawn("site", options.clone());
+        let blocking = ranker.search_ranked("site", &options).expect("Search");
+
+        assert_eq!(handle.wait().results(), blocking);
+        assert!(handle.try_poll().is_some());
+    }
+
+    #[test]
+    fn spawned_search_cancels() {
+        let ranker = Arc::new(PageRanker::from_registry(linked_registry(100_000)));
+
+        let mut handle = ranker.search_spawn("site", SearchOptions::default());
+        handle.cancel();
+
+        assert_eq!(handle.try_poll(), Some(SearchOutcome::Cancelled));
+        assert_eq!(handle.wait(), SearchOutcome::Cancelled);
+        // The search itself stopped partway through ranking, rather than running to the end
+        assert_eq!(
+            handle.receiver.recv().expect("Search outcome"),
+            Err(SearchError::RankingFailed(RankingFailure::Aborted))
+        );
+
+        // Dropping a handle mid search must not disturb the ranker
+        drop(ranker.search_spawn("site", SearchOptions::default()));
+        assert!(!ranker.search("site").expect("Search").is_empty());
+    }
+
+    #[test]
+    fn cancelled_ranking_stops() {
+        let ranker = PageRanker::from_registry(linked_registry(100));
+        let cancel = AtomicBool::new(true);
+
+        assert_eq!(
+            ranker
+                .score_matches("site", &SearchOptions::default(), &cancel)
+                .err(),
+            Some(SearchError::RankingFailed(RankingFailure::Aborted))
+        );
+    }
+
+    #[test]
+    fn results_carry_snippets_of_site_text() {
+        let mut sites = registry(&["Rust Home", "Rust Blog"]);
+        let keys: Vec<_> = sites.keys().collect();
+        sites[keys[0]].text = Some("Welcome! Rust empowers everyone.".to_string());
+
+        let ranker = PageRanker::from_registry(sites);
+        let results = ranker
+            .search_ranked("rust", &SearchOptions::default())
+            .expect("Search");
+
+        let home = results.iter().find(|result| result.key == keys[0]).unwrap();
+        let snippet = home.snippet.as_ref().expect("Snippet");
+        assert_eq!(snippet.text, "Welcome! Rust empowers everyone.");
+        assert_eq!(&snippet.text[snippet.highlight[0].clone()], "Rust");
+
+        let blog = results.iter().find(|result| result.key == keys[1]).unwrap();
+        assert_eq!(blog.snippet, None);
+    }
+
+    #[test]
+    fn summaries_fall_back_from_description() {
+        let mut sites = registry(&["Rust Home", "Rust Blog", "Rust Forum", "Rust Wiki"]);
+        let keys: Vec<_> = sites.keys().collect();
+        sites[keys[0]].description = Some("Rust empowers everyone to build software".to_string());
+        sites[keys[1]].text = Some("Posts about Rust".to_string());
+        sites[keys[2]].text = Some("Ask questions and share projects".to_string());
+
+        let ranker = PageRanker::from_registry(sites);
+        let results = ranker
+            .search_ranked("rust", &SearchOptions::default())
+            .expect("Search");
+        let summary = |key| {
+            results
+                .iter()
+                .find(|result| result.key == key)
+                .and_then(|result| result.summary(20))
+        };
+
+        assert_eq!(summary(keys[0]).as_deref(), Some("Rust empowers…"));
+        assert_eq!(summary(keys[1]).as_deref(), Some("Posts about Rust"));
+        assert_eq!(summary(keys[2]).as_deref(), Some("Ask questions and…"));
+        assert_eq!(summary(keys[3]), None);
+    }
+
+    #[test]
+    fn fresher_crawl_wins_with_half_life() {
+        let mut sites = registry(&["Rust A", "Rust B", "Rust C"]);
+        let keys: Vec<_> = sites.keys().collect();
+        let now = unix_now();
+        sites[keys[0]].last_crawled = Some(now - 3600);
+        sites[keys[1]].last_crawled = Some(now);
+        let ranker = PageRanker::from_registry(sites);
+
+        let score = |results: &[SearchResult], key| {
+            results
+                .iter()
+                .find(|result| result.key == key)
+                .map(|result| result.score)
+                .expect("Result for site")
+        };
+
+        let timeless = ranker
+            .search_ranked("rust", &SearchOptions::default())
+            .expect("Search");
+        assert_eq!(score(&timeless, keys[0]), score(&timeless, keys[1]));
+
+        let options = SearchOptions {
+            recency_half_life: Some(Duration::from_secs(3600)),
+            ..Default::default()
+        };
+        let recent = ranker.search_ranked("rust", &options).expect("Search");
+        assert_eq!(recent[0].key, keys[1]);
+        assert!((score(&recent, keys[0]) / score(&recent, keys[1]) - 0.5).abs() < 1e-3);
+
+        // Never crawled sites sink to the bottom rather than disappearing
+        assert_eq!(recent[2].key, keys[2]);
+        assert_eq!(recent[2].score, 0f32);
+    }
+
+    #[test]
+    fn normalized_scores_relative_to_best() {
+        // Sites crawled a half-life ago keep their order but score at most a half
+        let mut sites = linked_registry(20);
+        for site in sites.values_mut() {
+            site.last_crawled = Some(unix_now() - 3600);
+        }
+        let ranker = PageRanker::from_registry(sites);
+        let options = SearchOptions {
+            recency_half_life: Some(Duration::from_secs(3600)),
+            ..Default::default()
+        };
+
+        let raw = ranker.search_ranked("site", &options).expect("Search");
+        let best = raw[0].score;
+        let normalized = ranker
+            .search_ranked(
+                "site",
+                &SearchOptions {
+                    normalize_scores: true,
+                    ..options
+                },
+            )
+            .expect("Normalized search");
+
+        assert_eq!(normalized[0].score, 1f32);
+        assert!(best < 1f32);
+        for (raw, normalized) in raw.iter().zip(&normalized) {
+            assert_eq!(raw.key, normalized.key);
+            assert!((normalized.score - raw.score / best).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn title_weight_promotes_title_match() {
+        let mut sites = registry(&["Rust", "Other"]);
+        let keys: Vec<_> = sites.keys().collect();
+        sites[keys[1]].text = Some("Rust".to_string());
+        let ranker = PageRanker::from_registry(sites);
+
+        let mut options = SearchOptions {
+            exact_title_boost: 1.0,
+            ..Default::default()
+        };
+        let title_only = ranker.search_ranked("rust", &options).expect("Search");
+        assert_eq!(title_only.len(), 1);
+
+        options.field_weights.body = 1.0;
+        let tied = ranker.search_ranked("rust", &options).expect("Search");
+        assert_eq!(tied.len(), 2);
+        assert_eq!(tied[0].score, tied[1].score);
+
+        options.field_weights.title = 3.0;
+        let boosted = ranker.search_ranked("rust", &options).expect("Search");
+        assert_eq!(boosted[0].key, keys[0]);
+        assert!(boosted[0].score > boosted[1].score);
+    }
+
+    #[test]
+    fn backlinks_exclude_self_links() {
+        let mut sites = registry(&["Home", "About", "Blog"]);
+        let keys: Vec<_> = sites.keys().collect();
+        for (from, to) in [(0, 0), (0, 1), (1, 0), (2, 0), (2, 0), (2, 1), (2, 2)] {
+            sites[keys[from]].connections.push(keys[to]);
+        }
+        let ranker = PageRanker::from_registry(sites);
+
+        assert_eq!(ranker.backlinks(keys[0]), [keys[1], keys[2]]);
+        assert_eq!(ranker.backlink_count(keys[1]), 2);
+        assert_eq!(ranker.backlink_count(keys[2]), 0);
+
+        let results = ranker.search_ranked("home", &SearchOptions::default());
+        assert_eq!(results.expect("Search")[0].backlinks, 2);
+    }
+
+    #[test]
+    fn backlinks_follow_added_and_removed_sites() {
+        let mut sites = registry(&["Home", "About"]);
+        let keys: Vec<_> = sites.keys().collect();
+        sites[keys[1]].connections.push(keys[0]);
+        let mut ranker = PageRanker::from_registry(sites);
+        ranker.global_rank();
+
+        let blog = ranker.add_site(SiteLog {
+            url: "https://example.com/Blog".to_string(),
+            title: "Blog".to_string(),
+            connections: vec![keys[0], keys[1]],
+            ..Default::default()
+        });
+        assert_eq!(ranker.backlinks(keys[0]), [keys[1], blog]);
+        assert_eq!(ranker.backlinks(keys[1]), [blog]);
+        assert_eq!(ranker.global_rank().len(), 3);
+        assert_eq!(ranker.search("blog").expect("Search").len(), 1);
+
+        ranker.remove_site(keys[1]).expect("Remove site");
+        assert_eq!(ranker.backlinks(keys[0]), [blog]);
+        assert_eq!(ranker.sites[blog].connections, [keys[0]]);
+        assert!(ranker.search("about").expect("Search").is_empty());
+        assert_eq!(ranker.global_rank().len(), 2);
+        assert!(ranker.remove_site(keys[1]).is_none());
+    }
+
+    #[test]
+    fn blank_queries_give_global_top() {
+        let mut sites = registry(&["Home", "About", "Blog", "Contact"]);
+        let keys: Vec<_> = sites.keys().collect();
+        for (from, to) in [(0, 1), (0, 2), (1, 0), (2, 0), (2, 1), (3, 0)] {
+            sites[keys[from]].connections.push(keys[to]);
+        }
+        let ranker = PageRanker::from_registry(sites);
+        let expected: Vec<_> = ranker
+            .top_sites(4)
+            .into_iter()
+            .map(|site| &site.url)
+            .collect();
+
+        for term in ["", "   ", "???"] {
+            let SearchOutcome::GlobalTop { results, .. } =
+                ranker.search_outcome(term, &SearchOptions::default())
+            else {
+                panic!("{term:?} should give the global top sites");
+            };
+            let urls: Vec<_> = results.iter().map(|result| &result.site.url).collect();
+            assert_eq!(urls, expected);
+            assert_eq!(results[0].rank, 1.0);
+
+            let sites: Vec<_> = ranker
+                .search(term)
+                .expect("Search")
+                .into_iter()
+                .map(|site| &site.url)
+                .collect();
+            assert_eq!(sites, expected);
+        }
+
+        assert!(matches!(
+            ranker.search_outcome("home", &SearchOptions::default()),
+            SearchOutcome::Ranked { .. }
+        ));
+        // Empty parentheses are malformed rather than blank
+        assert!(matches!(
+            ranker.search_outcome("( )", &SearchOptions::default()),
+            SearchOutcome::Failed(SearchError::InvalidQuery { .. })
+        ));
+    }
+
+    #[test]
+    fn invalid_searches_give_none() {
+        let ranker = PageRanker::from_registry(registry(&["Rust", "Python"]));
+
+        assert_eq!(ranker.search("( )"), None);
+        assert_eq!(ranker.search_ranked("( )", &SearchOptions::default()), None);
+        let unusable = SearchOptions {
+            alpha: 2.0,
+            ..Default::default()
+        };
+        assert_eq!(ranker.search_ranked("rust", &unusable), None);
+        // Unlike a term matching nothing
+        assert_eq!(ranker.search("missing"), Some(vec![]));
+        assert!(matches!(
+            ranker.try_search("( )", &SearchOptions::default()),
+            Err(SearchError::InvalidQuery { .. })
+        ));
+    }
+
+    #[test]
+    fn title_highlights_map_to_original_title() {
+        let ranker = PageRanker::from_registry(registry(&["Ça va? ZÜRICH Straße Zurich"]));
+        let results = ranker
+            .search_ranked("zurich", &SearchOptions::default())
+            .expect("Search");
+
+        let title = &results[0].site.title;
+        let highlighted: Vec<_> = results[0]
+            .title_highlights
+            .iter()
+            .map(|range| &title[range.clone()])
+            .collect();
+        assert_eq!(highlighted, ["ZÜRICH", "Zurich"]);
+    }
+
+    #[test]
+    fn unicode_titles_match_case_insensitively() {
+        let ranker = PageRanker::from_registry(registry(&[
+            "Café de Flore",
+            "CAFÉ racer",
+            "Cafeteria",
+            "東京 タワー",
+            "東京",
+            "京都",
+        ]));
+        let titles = |term| {
+            ranker
+                .search_ranked(term, &SearchOptions::default())
+                .expect("Search")
+                .iter()
+                .map(|result| {
+                    let highlighted: Vec<_> = result
+                        .title_highlights
+                        .iter()
+                        .map(|range| &result.site.title[range.clone()])
+                        .collect();
+                    (result.site.title.as_str(), highlighted)
+                })
+                .sorted()
+                .collect::<Vec<_>>()
+        };
+
+        let cafes = [
+            ("CAFÉ racer", vec!["CAFÉ"]),
+            ("Café de Flore", vec!["Café"]),
+        ];
+        assert_eq!(titles("café"), cafes);
+        assert_eq!(titles("CAFE"), cafes);
+        assert_eq!(
+            titles("東京"),
+            [("東京", vec!["東京"]), ("東京 タワー", vec!["東京"])]
+        );
+        assert_eq!(titles("京都"), [("京都", vec!["京都"])]);
+    }
+
+    #[test]
+    fn overlapping_title_highlights_merged() {
+        let ranker = PageRanker::from_registry(registry(&["Éclair pâtisserie"]));
+        let results = ranker
+            .search_ranked("patisserie pat serie", &SearchOptions::default())
+            .expect("Search");
+
+        let title = &results[0].site.title;
+        let highlighted: Vec<_> = results[0]
+            .title_highlights
+            .iter()
+            .map(|range| &title[range.clone()])
+            .collect();
+        assert_eq!(highlighted, ["pâtisserie"]);
+    }
+
+    #[test]
+    fn search_metrics_populated() {
+        let ranker = PageRanker::from_registry(linked_registry(500));
+
+        let outcome = ranker.search_outcome("site", &SearchOptions::default());
+        let metrics = outcome.metrics().expect("Metrics");
+        assert_eq!(metrics.candidates, 250);
+        assert_eq!(outcome.results().len(), 250);
+        assert!(metrics.iterations > 0);
+        assert!(metrics.reduce_time > Duration::ZERO);
+        assert!(metrics.graph_build_time > Duration::ZERO);
+        assert!(metrics.rank_time > Duration::ZERO);
+
+        let none = ranker.search_outcome("nothing", &SearchOptions::default());
+        assert_eq!(none.metrics().expect("Metrics").candidates, 0);
+    }
+
+    #[test]
+    fn score_breakdown_recombines() {
+        let mut sites = registry(&["Rust", "Rust Blog", "Rust Book"]);
+        let keys: Vec<_> = sites.keys().collect();
+        for (from, to) in [(1, 0), (2, 0), (2, 1)] {
+            sites[keys[from]].connections.push(keys[to]);
+        }
+        let ranker = PageRanker::from_registry(sites);
+        let results = ranker
+            .search_ranked("rust", &SearchOptions::default())
+            .expect("Search");
+
+        for result in &results {
+            let breakdown = ranker
+                .explain_score("rust", &result.site.url)
+                .expect("Explain");
+            assert_eq!(breakdown.score, result.score);
+            assert!((breakdown.combined() - result.score).abs() < 1e-6);
+            assert_eq!(breakdown.backlinks, result.backlinks);
+        }
+        assert_eq!(
+            ranker
+                .explain_score("rust", &results[0].site.url)
+                .map(|b| b.boost),
+            Some(2.0)
+        );
+        assert_eq!(ranker.explain_score("python", &results[0].site.url), None);
+    }
+
+    #[test]
+    fn tied_scores_order_by_chosen_tie_break() {
+        let ranker = PageRanker::from_registry(registry(&[
+            "rust crate docs",
+            "Rust",
+            "Rust book",
+            "about rust",
+        ]));
+        let options = SearchOptions {
+            alpha: 1f32,
+            exact_title_boost: 1f32,
+            title_prefix_boost: 1f32,
+            title_word_boost: 1f32,
+            ..Default::default()
+        };
+        let titles = |tie_break| {
+            let results = ranker
+                .search_ranked(
+                    "rust",
+                    &SearchOptions {
+                        tie_break,
+                        ..options.clone()
+                    },
+                )
+                .expect("Search");
+            assert!(results
+                .iter()
+                .all(|result| result.score == results[0].score));
+            results
+                .iter()
+                .map(|result| result.site.title.as_str())
+                .collect::<Vec<_>>()
+        };
+
+        // "about rust" and "Rust book" are equally relevant, so fall back to their titles
+        assert_eq!(
+            titles(TieBreak::Relevance),
+            ["Rust", "about rust", "Rust book", "rust crate docs"]
+        );
+        assert_eq!(titles(TieBreak::default()), titles(TieBreak::Relevance));
+        assert_eq!(
+            titles(TieBreak::Title),
+            ["about rust", "Rust", "Rust book", "rust crate docs"]
+        );
+    }
+
+    #[test]
+    fn tied_scores_order_by_backlinks_then_url() {
+        let mut sites = registry(&["Rust b", "Rust x", "Rust a", "Rust z", "Home", "About"]);
+        let keys: Vec<_> = sites.keys().collect();
+        for (from, to) in [(4, 3), (4, 1), (5, 3)] {
+            sites[keys[from]].connections.push(keys[to]);
+        }
+        let ranker = PageRanker::from_registry(sites);
+
+        let options = SearchOptions {
+            tie_break: TieBreak::Backlinks,
+            ..Default::default()
+        };
+        let results = ranker.search_ranked("rust", &options).expect("Search");
+        assert!(results
+            .iter()
+            .all(|result| result.score == results[0].score));
+
+        let titles: Vec<_> = results
+            .iter()
+            .map(|result| result.site.title.as_str())
+            .collect();
+        assert_eq!(titles, ["Rust z", "Rust x", "Rust a", "Rust b"]);
+    }
+
+    #[test]
+    fn reciprocity_ignores_self_links() {
+        let mut sites = registry(&["Home", "About", "Blog"]);
+        let keys: Vec<_> = sites.keys().collect();
+        for (from, to) in [
+            (0, 0),
+            (0, 1),
+            (1, 0),
+            (1, 1),
+            (1, 2),
+            (2, 0),
+            (2, 2),
+            (0, 1),
+        ] {
+            sites[keys[from]].connections.push(keys[to]);
+        }
+        let ranker = PageRanker::from_registry(sites);
+
+        // Of Home -> About, About -> Home, About -> Blog and Blog -> Home, only the first two are
+        // reciprocated
+        assert_eq!(ranker.link_reciprocity(), 0.5);
+        assert_eq!(
+            PageRanker::from_registry(registry(&["Home"])).link_reciprocity(),
+            0.0
+        );
+    }
+
+    /// Four rust sites where "Rust" is linked from every other, so it clearly scores highest
+    fn cutoff_ranker() -> PageRanker {
+        let mut sites = registry(&["Rust", "Rust Blog", "Rust Book", "Rust Forum"]);
+        let keys: Vec<_> = sites.keys().collect();
+        for from in 1..4 {
+            sites[keys[from]].connections.push(keys[0]);
+        }
+        PageRanker::from_registry(sites)
+    }
+
+    #[test]
+    fn low_scores_cut_off() {
+        let ranker = cutoff_ranker();
+        let everything = ranker
+            .search_outcome("rust", &SearchOptions::default())
+            .results()
+            .len();
+
+        let options = SearchOptions {
+            min_score: Some(0.5),
+            ..Default::default()
+        };
+        let outcome = ranker.search_outcome("rust", &options);
+        let best = outcome.results()[0].score;
+        assert!(outcome.results().len() < everything);
+        assert!(outcome
+            .results()
+            .iter()
+            .all(|result| result.score / best >= 0.5));
+        assert!(outcome.metrics().expect("Metrics").truncated);
+
+        let options = SearchOptions {
+            max_results: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            ranker
+                .search_ranked("rust", &options)
+                .expect("Search")
+                .len(),
+            2
+        );
+        let none = SearchOptions {
+            max_results: Some(0),
+            ..Default::default()
+        };
+        assert!(matches!(
+            ranker.search_outcome("rust", &none),
+            SearchOutcome::Failed(SearchError::InvalidQuery { .. })
+        ));
+
+        let untouched = ranker.search_outcome("rust", &SearchOptions::default());
+        assert!(!untouched.metrics().expect("Metrics").truncated);
+    }
+
+    #[test]
+    fn cutoff_keeps_best_result() {
+        let ranker = cutoff_ranker();
+        let options = SearchOptions {
+            min_score: Some(2.0),
+            ..Default::default()
+        };
+
+        let results = ranker.search_ranked("rust", &options).expect("Search");
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].site.title, "Rust");
+    }
+
+    #[test]
+    fn cutoff_applies_before_paging() {
+        let ranker = cutoff_ranker();
+        let options = SearchOptions {
+            max_results: Some(3),
+            ..Default::default()
+        };
+
+        let first = ranker
+            .search_page_with("rust", &options, 0, 2)
+            .expect("Search");
+        assert_eq!(first.total_matches, 3);
+        assert_eq!(first.total_pages, 2);
+        assert!(first.truncated);
+
+        let last = ranker
+            .search_page_with("rust", &options, 1, 2)
+            .expect("Search");
+        assert_eq!(last.results.len(), 1);
+
+        // Different options aren't served from the cache
+        let unlimited = ranker.search_page("rust", 0, 2).expect("Search");
+        assert_eq!(unlimited.total_matches, 4);
+        assert!(!unlimited.truncated);
+    }
+
+    #[test]
+    fn self_loops_can_be_disabled() {
+        // Both sites link to B, which only links to itself
+        let mut sites = registry(&["Rust A", "Rust B", "Rust C"]);
+        let keys: Vec<_> = sites.keys().collect();
+        for (from, to) in [(0, 1), (1, 1), (2, 1)] {
+            sites[keys[from]].connections.push(keys[to]);
+        }
+        let ranker = PageRanker::from_registry(sites);
+
+        let rank_of = |self_loops, key| {
+            let options = SearchOptions {
+                alpha: 1f32,
+                self_loops,
+                ..Default::default()
+            };
+            ranker
+                .search_ranked("rust", &options)
+                .expect("Search")
+                .iter()
+                .find(|result| result.key == key)
+                .expect("Result")
+                .rank
+        };
+
+        // With its self link B hoards rank, without it B has no links and spreads its rank evenly
+        assert_eq!(rank_of(true, keys[1]), 1f32);
+        assert_eq!(rank_of(false, keys[1]), 1f32);
+        assert!(rank_of(false, keys[0]) > rank_of(true, keys[0]) * 2f32);
+        assert_eq!(rank_of(false, keys[0]), rank_of(false, keys[2]));
+    }
+
+    #[test]
+    fn outdegree_capped_to_most_linked() {
+        // The hub links to four sites, the last two of which have other backlinks
+        let mut sites = registry(&["Rust hub", "Rust a", "Rust b", "Rust c", "Rust d", "Rust e"]);
+        let keys: Vec<_> = sites.keys().collect();
+        for to in 1..5 {
+            sites[keys[0]].connections.push(keys[to]);
+        }
+        sites[keys[5]].connections.extend([keys[3], keys[4]]);
+        sites[keys[1]].connections.push(keys[4]);
+        let ranker = PageRanker::from_registry(sites);
+        let options = SearchOptions {
+            max_outdegree: Some(2),
+            ..Default::default()
+        };
+
+        let hub = ranker.explain_with("rust", keys[0], &options);
+        assert_eq!(
+            hub.outbound,
+            [
+                (keys[4], 1f32 / 3f32),
+                (keys[3], 1f32 / 3f32),
+                (keys[0], 1f32 / 3f32)
+            ]
+        );
+
+        let uncapped = ranker.explain_with("rust", keys[0], &SearchOptions::default());
+        assert_eq!(uncapped.outbound.len(), 5);
+
+        // Dropped links no longer feed rank to their targets
+        let rank = |options: &SearchOptions, key| {
+            ranker
+                .rank_subgraph(
+                    &keys,
+                    options,
+                    &AtomicBool::new(false),
+                    &mut SearchMetrics::default(),
+                )
+                .expect("Rank subgraph")[&key]
+        };
+        assert!(rank(&options, keys[2]) < rank(&SearchOptions::default(), keys[2]));
+    }
+
+    fn hosted_registry() -> SlotMap<SiteKey, SiteLog> {
+        let mut sites = SlotMap::default();
+        for url in [
+            "https://en.wikipedia.org/wiki/Rust",
+            "https://www.rust-lang.org/",
+            "doc.rust-lang.org:443/book",
+            "not a url/rust",
+        ] {
+            sites.insert(SiteLog {
+                url: url.to_string(),
+                title: "Rust".to_string(),
+                ..Default::default()
+            });
+        }
+        sites
+    }
+
+    /// Two sites on one host and one on another, all linking to the busiest page
+    fn multi_page_registry() -> SlotMap<SiteKey, SiteLog> {
+        let mut sites = SlotMap::default();
+        let keys: Vec<_> = [
+            "https://docs.rs/rust",
+            "https://DOCS.rs:443/rust/std",
+            "http://user@blog.rust-lang.org/rust",
+        ]
+        .into_iter()
+        .map(|url| {
+            sites.insert(SiteLog {
+                url: url.to_string(),
+                title: "Rust".to_string(),
+                ..Default::default()
+            })
+        })
+        .collect();
+
+        for key in &keys {
+            sites[*key].connections = vec![*key, keys[0]];
+        }
+        sites
+    }
+
+    #[test]
+    fn domain_ranks_aggregate_pages() {
+        let ranker = PageRanker::from_registry(multi_page_registry());
+        let domains = ranker.domain_ranking();
+
+        let hosts: Vec<_> = domains
+            .iter()
+            .map(|(host, _, pages)| (host.as_str(), *pages))
+            .collect();
+        assert_eq!(hosts, [("docs.rs", 2), ("blog.rust-lang.org", 1)]);
+
+        let total: f32 = domains.iter().map(|(_, rank, _)| rank).sum();
+        let site_total: f32 = ranker.global_rank().values().sum();
+        assert!((total - site_total).abs() < 1e-5);
+    }
+
+    #[test]
+    fn collapsed_search_keeps_best_page_per_domain() {
+        let ranker = PageRanker::from_registry(multi_page_registry());
+        let options = SearchOptions {
+            collapse_domains: true,
+            ..Default::default()
+        };
+
+        let full = ranker.search("rust").expect("Search");
+        assert_eq!(full.len(), 3);
+
+        let collapsed = ranker
+            .search_ranked("rust", &options)
+            .expect("Collapsed search");
+        let urls: Vec<_> = collapsed
+            .iter()
+            .map(|result| result.site.url.as_str())
+            .collect();
+        assert_eq!(
+            urls,
+            [
+                "https://docs.rs/rust",
+                "http://user@blog.rust-lang.org/rust"
+            ]
+        );
+    }
+
+    fn urls<'a>(results: &[SearchResult<'a>]) -> Vec<&'a str> {
+        let mut urls: Vec<_> = results
+            .iter()
+            .map(|result| result.site.url.as_str())
+            .collect();
+        urls.sort();
+        urls
+    }
+
+    #[test]
+    fn include_domains_filter() {
+        let ranker = PageRanker::from_registry(hosted_registry());
+        let options = SearchOptions {
+            include_domains: vec!["rust-lang.org".to_string()],
+            ..Default::default()
+        };
+
+        let results = ranker.search_ranked("rust", &options).expect("Search");
+        assert_eq!(
+            urls(&results),
+            ["doc.rust-lang.org:443/book", "https://www.rust-lang.org/"]
+        );
+    }
+
+    #[test]
+    fn exclude_domains_filter() {
+        let ranker = PageRanker::from_registry(hosted_registry());
+        let options = SearchOptions {
+            exclude_domains: vec!["rust-lang.org".to_string()],
+            ..Default::default()
+        };
+
+        let results = ranker.search_ranked("rust", &options).expect("Search");
+        assert_eq!(
+            urls(&results),
+            ["https://en.wikipedia.org/wiki/Rust", "not a url/rust"]
+        );
+    }
+
+    #[test]
+    fn site_token_restricts_domain() {
+        let ranker = PageRanker::from_registry(hosted_registry());
+
+        let results = ranker
+            .search_ranked("site:en.wikipedia.org rust", &SearchOptions::default())
+            .expect("Search");
+        assert_eq!(urls(&results), ["https://en.wikipedia.org/wiki/Rust"]);
+    }
+
+    #[test]
+    fn malformed_urls_never_match_domains() {
+        let ranker = PageRanker::from_registry(hosted_registry());
+        let options = SearchOptions {
+            include_domains: vec!["url".to_string()],
+            ..Default::default()
+        };
+
+        assert!(ranker
+            .search_ranked("rust", &options)
+            .expect("Search")
+            .is_empty());
+    }
+
+    #[test]
+    fn no_matches_is_empty() {
+        let ranker = PageRanker::from_registry(registry(&["Rust", "Python"]));
+
+        assert_eq!(ranker.search("haskell"), Some(vec![]));
+        let page = ranker.search_page("haskell", 0, 10).expect("Valid page");
+        assert_eq!(page.total_matches, 0);
+        assert_eq!(page.total_pages, 0);
+    }
+
+    #[test]
+    fn solve_failure_is_none() {
+        let ranker = PageRanker::from_registry(registry(&["Rust", "Rust belt"]));
+        let options = SearchOptions {
+            max_iterations: 0,
+            ..Default::default()
+        };
+
+        assert!(ranker.search_ranked("rust", &options).is_none());
+    }
+
+    #[test]
+    fn search_errors_say_why() {
+        let ranker = PageRanker::from_registry(registry(&["Rust", "Rust belt"]));
+
+        assert_eq!(
+            ranker.try_search("java", &SearchOptions::default()),
+            Err(SearchError::NoMatches)
+        );
+        assert_eq!(ranker.search("java"), Some(vec![]));
+
+        let unconverged = SearchOptions {
+            max_iterations: 0,
+            ..Default::default()
+        };
+        assert_eq!(
+            ranker.try_search("rust", &unconverged),
+            Err(SearchError::RankingFailed(RankingFailure::NotConverged {
+                iterations: 0
+            }))
+        );
+
+        let invalid = SearchOptions {
+            alpha: 2.0,
+            ..Default::default()
+        };
+        assert!(matches!(
+            ranker.try_search("rust", &invalid),
+            Err(SearchError::InvalidQuery { position: None, .. })
+        ));
+        assert!(ranker.search_ranked("rust", &invalid).is_none());
+
+        let found = ranker
+            .try_search("rust", &SearchOptions::default())
+            .expect("Search");
+        assert_eq!(found.results().len(), 2);
+    }
+
+    #[test]
+    fn leaky_link_graph_rejected() {
+        // A ranker builds its link graphs so every site's links sum to 1, so the leak is added by
+        // hand to a graph over a registry's sites
+        let sites = registry(&["Rust", "Rust belt"]);
+        let mut graph = ConnectionGraph::default();
+        let nodes: Vec<_> = sites
+            .keys()
+            .map(|key| {
+                let node = graph.register();
+                graph.set_val(node, key);
+                node
+            })
+            .collect();
+        graph.connect(nodes[0], nodes[1], 0.5);
+        graph.connect(nodes[1], nodes[0], 1.0);
+
+        let mut metrics = SearchMetrics::default();
+        let failure = solve(
+            &graph,
+            &SearchOptions::default(),
+            &AtomicBool::new(false),
+            &mut metrics,
+        )
+        .expect_err("Leaky graph ranked");
+        assert_eq!(failure, RankingFailure::InvalidGraph);
+        assert_eq!(metrics.iterations, 0);
+        assert_eq!(
+            SearchError::RankingFailed(failure).to_string(),
+            "the link graph between matches is invalid"
+        );
+    }
+
+    #[test]
+    fn solve_failure_falls_back_to_backlinks() {
+        let mut sites = registry(&["Rust", "Rust belt", "Rust book", "Home"]);
+        let keys: Vec<_> = sites.keys().collect();
+        for (from, to) in [(0, 2), (1, 2), (3, 2), (3, 1)] {
+            sites[keys[from]].connections.push(keys[to]);
+        }
+        let ranker = PageRanker::from_registry(sites);
+        let options = SearchOptions {
+            alpha: 1.0,
+            exact_title_boost: 1.0,
+            title_prefix_boost: 1.0,
+            max_iterations: 0,
+            fallback_ranking: true,
+            ..Default::default()
+        };
+
+        let results = ranker.search_ranked("rust", &options).expect("Fallback");
+        let ordered: Vec<_> = results.iter().map(|result| result.key).collect();
+        assert_eq!(ordered, [keys[2], keys[1], keys[0]]);
+        assert_eq!(results[0].rank, 1.0);
+    }
+
+    #[test]
+    fn quoted_phrase_must_be_contiguous() {
+        let sites = registry(&[
+            "The Rust Programming Language",
+            "Rust: a language for programming",
+            "Programming language theory",
+        ]);
+        let keys: Vec<_> = sites.keys().collect();
+        let ranker = PageRanker::from_registry(sites);
+
+        let phrase = ranker
+            .search_ranked(r#""rust programming language""#, &SearchOptions::default())
+            .expect("Search");
+        assert_eq!(phrase.len(), 1);
+        assert_eq!(phrase[0].key, keys[0]);
+
+        let mixed = ranker
+            .search_ranked(
+                r#"theory "programming language""#,
+                &SearchOptions::default(),
+            )
+            .expect("Search");
+        assert_eq!(mixed.len(), 1);
+        assert_eq!(mixed[0].key, keys[2]);
+    }
+
+    #[test]
+    fn unbalanced_quote_rejected() {
+        let ranker = PageRanker::from_registry(registry(&[
+            "The Rust Programming Language",
+            "Rust: a language for programming",
+        ]));
+
+        assert_eq!(
+            ranker.try_search(r#"rust "programming language"#, &SearchOptions::default()),
+            Err(SearchError::InvalidQuery {
+                position: Some(5),
+                message: "unclosed '\"'".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn boolean_queries_narrow_matches() {
+        let sites = registry(&[
+            "Rust belt",
+            "Rust language",
+            "Iron oxide",
+            "Python language",
+        ]);
+        let keys: Vec<_> = sites.keys().collect();
+        let ranker = PageRanker::from_registry(sites);
+        let matched = |term| {
+            let mut keys: Vec<_> = ranker
+                .search_ranked(term, &SearchOptions::default())
+                .expect("Search")
+                .iter()
+                .map(|result| result.key)
+                .collect();
+            keys.sort();
+            keys
+        };
+
+        assert_eq!(matched("rust OR oxide"), [keys[0], keys[1], keys[2]]);
+        assert_eq!(matched("rust -belt"), [keys[1]]);
+        assert_eq!(matched("language NOT (rust OR iron)"), [keys[3]]);
+        assert_eq!(matched("NOT language"), [keys[0], keys[2]]);
+    }
+
+    #[test]
+    fn accented_titles_match_plain_queries() {
+        let sites = registry(&["Zürich", "Café de Flore", "Zurich Insurance"]);
+        let keys: Vec<_> = sites.keys().collect();
+        let ranker = PageRanker::from_registry(sites);
+        let options = SearchOptions::default();
+
+        let zurich = ranker.reduce_registry_by_term(&query("zurich"), &options);
+        assert_eq!(zurich, &[keys[0], keys[2]]);
+
+        let shouted = ranker.reduce_registry_by_term(&query("ZÜRICH"), &options);
+        assert_eq!(shouted, zurich);
+
+        let partial = ranker.reduce_registry_by_term(&query("CAF"), &options);
+        assert_eq!(partial, &[keys[1]]);
     }
 }
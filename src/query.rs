@@ -0,0 +1,483 @@
+//! Parsing of search terms into boolean queries combining words, quoted phrases and `site:`
+//! filters with `AND`, `OR`, `NOT` and parentheses
+
+use std::{fmt, iter::Peekable, str::CharIndices};
+
+use crate::{crawler::host_of, index::tokenize};
+
+/// A boolean search query. Words side by side are implicitly joined with `AND`, and `NOT` binds
+/// tighter than `AND`, which binds tighter than `OR`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Query {
+    /// A folded token that must appear in the text
+    Term(String),
+    /// Folded tokens that must appear back to back, from quotes or words like "rust-lang"
+    Phrase(Vec<String>),
+    /// Every part must match. An empty list matches anything
+    And(Vec<Query>),
+    /// Any part must match
+    Or(Vec<Query>),
+    Not(Box<Query>),
+    /// A `field:value` filter. Only `site:domain` is understood
+    Field(String, String),
+}
+
+/// Why a query couldn't be parsed, and the byte offset in the term where it went wrong
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueryError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+/// A lexical piece of a query
+#[derive(Clone, Debug, PartialEq)]
+enum Lexeme {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Query(Query),
+}
+
+impl Query {
+    /// Parses a search term. Words that are nothing but punctuation are ignored, while unbalanced
+    /// parentheses or quotes and operators missing an operand are errors
+    pub fn parse(term: &str) -> Result<Self, QueryError> {
+        let mut parser = Parser {
+            lexemes: lex(term)?.into_iter().peekable(),
+            end: term.len(),
+        };
+
+        if parser.lexemes.peek().is_none() {
+            return Ok(Self::And(vec![]));
+        }
+
+        let query = parser.or()?;
+        match parser.lexemes.next() {
+            Some((position, _)) => Err(QueryError {
+                position,
+                message: "unexpected ')'".to_string(),
+            }),
+            None => Ok(query),
+        }
+    }
+
+    /// Whether a site's title and URL match the query
+    pub fn eval(&self, title: &str, url: &str) -> bool {
+        self.eval_fields(&[title], url)
+    }
+
+    /// Whether a site matches the query, where words and phrases may appear in any of its fields
+    pub fn eval_fields(&self, fields: &[&str], url: &str) -> bool {
+        let tokenized: Vec<_> = fields.iter().map(|field| tokenize(field)).collect();
+        let folded: Vec<_> = tokenized.iter().map(|tokens| tokens.join(" ")).collect();
+
+        self.matches(&folded, &tokenized, host_of(url).as_deref())
+    }
+
+    fn matches(&self, folded: &[String], tokenized: &[Vec<String>], host: Option<&str>) -> bool {
+        match self {
+            Self::Term(token) => folded.iter().any(|text| text.contains(token.as_str())),
+            Self::Phrase(phrase) => tokenized.iter().any(|tokens| {
+                tokens
+                    .windows(phrase.len())
+                    .any(|window| window == phrase.as_slice())
+            }),
+            Self::And(parts) => parts
+                .iter()
+                .all(|part| part.matches(folded, tokenized, host)),
+            Self::Or(parts) => parts
+                .iter()
+                .any(|part| part.matches(folded, tokenized, host)),
+            Self::Not(inner) => !inner.matches(folded, tokenized, host),
+            Self::Field(field, domain) if field == "site" => {
+                host.is_some_and(|host| on_domain(host, domain))
+            }
+            Self::Field(..) => false,
+        }
+    }
+
+    /// Whether the query has nothing to match on, such as an empty or all punctuation term. Site
+    /// filters don't count
+    pub fn is_blank(&self) -> bool {
+        match self {
+            Self::And(parts) => parts.iter().all(|part| matches!(part, Self::Field(..))),
+            Self::Field(..) => true,
+            _ => false,
+        }
+    }
+
+    /// Domains of the `site:` filters every match has to satisfy, those not nested under `OR` or
+    /// `NOT`
+    pub fn sites(&self) -> Vec<String> {
+        match self {
+            Self::Field(field, domain) if field == "site" => vec![domain.clone()],
+            Self::And(parts) => parts.iter().flat_map(Self::sites).collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Tokens of every word and phrase that isn't negated, which a matching site contains
+    pub fn positive_tokens(&self) -> Vec<String> {
+        let mut tokens = vec![];
+        self.collect_positive(&mut tokens);
+        tokens
+    }
+
+    fn collect_positive(&self, tokens: &mut Vec<String>) {
+        match self {
+            Self::Term(token) => tokens.push(token.clone()),
+            Self::Phrase(phrase) => tokens.extend(phrase.iter().cloned()),
+            Self::And(parts) | Self::Or(parts) => {
+                for part in parts {
+                    part.collect_positive(tokens);
+                }
+            }
+            Self::Not(_) | Self::Field(..) => {}
+        }
+    }
+}
+
+/// Whether a host is a domain or one of its subdomains
+pub fn on_domain(host: &str, domain: &str) -> bool {
+    let domain = domain.trim_start_matches('.').to_lowercase();
+    host == domain || host.ends_with(&format!(".{domain}"))
+}
+
+/// Recursive descent over the lexemes of a query, one method per precedence level
+struct Parser {
+    lexemes: Peekable<std::vec::IntoIter<(usize, Lexeme)>>,
+    /// Length of the term, where errors about a missing operand at the end are reported
+    end: usize,
+}
+
+impl Parser {
+    fn or(&mut self) -> Result<Query, QueryError> {
+        let mut parts = vec![self.and()?];
+        while self.next_if(&Lexeme::Or) {
+            parts.push(self.and()?);
+        }
+
+        Ok(flatten(parts, Query::Or))
+    }
+
+    fn and(&mut self) -> Result<Query, QueryError> {
+        let mut parts = vec![self.not()?];
+        loop {
+            // Operands side by side are joined with an implicit AND
+            let implicit = matches!(
+                self.lexemes.peek(),
+                Some((_, Lexeme::Open | Lexeme::Not | Lexeme::Query(_)))
+            );
+            if self.next_if(&Lexeme::And) || implicit {
+                parts.push(self.not()?);
+            } else {
+                break;
+            }
+        }
+
+        Ok(flatten(parts, Query::And))
+    }
+
+    fn not(&mut self) -> Result<Query, QueryError> {
+        if self.next_if(&Lexeme::Not) {
+            Ok(Query::Not(Box::new(self.not()?)))
+        } else {
+            self.atom()
+        }
+    }
+
+    fn atom(&mut self) -> Result<Query, QueryError> {
+        match self.lexemes.next() {
+            Some((_, Lexeme::Query(query))) => Ok(query),
+            Some((position, Lexeme::Open)) => {
+                let inner = self.or()?;
+                match self.lexemes.next() {
+                    Some((_, Lexeme::Close)) => Ok(inner),
+                    _ => Err(QueryError {
+                        position,
+                        message: "unclosed '('".to_string(),
+                    }),
+                }
+            }
+            Some((position, lexeme)) => Err(QueryError {
+                position,
+                message: match lexeme {
+                    Lexeme::Close => "expected a search term before ')'",
+                    Lexeme::And => "expected a search term before 'AND'",
+                    Lexeme::Or => "expected a search term before 'OR'",
+                    _ => "expected a search term",
+                }
+                .to_string(),
+            }),
+            None => Err(QueryError {
+                position: self.end,
+                message: "expected a search term at the end of the query".to_string(),
+            }),
+        }
+    }
+
+    /// Consumes the next lexeme if it's the one given
+    fn next_if(&mut self, expected: &Lexeme) -> bool {
+        self.lexemes
+            .next_if(|(_, lexeme)| lexeme == expected)
+            .is_some()
+    }
+}
+
+/// Joins the parts of an `AND` or `OR`, unwrapping a lone part
+fn flatten(mut parts: Vec<Query>, join: fn(Vec<Query>) -> Query) -> Query {
+    if parts.len() == 1 {
+        parts.remove(0)
+    } else {
+        join(parts)
+    }
+}
+
+/// Splits a term into parentheses, operators, and words, phrases and fields with their positions
+fn lex(term: &str) -> Result<Vec<(usize, Lexeme)>, QueryError> {
+    let mut lexemes = vec![];
+    let mut chars = term.char_indices().peekable();
+
+    while let Some(&(position, c)) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                lexemes.push((position, Lexeme::Open));
+            }
+            ')' => {
+                chars.next();
+                lexemes.push((position, Lexeme::Close));
+            }
+            '"' => {
+                chars.next();
+                let start = position + 1;
+                let end = loop {
+                    match chars.next() {
+                        Some((end, '"')) => break end,
+                        Some(_) => {}
+                        None => {
+                            return Err(QueryError {
+                                position,
+                                message: "unclosed '\"'".to_string(),
+                            })
+                        }
+                    }
+                };
+
+                let phrase = tokenize(&term[start..end]);
+                if phrase.is_empty() {
+                    return Err(QueryError {
+                        position,
+                        message: "empty phrase".to_string(),
+                    });
+                }
+                lexemes.push((position, Lexeme::Query(Query::Phrase(phrase))));
+            }
+            '-' => {
+                chars.next();
+                match chars.peek() {
+                    Some((_, next)) if !next.is_whitespace() => {
+                        lexemes.push((position, Lexeme::Not))
+                    }
+                    _ => {}
+                }
+            }
+            _ => {
+                let word = take_word(term, &mut chars);
+                if let Some(lexeme) = word_lexeme(word, position)? {
+                    lexemes.push((position, lexeme));
+                }
+            }
+        }
+    }
+
+    Ok(lexemes)
+}
+
+/// Consumes a word up to the next space, parenthesis or quote
+fn take_word<'a>(term: &'a str, chars: &mut Peekable<CharIndices<'a>>) -> &'a str {
+    let start = chars.peek().map_or(term.len(), |(idx, _)| *idx);
+    while chars
+        .next_if(|(_, c)| !c.is_whitespace() && !matches!(c, '(' | ')' | '"'))
+        .is_some()
+    {}
+    let end = chars.peek().map_or(term.len(), |(idx, _)| *idx);
+
+    &term[start..end]
+}
+
+/// Reads a word as an operator, a `field:value` filter or search tokens. Words with no tokens
+/// give nothing
+fn word_lexeme(word: &str, position: usize) -> Result<Option<Lexeme>, QueryError> {
+    let lexeme = match word {
+        "AND" => Lexeme::And,
+        "OR" => Lexeme::Or,
+        "NOT" => Lexeme::Not,
+        _ if word
+            .get(.."site:".len())
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case("site:")) =>
+        {
+            let domain = &word["site:".len()..];
+            if domain.is_empty() {
+                return Err(QueryError {
+                    position,
+                    message: "expected a domain after 'site:'".to_string(),
+                });
+            }
+            Lexeme::Query(Query::Field("site".to_string(), domain.to_string()))
+        }
+        _ => {
+            let mut tokens = tokenize(word);
+            match tokens.len() {
+                0 => return Ok(None),
+                1 => Lexeme::Query(Query::Term(tokens.remove(0))),
+                _ => Lexeme::Query(Query::Phrase(tokens)),
+            }
+        }
+    };
+
+    Ok(Some(lexeme))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Query;
+
+    #[test]
+    fn phrases_and_words_split() {
+        let query =
+            Query::parse(r#"guide "Rust programming" site:rust-lang.org book"#).expect("Parse");
+
+        assert_eq!(query.sites(), ["rust-lang.org"]);
+        assert_eq!(
+            query.positive_tokens(),
+            ["guide", "rust", "programming", "book"]
+        );
+    }
+
+    #[test]
+    fn site_prefix_checked_on_char_boundaries() {
+        let query = Query::parse("SITE:example.com sité:x 東京東京").expect("Parse");
+
+        assert_eq!(query.sites(), ["example.com"]);
+        assert!(!query.is_blank());
+    }
+
+    #[test]
+    fn blank_queries_spotted() {
+        for term in ["", "  ?! ", "???", "site:rust-lang.org"] {
+            assert!(Query::parse(term).expect(term).is_blank(), "{term}");
+        }
+        assert!(!Query::parse("rust?").expect("Parse").is_blank());
+        assert!(!Query::parse(r#""rust""#).expect("Parse").is_blank());
+        assert!(!Query::parse("site:rust-lang.org OR rust")
+            .expect("Parse")
+            .is_blank());
+        // Empty parentheses are an error rather than a blank query
+        assert!(Query::parse("( )").is_err());
+    }
+
+    #[test]
+    fn phrase_must_be_contiguous() {
+        let query = Query::parse(r#""rust programming language""#).expect("Parse");
+
+        assert!(query.eval("The Rust Programming Language", ""));
+        assert!(!query.eval("Rust: a language for systems programming", ""));
+    }
+
+    fn term(token: &str) -> Query {
+        Query::Term(token.to_string())
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and_than_or() {
+        let query = Query::parse("rust OR iron oxide NOT red").expect("Parse");
+
+        assert_eq!(
+            query,
+            Query::Or(vec![
+                term("rust"),
+                Query::And(vec![
+                    term("iron"),
+                    term("oxide"),
+                    Query::Not(Box::new(term("red")))
+                ])
+            ])
+        );
+        assert!(query.eval("Rust", ""));
+        assert!(query.eval("Iron oxide", ""));
+        assert!(!query.eval("Red iron oxide", ""));
+    }
+
+    #[test]
+    fn parentheses_group() {
+        let query = Query::parse("(rust OR iron) AND -oxide").expect("Parse");
+
+        assert_eq!(
+            query,
+            Query::And(vec![
+                Query::Or(vec![term("rust"), term("iron")]),
+                Query::Not(Box::new(term("oxide")))
+            ])
+        );
+        assert!(query.eval("Iron age", ""));
+        assert!(!query.eval("Iron oxide", ""));
+        assert!(!query.eval("Bronze age", ""));
+    }
+
+    #[test]
+    fn phrases_inside_boolean_expressions() {
+        let query = Query::parse(r#"("programming language" OR compiler) NOT site:python.org"#)
+            .expect("Parse");
+
+        assert!(query.eval("Rust programming language", "https://rust-lang.org"));
+        assert!(query.eval("Rust compiler", "https://rust-lang.org"));
+        assert!(!query.eval("Language for programming", "https://rust-lang.org"));
+        assert!(!query.eval("Python programming language", "https://docs.python.org"));
+        assert_eq!(
+            query.positive_tokens(),
+            ["programming", "language", "compiler"]
+        );
+    }
+
+    #[test]
+    fn malformed_queries_rejected() {
+        for (term, position) in [
+            ("(rust", 0),
+            ("rust)", 4),
+            ("rust AND", 8),
+            ("OR rust", 0),
+            ("rust OR (NOT)", 12),
+            (r#"rust "iron"#, 5),
+            (r#"rust """#, 5),
+            ("site: rust", 0),
+            ("()", 1),
+        ] {
+            let err = Query::parse(term).expect_err(term);
+            assert_eq!(err.position, position, "{term}: {err}");
+        }
+    }
+
+    #[test]
+    fn punctuation_words_ignored() {
+        assert_eq!(Query::parse("").expect("Parse"), Query::And(vec![]));
+        assert_eq!(Query::parse("rust ???").expect("Parse"), term("rust"));
+        assert_eq!(
+            Query::parse("rust-lang").expect("Parse"),
+            Query::Phrase(vec!["rust".to_string(), "lang".to_string()])
+        );
+    }
+}
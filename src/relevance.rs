@@ -0,0 +1,171 @@
+//! Text relevance scoring of site titles against a search term, a lightweight TF-IDF
+
+use crate::{crawler::SiteLog, index::tokenize};
+
+/// Multiplier applied to a title's relevance when it contains the query tokens as a contiguous
+/// phrase
+pub const EXACT_PHRASE_BONUS: f32 = 1.5;
+
+/// How much each field of a site counts towards its text relevance. Fields weighted 0 are ignored
+/// entirely, so by default only titles are searched
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FieldWeights {
+    pub title: f32,
+    pub url: f32,
+    /// Weight of the site's crawled text, when it has any
+    pub body: f32,
+}
+
+impl Default for FieldWeights {
+    fn default() -> Self {
+        Self {
+            title: 1f32,
+            url: 0f32,
+            body: 0f32,
+        }
+    }
+}
+
+impl FieldWeights {
+    /// The text of every field of a site with a positive weight, alongside that weight
+    pub fn fields<'a>(&self, site: &'a SiteLog) -> Vec<(&'a str, f32)> {
+        [
+            (Some(site.title.as_str()), self.title),
+            (Some(site.url.as_str()), self.url),
+            (site.text.as_deref(), self.body),
+        ]
+        .into_iter()
+        .filter_map(|(text, weight)| text.filter(|_| weight > 0f32).map(|text| (text, weight)))
+        .collect()
+    }
+
+    /// Whether any field besides the title is searched
+    pub fn beyond_title(&self) -> bool {
+        self.url > 0f32 || self.body > 0f32
+    }
+
+    /// Sum of each weighted field's relevance to the query tokens
+    pub fn relevance(&self, site: &SiteLog, query: &[String], idf: &[f32]) -> f32 {
+        self.fields(site)
+            .into_iter()
+            .map(|(text, weight)| title_relevance(text, query, idf) * weight)
+            .sum()
+    }
+}
+
+/// How closely a title matches a query as a whole
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TitleMatch {
+    /// The title is exactly the query
+    Exact,
+    /// The title starts with the query
+    Prefix,
+    /// The query appears elsewhere in the title as complete words
+    Word,
+    /// The query appears only inside longer words or scattered through the title, if at all
+    Partial,
+}
+
+/// Classifies how a title matches the query tokens, ignoring case and punctuation
+pub fn title_match(title: &str, query: &[String]) -> TitleMatch {
+    let title_tokens = tokenize(title);
+
+    if query.is_empty() {
+        TitleMatch::Partial
+    } else if title_tokens == query {
+        TitleMatch::Exact
+    } else if title_tokens.starts_with(query) {
+        TitleMatch::Prefix
+    } else if contains_phrase(&title_tokens, query) {
+        TitleMatch::Word
+    } else {
+        TitleMatch::Partial
+    }
+}
+
+/// Inverse document frequency of a token that appears in `containing` out of `total` documents
+pub fn inverse_document_frequency(total: usize, containing: usize) -> f32 {
+    (1f32 + total as f32 / (containing.max(1) as f32)).ln()
+}
+
+/// Scores how relevant a title is to a set of query tokens, weighting each token's frequency
+/// within the title by its inverse document frequency
+pub fn title_relevance(title: &str, query: &[String], idf: &[f32]) -> f32 {
+    let title_tokens = tokenize(title);
+    if title_tokens.is_empty() {
+        return 0f32;
+    }
+
+    let mut score = 0f32;
+    for (token, idf) in query.iter().zip(idf) {
+        let occurrences = title_tokens
+            .iter()
+            .filter(|title_token| title_token.contains(token.as_str()))
+            .count();
+
+        score += (occurrences as f32 / title_tokens.len() as f32) * idf;
+    }
+
+    if contains_phrase(&title_tokens, query) {
+        score *= EXACT_PHRASE_BONUS;
+    }
+
+    score
+}
+
+/// Whether the query tokens appear back to back within the title
+fn contains_phrase(title_tokens: &[String], query: &[String]) -> bool {
+    !query.is_empty()
+        && title_tokens
+            .windows(query.len())
+            .any(|window| window == query)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::index::tokenize;
+
+    use super::{inverse_document_frequency, title_match, title_relevance, TitleMatch};
+
+    #[test]
+    fn rarer_tokens_weigh_more() {
+        assert!(inverse_document_frequency(100, 1) > inverse_document_frequency(100, 50))
+    }
+
+    #[test]
+    fn focused_titles_are_more_relevant() {
+        let query = tokenize("rust");
+        let idf = [1f32];
+
+        assert!(
+            title_relevance("Rust", &query, &idf)
+                > title_relevance("Rust belt economic history", &query, &idf)
+        )
+    }
+
+    #[test]
+    fn exact_phrase_is_rewarded() {
+        let query = tokenize("programming language");
+        let idf = [1f32, 1f32];
+
+        assert!(
+            title_relevance("Rust programming language", &query, &idf)
+                > title_relevance("Rust language for programming", &query, &idf)
+        )
+    }
+
+    #[test]
+    fn title_matches_classified() {
+        let query = tokenize("rust");
+
+        assert_eq!(title_match("Rust", &query), TitleMatch::Exact);
+        assert_eq!(title_match("rust!", &query), TitleMatch::Exact);
+        assert_eq!(title_match("Rust belt", &query), TitleMatch::Prefix);
+        assert_eq!(title_match("History of rust", &query), TitleMatch::Word);
+        assert_eq!(title_match("Trusty tools", &query), TitleMatch::Partial);
+        assert_eq!(
+            title_match("Rust belt language", &tokenize("rust language")),
+            TitleMatch::Partial
+        );
+    }
+}
@@ -0,0 +1,170 @@
+//! Parsing of robots.txt files, deciding which paths on a host a crawler may fetch
+
+use std::time::Duration;
+
+/// A single `Allow` or `Disallow` line
+#[derive(Clone, Debug, PartialEq)]
+struct Rule {
+    allow: bool,
+    path: String,
+}
+
+/// The rules of a robots.txt file that apply to one user agent
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Robots {
+    rules: Vec<Rule>,
+    /// How long to wait between fetches from the host
+    pub crawl_delay: Option<Duration>,
+}
+
+/// A group of rules and the user agents they're for
+#[derive(Default)]
+struct Group {
+    agents: Vec<String>,
+    rules: Vec<Rule>,
+    crawl_delay: Option<Duration>,
+}
+
+impl Robots {
+    /// Rules allowing every path, used for hosts without a robots.txt
+    pub fn allow_all() -> Self {
+        Self::default()
+    }
+
+    /// Parses a robots.txt file, keeping the group naming the agent or, failing that, the `*`
+    /// group. Unknown lines and comments are ignored
+    pub fn parse(text: &str, agent: &str) -> Self {
+        let mut groups: Vec<Group> = vec![];
+        let mut reading_agents = false;
+
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let Some((field, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+
+            match field.trim().to_ascii_lowercase().as_str() {
+                "user-agent" => {
+                    // Consecutive agent lines share the group that follows them
+                    if !reading_agents {
+                        groups.push(Group::default());
+                    }
+                    reading_agents = true;
+                    if let Some(group) = groups.last_mut() {
+                        group.agents.push(value.to_ascii_lowercase());
+                    }
+                }
+                field @ ("allow" | "disallow") => {
+                    reading_agents = false;
+                    // An empty disallow allows everything, which is the same as no rule at all
+                    if let (Some(group), false) = (groups.last_mut(), value.is_empty()) {
+                        group.rules.push(Rule {
+                            allow: field == "allow",
+                            path: value.to_string(),
+                        });
+                    }
+                }
+                "crawl-delay" => {
+                    reading_agents = false;
+                    if let Some(group) = groups.last_mut() {
+                        // Delays too long for a duration, negative or not numbers are ignored
+                        group.crawl_delay = value
+                            .parse::<f32>()
+                            .ok()
+                            .and_then(|secs| Duration::try_from_secs_f32(secs).ok());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let agent = agent.to_ascii_lowercase();
+        let named = groups.iter().position(|group| {
+            group
+                .agents
+                .iter()
+                .any(|name| name != "*" && agent.contains(name.as_str()))
+        });
+        let any = groups
+            .iter()
+            .position(|group| group.agents.iter().any(|name| name == "*"));
+
+        named
+            .or(any)
+            .map(|idx| {
+                let group = groups.swap_remove(idx);
+                Self {
+                    rules: group.rules,
+                    crawl_delay: group.crawl_delay,
+                }
+            })
+            .unwrap_or_default()
+    }
+
+    /// Whether a path may be fetched. The longest matching rule decides, with `Allow` winning ties
+    pub fn is_allowed(&self, path: &str) -> bool {
+        self.rules
+            .iter()
+            .filter(|rule| path.starts_with(&rule.path))
+            .max_by_key(|rule| (rule.path.len(), rule.allow))
+            .is_none_or(|rule| rule.allow)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Robots;
+
+    #[test]
+    fn disallow_root_blocks_everything() {
+        let robots = Robots::parse("User-agent: *\nDisallow: /", "crawler");
+
+        assert!(!robots.is_allowed("/"));
+        assert!(!robots.is_allowed("/wiki/Rust"));
+    }
+
+    #[test]
+    fn longest_prefix_decides() {
+        let robots = Robots::parse(
+            "# Keep out of drafts\nUser-agent: *\nDisallow: /wiki/\nAllow: /wiki/Public\nDisallow:\n",
+            "crawler",
+        );
+
+        assert!(robots.is_allowed("/"));
+        assert!(robots.is_allowed("/about"));
+        assert!(!robots.is_allowed("/wiki/Draft"));
+        assert!(robots.is_allowed("/wiki/Public_page"));
+    }
+
+    #[test]
+    fn named_agent_group_preferred() {
+        let text = "User-agent: *\nDisallow: /\n\nUser-agent: other\nUser-agent: Page_Rank_From_Scratch\nDisallow: /private\nCrawl-delay: 1.5\n";
+        let robots = Robots::parse(text, "page_rank_from_scratch/0.1");
+
+        assert!(robots.is_allowed("/public"));
+        assert!(!robots.is_allowed("/private/page"));
+        assert_eq!(robots.crawl_delay, Some(Duration::from_millis(1500)));
+
+        let robots = Robots::parse(text, "someone_else");
+        assert!(!robots.is_allowed("/public"));
+        assert_eq!(robots.crawl_delay, None);
+    }
+
+    #[test]
+    fn empty_or_unrelated_file_allows_everything() {
+        assert!(Robots::parse("", "crawler").is_allowed("/anything"));
+        assert!(Robots::parse("<html>Not found</html>", "crawler").is_allowed("/anything"));
+        assert!(Robots::parse("User-agent: other\nDisallow: /", "crawler").is_allowed("/"));
+    }
+
+    #[test]
+    fn unusable_crawl_delays_ignored() {
+        for delay in ["1e30", "-2", "inf", "NaN", "soon"] {
+            let text = format!("User-agent: *\nCrawl-delay: {delay}\n");
+            assert_eq!(Robots::parse(&text, "crawler").crawl_delay, None, "{delay}");
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! HTTP search server, answering `GET /search?q=...&limit=...` with a ranker's results as JSON
+
+use std::{io, net::SocketAddr, sync::Arc};
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+
+use crate::page_rank::{PageRanker, SearchError, SearchOptions, SearchOutcome, SearchResult};
+
+/// How many results a search gives when no limit is asked for
+pub const DEFAULT_LIMIT: usize = 10;
+
+/// Query string of a search request
+#[derive(Debug, Deserialize)]
+struct SearchParams {
+    q: String,
+    limit: Option<usize>,
+}
+
+/// Body of a successful search
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SearchResponse {
+    pub query: String,
+    /// Whether the query was blank, so the results are the top sites overall
+    pub global_top: bool,
+    pub results: Vec<SearchHit>,
+}
+
+/// A single search result
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub url: String,
+    pub title: String,
+    /// Blended score results are ordered by
+    pub score: f32,
+    /// PageRank within the query subgraph, relative to the best ranked match
+    pub rank: f32,
+    /// Title relevance to the query, relative to the most relevant match
+    pub relevance: f32,
+    pub backlinks: usize,
+    pub description: Option<String>,
+}
+
+impl From<&SearchResult<'_>> for SearchHit {
+    fn from(result: &SearchResult<'_>) -> Self {
+        Self {
+            url: result.site.url.clone(),
+            title: result.site.title.clone(),
+            score: result.score,
+            rank: result.rank,
+            relevance: result.relevance,
+            backlinks: result.backlinks,
+            description: result.description.map(str::to_string),
+        }
+    }
+}
+
+/// Body of a failed search
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+/// Routes for searching a ranker
+pub fn router(ranker: Arc<PageRanker>) -> Router {
+    Router::new()
+        .route("/search", get(search))
+        .with_state(ranker)
+}
+
+/// Serves searches of a ranker at an address until the server fails
+pub async fn serve(ranker: Arc<PageRanker>, addr: SocketAddr) -> io::Result<()> {
+    serve_on(ranker, TcpListener::bind(addr).await?).await
+}
+
+/// Serves searches of a ranker on an already bound listener, such as one on an ephemeral port
+pub async fn serve_on(ranker: Arc<PageRanker>, listener: TcpListener) -> io::Result<()> {
+    axum::serve(listener, router(ranker)).await
+}
+
+async fn search(
+    State(ranker): State<Arc<PageRanker>>,
+    Query(params): Query<SearchParams>,
+) -> Response {
+    // Ranking is CPU bound, so it's kept off the threads serving requests
+    let searched = tokio::task::spawn_blocking(move || {
+        let options = SearchOptions {
+            max_results: Some(params.limit.unwrap_or(DEFAULT_LIMIT)),
+            ..Default::default()
+        };
+        let outcome = ranker.search_outcome(&params.q, &options);
+        let global_top = matches!(outcome, SearchOutcome::GlobalTop { .. });
+
+        match outcome {
+            SearchOutcome::Failed(err) => Err(err),
+            outcome => Ok(SearchResponse {
+                query: params.q,
+                global_top,
+                results: outcome.results().iter().map(SearchHit::from).collect(),
+            }),
+        }
+    })
+    .await;
+
+    let (status, error) = match searched {
+        Ok(Ok(response)) => return Json(response).into_response(),
+        Ok(Err(err @ SearchError::InvalidQuery { .. })) => {
+            (StatusCode::BAD_REQUEST, err.to_string())
+        }
+        Ok(Err(err)) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+    };
+
+    (status, Json(ErrorResponse { error })).into_response()
+}
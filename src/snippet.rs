@@ -0,0 +1,200 @@
+//! Short excerpts of a page's text around where it matches a query, with the matched parts marked
+//! for highlighting
+
+use std::ops::Range;
+
+use crate::index::{merge_ranges, FoldedText};
+
+/// Most characters of page text shown in a snippet, not counting ellipses
+pub const SNIPPET_LEN: usize = 160;
+
+/// Most characters of a site's description shown alongside a result, not counting the ellipsis
+pub const DESCRIPTION_LEN: usize = 120;
+
+/// Characters of context shown before the first match when the text is cut
+const CONTEXT_BEFORE: usize = 40;
+
+/// Marks text cut off from either end of a snippet
+const ELLIPSIS: &str = "…";
+
+/// An excerpt of page text around a query match
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snippet {
+    pub text: String,
+    /// Byte ranges of `text` that matched the query
+    pub highlight: Vec<Range<usize>>,
+}
+
+/// Cuts a snippet from text around the first occurrence of any query token, ellipsized at word
+/// boundaries where the text is cut. Returns `None` if no token occurs in the text
+pub fn snippet(text: &str, tokens: &[String]) -> Option<Snippet> {
+    let folded = FoldedText::new(text);
+    let matches = merge_ranges(
+        tokens
+            .iter()
+            .flat_map(|token| folded.find_all(token))
+            .collect(),
+    );
+    let first = matches.first()?.clone();
+
+    let mut start = text[..first.start]
+        .char_indices()
+        .rev()
+        .nth(CONTEXT_BEFORE - 1)
+        .map_or(0, |(idx, _)| idx);
+    if start > 0 {
+        // Begin on a whole word where one starts before the match
+        if let Some(space) = text[start..first.start].find(char::is_whitespace) {
+            start += space;
+        }
+    }
+
+    let mut end = text[start..]
+        .char_indices()
+        .nth(SNIPPET_LEN)
+        .map_or(text.len(), |(idx, _)| start + idx);
+    if end < text.len() {
+        // End on a whole word unless that would cut into the match
+        if let Some(space) = text[start..end].rfind(char::is_whitespace) {
+            if start + space >= first.end {
+                end = start + space;
+            }
+        }
+    }
+
+    let excerpt = &text[start..end];
+    let trimmed_start = start + (excerpt.len() - excerpt.trim_start().len());
+    let trimmed_end = end - (excerpt.len() - excerpt.trim_end().len());
+
+    let mut snippet = String::new();
+    if trimmed_start > 0 && !text[..trimmed_start].trim().is_empty() {
+        snippet.push_str(ELLIPSIS);
+    }
+    let offset = snippet.len();
+    snippet.push_str(&text[trimmed_start..trimmed_end]);
+    if !text[trimmed_end..].trim().is_empty() {
+        snippet.push_str(ELLIPSIS);
+    }
+
+    let highlight = matches
+        .into_iter()
+        .filter(|range| range.start < trimmed_end && range.end > trimmed_start)
+        .map(|range| {
+            let start = range.start.max(trimmed_start) - trimmed_start + offset;
+            let end = range.end.min(trimmed_end) - trimmed_start + offset;
+            start..end
+        })
+        .collect();
+
+    Some(Snippet {
+        text: snippet,
+        highlight,
+    })
+}
+
+/// Shortens text to at most `max_chars` characters, cutting at the last word boundary that fits
+/// and marking the cut with an ellipsis. Text that already fits is only trimmed
+pub fn truncate_words(text: &str, max_chars: usize) -> String {
+    let text = text.trim();
+    let Some((cut, _)) = text.char_indices().nth(max_chars) else {
+        return text.to_string();
+    };
+
+    // Keep a single overlong word cut mid-way rather than dropping everything
+    let end = match text[..cut].rfind(char::is_whitespace) {
+        Some(space) if !text[..space].trim().is_empty() => space,
+        _ => cut,
+    };
+
+    format!("{}{ELLIPSIS}", text[..end].trim_end())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{snippet, truncate_words, Snippet, SNIPPET_LEN};
+
+    fn tokens(tokens: &[&str]) -> Vec<String> {
+        tokens.iter().map(|token| token.to_string()).collect()
+    }
+
+    fn highlighted(snippet: &Snippet) -> Vec<&str> {
+        snippet
+            .highlight
+            .iter()
+            .map(|range| &snippet.text[range.clone()])
+            .collect()
+    }
+
+    fn filler(words: usize) -> String {
+        vec!["lorem"; words].join(" ")
+    }
+
+    #[test]
+    fn match_at_start() {
+        let text = format!("Rust is a language. {}", filler(60));
+        let snippet = snippet(&text, &tokens(&["rust"])).expect("Snippet");
+
+        assert!(snippet.text.starts_with("Rust is"));
+        assert!(snippet.text.ends_with("lorem…"));
+        assert!(snippet.text.chars().count() <= SNIPPET_LEN + 1);
+        assert_eq!(highlighted(&snippet), ["Rust"]);
+    }
+
+    #[test]
+    fn match_in_middle() {
+        let text = format!("{} Rust programming {}", filler(40), filler(40));
+        let snippet = snippet(&text, &tokens(&["programming", "rust"])).expect("Snippet");
+
+        assert!(snippet.text.starts_with("…lorem"));
+        assert!(snippet.text.ends_with("lorem…"));
+        assert!(snippet.text.chars().count() <= SNIPPET_LEN + 2);
+        assert_eq!(highlighted(&snippet), ["Rust", "programming"]);
+    }
+
+    #[test]
+    fn match_at_end() {
+        let text = format!("{} all about Rust", filler(60));
+        let snippet = snippet(&text, &tokens(&["rust"])).expect("Snippet");
+
+        assert!(snippet.text.starts_with('…'));
+        assert!(snippet.text.ends_with("about Rust"));
+        assert_eq!(highlighted(&snippet), ["Rust"]);
+    }
+
+    #[test]
+    fn multi_byte_characters_beside_match() {
+        let text = "東京のRust会議について。Ünïcödé rust!";
+        let snippet = snippet(text, &tokens(&["rust", "unicode"])).expect("Snippet");
+
+        assert_eq!(snippet.text, text);
+        assert_eq!(highlighted(&snippet), ["Rust", "Ünïcödé", "rust"]);
+    }
+
+    #[test]
+    fn no_match_is_none() {
+        assert_eq!(snippet("Python", &tokens(&["rust"])), None);
+    }
+
+    #[test]
+    fn short_text_kept_whole() {
+        assert_eq!(
+            truncate_words("  The Rust language ", 40),
+            "The Rust language"
+        );
+    }
+
+    #[test]
+    fn long_text_cut_at_word() {
+        assert_eq!(
+            truncate_words("The Rust programming language", 12),
+            "The Rust…"
+        );
+        assert_eq!(truncate_words("Supercalifragilistic", 5), "Super…");
+    }
+
+    #[test]
+    fn multi_byte_text_cut_on_characters() {
+        assert_eq!(truncate_words("東京 は 日本の首都です", 6), "東京 は…");
+        assert_eq!(truncate_words("Ünïcödé Ünïcödé", 10), "Ünïcödé…");
+    }
+}
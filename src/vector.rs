@@ -1,11 +1,13 @@
 //! Vector implementation
 use std::{
-    fmt::Debug,
+    fmt::{self, Debug},
     marker::PhantomData,
     ops::{Index, IndexMut},
     slice::SliceIndex,
 };
 
+use rand::Rng;
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Probability;
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -64,6 +66,33 @@ impl<const N: usize, TYPE: Debug, Idx: SliceIndex<[f32], Output = f32>> IndexMut
     }
 }
 
+/// A collection held a different number of values than the vector or matrix it was converted to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LengthMismatch {
+    pub expected: usize,
+    pub found: usize,
+}
+
+impl fmt::Display for LengthMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "expected {} values, found {}", self.expected, self.found)
+    }
+}
+
+impl std::error::Error for LengthMismatch {}
+
+impl<const N: usize> TryFrom<Vec<f32>> for Vector<N, General> {
+    type Error = LengthMismatch;
+    fn try_from(data: Vec<f32>) -> Result<Self, Self::Error> {
+        let found = data.len();
+        let data = data
+            .try_into()
+            .map_err(|_| LengthMismatch { expected: N, found })?;
+
+        Ok(Self::from_data(data))
+    }
+}
+
 impl<const N: usize, TYPE: Debug> Default for Vector<N, TYPE> {
     fn default() -> Self {
         Self {
@@ -79,17 +108,22 @@ impl<const N: usize> Vector<N, General> {
     }
 
     pub fn from_data(data: [f32; N]) -> Self {
-        let mut vec = Self::zero_vector();
-
-        for col in 0..N {
-            vec.data[col] = data[col];
+        Self {
+            data,
+            phantom_type: PhantomData,
         }
-
-        vec
     }
 }
 
 impl<const N: usize, TYPE: Debug> Vector<N, TYPE> {
+    /// Wraps raw data as this vector type without checking that it upholds the type's invariants
+    pub(crate) fn from_raw(data: [f32; N]) -> Self {
+        Self {
+            data,
+            phantom_type: PhantomData,
+        }
+    }
+
     pub fn magnitude(&self) -> f32 {
         self.data.iter().map(|val| val.powi(2)).sum::<f32>().sqrt()
     }
@@ -98,6 +132,15 @@ impl<const N: usize, TYPE: Debug> Vector<N, TYPE> {
         self.data.iter().sum()
     }
 
+    /// Running totals of the entries, so entry `i` is the sum of entries `0..=i`
+    pub fn cumsum(&self) -> Vector<N, General> {
+        let mut total = 0f32;
+        Vector::from_raw(self.data.map(|val| {
+            total += val;
+            total
+        }))
+    }
+
     pub fn unit_vector(&self) -> Vector<N, Unit> {
         let mut unit_vec = Vector::default();
         let magnitude = self.magnitude();
@@ -111,14 +154,72 @@ impl<const N: usize, TYPE: Debug> Vector<N, TYPE> {
 
     pub fn probability_vector(&self) -> Option<Vector<N, Probability>> {
         if (self.data.iter().sum::<f32>() - 1.0).abs() <= 0.01 {
-            let mut new_vec = Vector::default();
-            new_vec.data = self.data;
-            Some(new_vec)
+            Some(Vector {
+                data: self.data,
+                phantom_type: PhantomData,
+            })
         } else {
             None
         }
     }
 
+    /// The closest probability vector to this one by Euclidean distance. Unlike normalizing, this
+    /// handles negative entries, which are pulled up to zero
+    pub fn project_to_simplex(&self) -> Vector<N, Probability> {
+        let mut sorted = self.data;
+        sorted.sort_by(|a, b| b.total_cmp(a));
+
+        // Find how many of the largest entries stay positive once shifted down by the threshold
+        let mut cumulative = 0f32;
+        let mut threshold = 0f32;
+        for (idx, val) in sorted.iter().enumerate() {
+            cumulative += val;
+            let candidate = (cumulative - 1f32) / (idx + 1) as f32;
+            if val - candidate > 0f32 {
+                threshold = candidate;
+            }
+        }
+
+        Vector::from_raw(self.data.map(|val| (val - threshold).max(0f32)))
+    }
+
+    /// Index of the largest entry, the first of them if several tie. An empty vector gives 0
+    pub fn argmax(&self) -> usize {
+        self.arg_by(|val, best| val > best)
+    }
+
+    /// Index of the smallest entry, the first of them if several tie. An empty vector gives 0
+    pub fn argmin(&self) -> usize {
+        self.arg_by(|val, best| val < best)
+    }
+
+    /// Index of the first entry no other entry beats
+    fn arg_by(&self, beats: impl Fn(f32, f32) -> bool) -> usize {
+        let mut best = 0;
+        for (idx, val) in self.data.iter().enumerate().skip(1) {
+            if beats(*val, self.data[best]) {
+                best = idx;
+            }
+        }
+
+        best
+    }
+
+    pub fn dot<OTHER: Debug>(&self, other: &Vector<N, OTHER>) -> f32 {
+        self.data.iter().zip(other.data).map(|(a, b)| a * b).sum()
+    }
+
+    /// Cosine of the angle between two vectors, from -1 for opposite directions to 1 for the same
+    /// direction. A zero vector isn't similar to anything, giving 0
+    pub fn cosine_similarity<OTHER: Debug>(&self, other: &Vector<N, OTHER>) -> f32 {
+        let magnitudes = self.magnitude() * other.magnitude();
+        if magnitudes == 0f32 {
+            return 0f32;
+        }
+
+        self.dot(other) / magnitudes
+    }
+
     pub fn contains_zero(&self) -> bool {
         self.data.contains(&0f32)
     }
@@ -146,24 +247,97 @@ impl<const N: usize, TYPE: Debug> Vector<N, TYPE> {
     pub fn first_non_zero_term(&self) -> Option<usize> {
         self.data.iter().position(|elem| elem.abs() > 1e-6)
     }
+
+    /// Copies this vector into one of a different size, truncating extra entries or padding with
+    /// zeroes
+    pub fn resize<const M: usize>(&self) -> Vector<M, General> {
+        let mut result = Vector::zero_vector();
+        let shared = N.min(M);
+
+        result.data[..shared].copy_from_slice(&self.data[..shared]);
+
+        result
+    }
 }
 
 impl<const N: usize> Vector<N, Probability> {
     pub fn regular(&self) -> Option<Vector<N, ProbabilityRegular>> {
         if self.data.iter().filter(|element| **element > 0.0).count() == self.data.len() {
-            let mut new_vec = Vector::default();
-            new_vec.data = self.data;
-
-            Some(new_vec)
+            Some(Vector {
+                data: self.data,
+                phantom_type: PhantomData,
+            })
         } else {
             None
         }
     }
+
+    /// Draws an index with chance proportional to its entry, by inverse CDF. Entries of zero are
+    /// never drawn, and an empty vector gives 0
+    pub fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> usize {
+        let cdf = self.cumsum();
+        let total = cdf.data.last().copied().unwrap_or_default();
+        if total <= 0f32 {
+            return 0;
+        }
+
+        // Probability vectors only sum to roughly 1, so draw against the true total
+        let draw = rng.gen_range(0f32..total);
+        cdf.data
+            .partition_point(|cumulative| *cumulative <= draw)
+            .min(N - 1)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Vector;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::{LengthMismatch, Vector};
+
+    #[test]
+    fn vec_converts_to_vector() {
+        let vector = Vector::<3>::try_from(vec![1.0, 2.0, 3.0]).expect("Convert vector");
+        assert_eq!(vector, Vector::from_data([1.0, 2.0, 3.0]));
+
+        assert_eq!(
+            Vector::<3>::try_from(vec![1.0, 2.0]),
+            Err(LengthMismatch {
+                expected: 3,
+                found: 2
+            })
+        );
+    }
+
+    #[test]
+    fn arg_extremes_found() {
+        let vector = Vector::from_data([0.2, 0.5, -1.0, 0.3]);
+        assert_eq!(vector.argmax(), 1);
+        assert_eq!(vector.argmin(), 2);
+    }
+
+    #[test]
+    fn arg_extremes_tie_to_first() {
+        let vector = Vector::from_data([0.1, 0.4, 0.1, 0.4]);
+        assert_eq!(vector.argmax(), 1);
+        assert_eq!(vector.argmin(), 0);
+        assert_eq!(Vector::<0>::zero_vector().argmax(), 0);
+    }
+
+    #[test]
+    fn cosine_similarity_measures_direction() {
+        let vector = Vector::from_data([1.0, 0.0]);
+        assert_eq!(vector.dot(&Vector::from_data([3.0, 4.0])), 3.0);
+        assert!((vector.cosine_similarity(&Vector::from_data([2.0, 0.0])) - 1.0).abs() < 1e-6);
+        assert!(
+            vector
+                .cosine_similarity(&Vector::from_data([0.0, 5.0]))
+                .abs()
+                < 1e-6
+        );
+        assert!((vector.cosine_similarity(&Vector::from_data([-1.0, 0.0])) + 1.0).abs() < 1e-6);
+        assert_eq!(vector.cosine_similarity(&Vector::<2>::zero_vector()), 0.0);
+    }
 
     #[test]
     fn regular_probability_vector() {
@@ -179,6 +353,52 @@ mod tests {
         assert!(p_vector.regular().is_none())
     }
 
+    #[test]
+    fn cumulative_sums() {
+        let vector = Vector::from_data([0.5, -1.0, 2.0]);
+        assert_eq!(vector.cumsum(), Vector::from_data([0.5, -0.5, 1.5]));
+        assert_eq!(Vector::<0>::zero_vector().cumsum(), Vector::zero_vector());
+    }
+
+    #[test]
+    fn sampling_reproduces_distribution() {
+        let distribution = [0.1, 0.0, 0.6, 0.3];
+        let vector = Vector::from_data(distribution)
+            .probability_vector()
+            .expect("Probability vector");
+        let mut rng = StdRng::seed_from_u64(7);
+
+        const DRAWS: usize = 100_000;
+        let mut counts = [0usize; 4];
+        for _ in 0..DRAWS {
+            counts[vector.sample(&mut rng)] += 1;
+        }
+
+        assert_eq!(counts[1], 0);
+        for (count, expected) in counts.iter().zip(distribution) {
+            let observed = *count as f32 / DRAWS as f32;
+            // Several standard deviations for this many draws
+            assert!(
+                (observed - expected).abs() < 0.01,
+                "{observed} drawn against {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn projected_onto_simplex() {
+        let projected = Vector::from_data([0.5, 1.0, -0.5]).project_to_simplex();
+        assert_eq!(projected.data, [0.25, 0.75, 0.0]);
+
+        let already = Vector::from_data([0.2, 0.3, 0.5]).project_to_simplex();
+        for (projected, expected) in already.data.iter().zip([0.2, 0.3, 0.5]) {
+            assert!((projected - expected).abs() < 1e-6);
+        }
+
+        let negative = Vector::from_data([-3.0, -1.0]).project_to_simplex();
+        assert_eq!(negative.data, [0.0, 1.0]);
+    }
+
     #[test]
     fn scalar_multiplication() {
         let vector = Vector::from_data([1f32, 2f32, 3f32]);
@@ -195,6 +415,22 @@ mod tests {
         assert_eq!(vector, Vector::from_data([2f32, 4f32, 6f32]))
     }
 
+    #[test]
+    fn vector_padded() {
+        let vector = Vector::from_data([1f32, 2f32]);
+        let padded: Vector<4> = vector.resize();
+
+        assert_eq!(padded, Vector::from_data([1f32, 2f32, 0f32, 0f32]))
+    }
+
+    #[test]
+    fn vector_truncated() {
+        let vector = Vector::from_data([1f32, 2f32, 3f32, 4f32]);
+        let truncated: Vector<2> = vector.resize();
+
+        assert_eq!(truncated, Vector::from_data([1f32, 2f32]))
+    }
+
     #[test]
     fn vector_subtraction() {
         let vector = Vector::from_data([1f32, 2f32, 3f32]);
@@ -0,0 +1,130 @@
+//! Drives the example crawler against a local site, stopping it partway and restarting it from
+//! its checkpoint
+
+use std::{
+    env,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use page_rank_from_scratch::crawler::WebCrawler;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+    process::Command,
+};
+
+/// Serves a chain of pages, `/0` through `/4`, each linking to the next, logging every path
+/// requested
+async fn serve_chain() -> (String, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("Bind listener");
+    let addr = listener.local_addr().expect("Local address");
+    let requests = Arc::new(Mutex::new(vec![]));
+    let log = Arc::clone(&requests);
+
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let mut request = vec![];
+            let mut buf = [0u8; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                match stream.read(&mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(read) => request.extend_from_slice(&buf[..read]),
+                }
+            }
+
+            let request = String::from_utf8_lossy(&request);
+            let path = request.split(' ').nth(1).unwrap_or_default().to_string();
+            log.lock().expect("Lock request log").push(path.clone());
+
+            let page = path
+                .trim_start_matches('/')
+                .parse::<usize>()
+                .ok()
+                .filter(|page| *page < 5);
+            let response = match page {
+                Some(page) => {
+                    let body = format!(
+                        "<title>Page {page}</title><a href=\"/{}\">Next</a>",
+                        page + 1
+                    );
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                        body.len()
+                    )
+                }
+                None => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_string(),
+            };
+            let _ = stream.write_all(response.as_bytes()).await;
+        }
+    });
+
+    (format!("http://{addr}"), requests)
+}
+
+/// The example binary, which `cargo test` builds alongside the tests
+fn crawler_example() -> PathBuf {
+    let mut path = env::current_exe().expect("Test binary path");
+    path.pop();
+    if path.ends_with("deps") {
+        path.pop();
+    }
+    path.join("examples")
+        .join(format!("crawler{}", env::consts::EXE_SUFFIX))
+}
+
+#[tokio::test]
+async fn crawl_resumes_from_checkpoint_after_restart() {
+    let (root, requests) = serve_chain().await;
+    let dir = tempfile::tempdir().expect("Create checkpoint dir");
+    let checkpoint = dir.path().join("checkpoint.json");
+    let example = crawler_example();
+    assert!(
+        example.exists(),
+        "{} missing, run through `cargo test` so examples are built",
+        example.display()
+    );
+
+    let run = |sites: &str| {
+        Command::new(&example)
+            .arg(format!("{root}/0"))
+            .arg(&checkpoint)
+            .arg(sites)
+            .output()
+    };
+
+    let first = run("2").await.expect("Run crawler");
+    assert!(first.status.success());
+    let saved = WebCrawler::load(&checkpoint).expect("Load first checkpoint");
+    let crawled = saved
+        .values()
+        .filter(|site| site.last_crawled.is_some())
+        .count();
+    assert_eq!(crawled, 2);
+
+    let second = run("100").await.expect("Rerun crawler");
+    assert!(second.status.success());
+    assert!(String::from_utf8_lossy(&second.stdout).contains("Resuming"));
+
+    let saved = WebCrawler::load(&checkpoint).expect("Load final checkpoint");
+    let mut titles: Vec<_> = saved
+        .values()
+        .filter(|site| site.last_crawled.is_some())
+        .map(|site| site.title.as_str())
+        .collect();
+    titles.sort();
+    assert_eq!(
+        titles,
+        ["", "Page 0", "Page 1", "Page 2", "Page 3", "Page 4"]
+    );
+
+    // Pages crawled before the restart aren't fetched again
+    let requests = requests.lock().expect("Lock request log");
+    for page in 0..=5 {
+        let path = format!("/{page}");
+        assert_eq!(requests.iter().filter(|req| **req == path).count(), 1);
+    }
+}
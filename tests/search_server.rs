@@ -0,0 +1,84 @@
+//! Queries the search server over HTTP
+#![cfg(feature = "server")]
+
+use std::sync::Arc;
+
+use page_rank_from_scratch::{
+    crawler::SiteLog,
+    page_rank::PageRanker,
+    server::{self, ErrorResponse, SearchResponse},
+};
+use slotmap::SlotMap;
+use tokio::net::TcpListener;
+
+/// A ranker over a few sites about Rust, most of them linking to the book
+fn ranker() -> PageRanker {
+    let mut sites = SlotMap::with_key();
+    let book = sites.insert(SiteLog {
+        url: "https://doc.rust-lang.org/book".to_string(),
+        title: "The Rust Book".to_string(),
+        description: Some("Learn Rust".to_string()),
+        ..Default::default()
+    });
+    let blog = sites.insert(SiteLog {
+        url: "https://blog.rust-lang.org".to_string(),
+        title: "Rust Blog".to_string(),
+        ..Default::default()
+    });
+    let forum = sites.insert(SiteLog {
+        url: "https://users.rust-lang.org".to_string(),
+        title: "Rust Forum".to_string(),
+        ..Default::default()
+    });
+    sites.insert(SiteLog {
+        url: "https://python.org".to_string(),
+        title: "Python".to_string(),
+        ..Default::default()
+    });
+
+    sites[book].connections = vec![book];
+    sites[blog].connections = vec![blog, book];
+    sites[forum].connections = vec![forum, book, blog];
+
+    PageRanker::from_registry(sites)
+}
+
+#[tokio::test]
+async fn search_answers_with_ranked_json() {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("Bind listener");
+    let addr = listener.local_addr().expect("Local address");
+    tokio::spawn(server::serve_on(Arc::new(ranker()), listener));
+
+    let response = reqwest::get(format!("http://{addr}/search?q=rust&limit=2"))
+        .await
+        .expect("Send search");
+    assert_eq!(response.status(), 200);
+    let body: serde_json::Value =
+        serde_json::from_slice(&response.bytes().await.expect("Read body")).expect("Parse JSON");
+
+    assert_eq!(body["query"], "rust");
+    assert_eq!(body["global_top"], false);
+    let results = body["results"].as_array().expect("Results list");
+    assert_eq!(results.len(), 2);
+    for field in ["url", "title", "score", "rank", "relevance", "backlinks"] {
+        assert!(results[0].get(field).is_some(), "missing {field}");
+    }
+
+    let response: SearchResponse = serde_json::from_value(body).expect("Parse response");
+    assert_eq!(response.results[0].url, "https://doc.rust-lang.org/book");
+    assert_eq!(
+        response.results[0].description.as_deref(),
+        Some("Learn Rust")
+    );
+    assert!(response.results[0].score >= response.results[1].score);
+
+    let response = reqwest::get(format!("http://{addr}/search?q=%22rust"))
+        .await
+        .expect("Send search");
+    assert_eq!(response.status(), 400);
+    let error: ErrorResponse =
+        serde_json::from_slice(&response.bytes().await.expect("Read body")).expect("Parse error");
+    assert!(!error.error.is_empty());
+}